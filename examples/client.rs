@@ -7,7 +7,6 @@ extern crate env_logger;
 
 use std::io::{stdout, stderr};
 use std::io::Write;
-use std::net::ToSocketAddrs;
 use std::time::Duration;
 use std::process::exit;
 
@@ -15,7 +14,7 @@ use url::{Url, Host};
 use url::SchemeData::Relative;
 use argparse::{ArgumentParser, Store};
 use rotor::{Scope, Time};
-use rotor_http::client::{connect_tcp, Request, Head, Client, RecvMode};
+use rotor_http::client::{connect_host, Request, Head, Client, RecvMode};
 use rotor_http::client::{Connection, Requester, Task, Version, ResponseError};
 use rotor_http::client::{ProtocolError};
 
@@ -75,8 +74,7 @@ impl Requester for Req {
     {
         req.start("GET", &self.0.serialize_path().unwrap(), Version::Http11);
         req.add_header("Host", self.0.serialize_host().unwrap().as_bytes()).unwrap();
-        req.done_headers().unwrap();
-        req.done();
+        req.empty_body().unwrap();
         Some(self)
     }
     fn headers_received(self, head: Head, _request: &mut Request,
@@ -145,14 +143,13 @@ fn main() {
             .ok();
         exit(1);
     }
-    let addr = match url.scheme_data {
+    let (host, port) = match url.scheme_data {
         Relative(ref scheme) => {
-            // Can't implement as trait because of E0117
             let port = scheme.port_or_default().unwrap();
             match scheme.host {
-                Host::Domain(ref d) => (d.as_str(), port).to_socket_addrs().unwrap().next().unwrap(),
-                Host::Ipv4(ref a) => (*a, port).to_socket_addrs().unwrap().next().unwrap(),
-                Host::Ipv6(ref a) => (*a, port).to_socket_addrs().unwrap().next().unwrap(),
+                Host::Domain(ref d) => (d.clone(), port),
+                Host::Ipv4(ref a) => (a.to_string(), port),
+                Host::Ipv6(ref a) => (a.to_string(), port),
             }
         }
         _ => unreachable!(),
@@ -160,7 +157,7 @@ fn main() {
     let creator = rotor::Loop::new(&rotor::Config::new()).unwrap();
     let mut loop_inst = creator.instantiate(Context);
     loop_inst.add_machine_with(|scope| {
-        connect_tcp::<Cli>(scope, &addr, url)
+        connect_host::<Cli>(scope, &host, port, url)
     }).unwrap();
     loop_inst.run().unwrap();
 }
@@ -0,0 +1,78 @@
+extern crate rotor;
+extern crate rotor_http;
+
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor::mio::tcp::{TcpListener, TcpStream};
+use rotor_http::server::{RecvMode, Server, Head, Response, Fsm};
+
+
+struct Context;
+
+struct Hello;
+
+impl Server for Hello {
+    type Seed = ();
+    type Context = Context;
+    fn headers_received(_seed: (), _head: Head, _res: &mut Response,
+        scope: &mut Scope<Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        Some((Hello, RecvMode::Buffered(1024),
+            scope.now() + Duration::new(10, 0)))
+    }
+    fn request_received(self, _data: &[u8], res: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        let data = b"Hello World!";
+        res.status(200, "OK");
+        res.add_length(data.len() as u64).unwrap();
+        res.done_headers().unwrap();
+        res.write_body(data);
+        res.done();
+        None
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn timeout(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        unimplemented!();
+    }
+    fn wakeup(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unimplemented!();
+    }
+    fn configure_socket(_seed: &(), sock: &TcpStream,
+        _scope: &mut Scope<Context>)
+    {
+        // Small responses should not wait for Nagle's algorithm.
+        // Note: it's not called for TLS sockets, see the docs
+        if let Err(e) = sock.set_nodelay(true) {
+            println!("Can't set TCP_NODELAY: {}", e);
+        }
+    }
+}
+
+fn main() {
+    println!("Starting http server on http://127.0.0.1:3000/");
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(Context);
+    let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<Hello, _>::new(lst, (), scope)
+    }).unwrap();
+    loop_inst.run().unwrap();
+}
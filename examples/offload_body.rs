@@ -0,0 +1,114 @@
+extern crate rotor;
+extern crate rotor_http;
+
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor_http::server::{RecvMode, Server, Head, Response, Fsm};
+use rotor_http::server::channel::{BodyChannel, BodyWorker, BodyReceiver};
+use rotor::mio::tcp::TcpListener;
+
+
+/// Counts the bytes and lines of the request body in a separate thread
+///
+/// Try it with a large file, e.g.:
+///
+///     curl -T /usr/share/dict/words http://127.0.0.1:3000/
+struct Count {
+    result: Arc<Mutex<Option<(usize, usize)>>>,
+}
+
+fn count(mut body: BodyReceiver) -> Option<(usize, usize)> {
+    let mut bytes = 0;
+    let mut lines = 0;
+    loop {
+        match body.recv() {
+            Ok(Some(chunk)) => {
+                // pretend it's an expensive computation
+                thread::sleep(Duration::from_millis(1));
+                bytes += chunk.len();
+                lines += chunk.iter().filter(|&&x| x == b'\n').count();
+            }
+            Ok(None) => return Some((bytes, lines)),
+            // request is aborted
+            Err(_) => return None,
+        }
+    }
+}
+
+impl Server for Count {
+    type Seed = ();
+    type Context = ();
+    fn headers_received(_seed: (), _head: Head, _res: &mut Response,
+        scope: &mut Scope<()>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        Some((Count { result: Arc::new(Mutex::new(None)) },
+              RecvMode::Progressive(16384),
+              scope.now() + Duration::new(120, 0)))
+    }
+    fn request_received(self, _data: &[u8], _res: &mut Response,
+        _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        // wait for the worker
+        Some(self)
+    }
+    fn timeout(self, _response: &mut Response, _scope: &mut Scope<()>)
+        -> Option<(Self, Time)>
+    {
+        None
+    }
+    fn wakeup(self, res: &mut Response, _scope: &mut Scope<()>)
+        -> Option<Self>
+    {
+        let result = self.result.lock().unwrap().take();
+        match result {
+            Some((bytes, lines)) => {
+                res.send_body(200, "OK", b"text/plain",
+                    format!("{} bytes, {} lines\n", bytes, lines)
+                    .as_bytes());
+                None
+            }
+            // just a progress of the body channel
+            None => Some(self),
+        }
+    }
+}
+
+impl BodyWorker for Count {
+    fn start_worker(&mut self, body: BodyReceiver, scope: &mut Scope<()>) {
+        let result = self.result.clone();
+        let notifier = scope.notifier();
+        thread::spawn(move || {
+            if let Some(res) = count(body) {
+                *result.lock().unwrap() = Some(res);
+                notifier.wakeup().ok();
+            }
+        });
+    }
+}
+
+fn main() {
+    println!("Starting http server on http://127.0.0.1:3000/");
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(());
+    let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<BodyChannel<Count>, _>::new(lst, (), scope)
+    }).unwrap();
+    loop_inst.run().unwrap();
+}
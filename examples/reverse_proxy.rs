@@ -0,0 +1,433 @@
+//! A reverse proxy forwarding all requests to a single upstream
+//!
+//! Both request and response bodies are streamed (`RecvMode::Progressive`).
+//! Each request is handed over to one of the few persistent client
+//! connections through the queue in the `Context`, both sides notify each
+//! other when new data is available.
+//!
+//! Try it with `cargo run --example hello_world_server` running on
+//! port 3000 and then:
+//!
+//! ```text
+//! cargo run --example reverse_proxy -- 127.0.0.1:3000
+//! curl http://127.0.0.1:8080/
+//! ```
+extern crate rotor;
+extern crate rotor_http;
+extern crate httparse;
+extern crate argparse;
+extern crate env_logger;
+
+use std::ascii::AsciiExt;
+use std::collections::VecDeque;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use argparse::{ArgumentParser, Store};
+use rotor::{Scope, Time, Notifier, Compose2};
+use rotor::mio::tcp::TcpListener;
+use rotor_http::client::{self, connect_tcp, Client, Requester, Task};
+use rotor_http::client::{Connection, ProtocolError, ResponseError};
+use rotor_http::server::{self, Server, Response, RecvMode, BodyKind, Fsm};
+use rotor_http::server::reason_phrase;
+
+/// Number of connections to the upstream
+const CONNECTIONS: usize = 4;
+/// Stop reading request body when this much is not sent upstream yet
+const MAX_PENDING: usize = 65536;
+
+
+struct Context {
+    /// Requests waiting for a free upstream connection
+    queue: VecDeque<Arc<Mutex<Exchange>>>,
+    /// Upstream connections waiting for a request
+    idle: Vec<Notifier>,
+}
+
+/// Everything known about the single proxied request
+struct Exchange {
+    method: String,
+    path: String,
+    headers: Vec<(String, Vec<u8>)>,
+    request_length: Option<u64>,
+    request_body: Vec<u8>,
+    request_done: bool,
+    status: Option<u16>,
+    response_headers: Vec<(String, Vec<u8>)>,
+    response_length: Option<u64>,
+    response_body: Vec<u8>,
+    response_done: bool,
+    failed: bool,
+    server: Notifier,
+    client: Option<Notifier>,
+}
+
+fn is_hop_by_hop(name: &str) -> bool {
+    ["Connection", "Keep-Alive", "Proxy-Authenticate", "Proxy-Authorization",
+     "TE", "Trailer", "Transfer-Encoding", "Upgrade", "Content-Length"]
+    .iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+/// Copies headers except hop-by-hop ones and ones listed in `Connection`
+fn copy_headers(headers: &[httparse::Header]) -> Vec<(String, Vec<u8>)> {
+    let listed = headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|h| h.value.split(|&x| x == b','))
+        .map(|x| String::from_utf8_lossy(x).trim().to_string())
+        .collect::<Vec<_>>();
+    headers.iter()
+        .filter(|h| !is_hop_by_hop(h.name))
+        .filter(|h| !listed.iter().any(|x| x.eq_ignore_ascii_case(h.name)))
+        .map(|h| (h.name.to_string(), h.value.to_vec()))
+        .collect()
+}
+
+fn content_length(headers: &[httparse::Header]) -> Option<u64> {
+    headers.iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|h| String::from_utf8_lossy(h.value).trim().parse().ok())
+}
+
+fn error_page(res: &mut Response, code: u16) {
+    let reason = reason_phrase(code).unwrap_or("");
+    res.status(code, reason);
+    res.add_length(reason.len() as u64).unwrap();
+    res.done_headers().unwrap();
+    res.write_body(reason.as_bytes());
+    res.done();
+}
+
+fn wake(notifier: &Option<Notifier>) {
+    if let Some(ref n) = *notifier {
+        n.wakeup().ok();
+    }
+}
+
+/// The server side of the proxy
+struct Proxy(Arc<Mutex<Exchange>>, bool);
+
+impl Proxy {
+    /// Sends everything we've got from upstream so far
+    fn forward(self, res: &mut Response) -> Option<Self> {
+        let mut ex = self.0.lock().unwrap();
+        if ex.failed {
+            if !res.is_started() {
+                error_page(res, 502);
+            }
+            // Otherwise response is truncated, connection will be closed
+            return None;
+        }
+        if !res.is_started() {
+            let code = match ex.status {
+                Some(code) => code,
+                None => return Some(Proxy(self.0.clone(), self.1)),
+            };
+            res.status(code, reason_phrase(code).unwrap_or(""));
+            // Headers that we can't send are skipped, e.g. there is no
+            // framing for 204 and 304 responses
+            for &(ref name, ref value) in &ex.response_headers {
+                res.add_header(name, value).ok();
+            }
+            match ex.response_length {
+                Some(n) => res.add_length(n).ok(),
+                None => res.add_chunked().ok(),
+            };
+            if !res.done_headers().unwrap() {
+                // Body is not allowed (e.g. HEAD request), ignore it
+                ex.response_body.clear();
+            }
+        }
+        let body = mem::replace(&mut ex.response_body, Vec::new());
+        res.write_body(&body);
+        if ex.response_done {
+            res.done();
+            return None;
+        }
+        if self.1 && ex.request_body.len() < MAX_PENDING {
+            res.resume_body_read();
+            return Some(Proxy(self.0.clone(), false));
+        }
+        Some(Proxy(self.0.clone(), self.1))
+    }
+    fn request_data(self, chunk: &[u8], res: &mut Response) -> Option<Self> {
+        let paused = {
+            let mut ex = self.0.lock().unwrap();
+            ex.request_body.extend(chunk);
+            wake(&ex.client);
+            ex.request_body.len() >= MAX_PENDING
+        };
+        if paused {
+            res.pause_body_read();
+        }
+        Some(Proxy(self.0, paused || self.1))
+    }
+}
+
+impl Server for Proxy {
+    type Seed = ();
+    type Context = Context;
+    fn headers_received(_seed: (), head: server::Head, _res: &mut Response,
+        scope: &mut Scope<Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let request_length = match head.body_kind {
+            BodyKind::Fixed(n) => Some(n),
+            BodyKind::Chunked => None,
+            // Upgrades can't be proxied by this example, it will be
+            // forwarded as a normal request with an empty body
+            BodyKind::Upgrade => Some(0),
+        };
+        let ex = Arc::new(Mutex::new(Exchange {
+            method: head.method.to_string(),
+            path: head.path.to_string(),
+            headers: copy_headers(head.headers),
+            request_length: request_length,
+            request_body: Vec::new(),
+            request_done: false,
+            status: None,
+            response_headers: Vec::new(),
+            response_length: None,
+            response_body: Vec::new(),
+            response_done: false,
+            failed: false,
+            server: scope.notifier(),
+            client: None,
+        }));
+        scope.queue.push_back(ex.clone());
+        if let Some(conn) = scope.idle.pop() {
+            conn.wakeup().ok();
+        }
+        Some((Proxy(ex, false), RecvMode::Progressive(4096),
+            scope.now() + Duration::new(120, 0)))
+    }
+    fn request_received(self, _data: &[u8], _res: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_chunk(self, chunk: &[u8], res: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        self.request_data(chunk, res)
+    }
+    fn request_end(self, _res: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        {
+            let mut ex = self.0.lock().unwrap();
+            ex.request_done = true;
+            wake(&ex.client);
+        }
+        Some(self)
+    }
+    fn timeout(self, res: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        self.0.lock().unwrap().failed = true;
+        if !res.is_started() {
+            error_page(res, 504);
+        }
+        None
+    }
+    fn wakeup(self, res: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        self.forward(res)
+    }
+}
+
+/// The upstream connection
+struct Upstream;
+
+impl Upstream {
+    fn next_request(self, scope: &mut Scope<Context>) -> Task<Upstream> {
+        match scope.queue.pop_front() {
+            Some(ex) => Task::Request(self, Forward(ex)),
+            None => {
+                let notifier = scope.notifier();
+                scope.idle.push(notifier);
+                Task::Sleep(self, scope.now() + Duration::new(86400, 0))
+            }
+        }
+    }
+}
+
+impl Client for Upstream {
+    type Requester = Forward;
+    type Seed = ();
+    fn create(_seed: (), _scope: &mut Scope<Context>) -> Self {
+        Upstream
+    }
+    fn connection_idle(self, _conn: &Connection, scope: &mut Scope<Context>)
+        -> Task<Upstream>
+    {
+        self.next_request(scope)
+    }
+    fn connection_error(self, err: &ProtocolError,
+        _scope: &mut Scope<Context>)
+    {
+        // Closed connections are not reestablished to keep example short
+        println!("Upstream connection error: {}", err);
+    }
+    fn wakeup(self, conn: &Connection, scope: &mut Scope<Context>)
+        -> Task<Upstream>
+    {
+        if conn.is_idle() {
+            self.next_request(scope)
+        } else {
+            Task::Sleep(self, scope.now() + Duration::new(86400, 0))
+        }
+    }
+    fn timeout(self, conn: &Connection, scope: &mut Scope<Context>)
+        -> Task<Upstream>
+    {
+        self.wakeup(conn, scope)
+    }
+}
+
+/// A single request to the upstream
+struct Forward(Arc<Mutex<Exchange>>);
+
+impl Forward {
+    /// Sends the part of the request body received so far
+    fn send_body(self, req: &mut client::Request) -> Option<Self> {
+        let server = {
+            let mut ex = self.0.lock().unwrap();
+            if ex.failed {
+                return None;
+            }
+            let body = mem::replace(&mut ex.request_body, Vec::new());
+            req.write_body(&body);
+            if ex.request_done && !req.is_complete() {
+                req.done();
+            }
+            ex.server.clone()
+        };
+        // Let the server side resume reading the request body
+        server.wakeup().ok();
+        Some(self)
+    }
+    fn fail(&self) {
+        let mut ex = self.0.lock().unwrap();
+        ex.failed = true;
+        ex.server.wakeup().ok();
+    }
+}
+
+impl Requester for Forward {
+    type Context = Context;
+    fn prepare_request(self, req: &mut client::Request,
+        scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        {
+            let mut ex = self.0.lock().unwrap();
+            ex.client = Some(scope.notifier());
+            req.start(&ex.method, &ex.path, client::Version::Http11);
+            for &(ref name, ref value) in &ex.headers {
+                req.add_header(name, value).ok();
+            }
+            match ex.request_length {
+                Some(n) => req.add_length(n).unwrap(),
+                None => req.add_chunked().unwrap(),
+            }
+            req.done_headers().unwrap();
+        }
+        self.send_body(req)
+    }
+    fn headers_received(self, head: client::Head, _req: &mut client::Request,
+        scope: &mut Scope<Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        {
+            let mut ex = self.0.lock().unwrap();
+            ex.status = Some(head.code);
+            ex.response_headers = copy_headers(head.headers);
+            ex.response_length = content_length(head.headers);
+            ex.server.wakeup().ok();
+        }
+        Some((self, RecvMode::Progressive(4096),
+            scope.now() + Duration::new(120, 0)))
+    }
+    fn response_received(self, _data: &[u8], _req: &mut client::Request,
+        _scope: &mut Scope<Context>)
+    {
+        unreachable!();
+    }
+    fn response_chunk(self, chunk: &[u8], _req: &mut client::Request,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        {
+            let mut ex = self.0.lock().unwrap();
+            if ex.failed {
+                return None;
+            }
+            ex.response_body.extend(chunk);
+            ex.server.wakeup().ok();
+        }
+        Some(self)
+    }
+    fn response_end(self, _req: &mut client::Request,
+        _scope: &mut Scope<Context>)
+    {
+        let mut ex = self.0.lock().unwrap();
+        ex.response_done = true;
+        ex.server.wakeup().ok();
+    }
+    fn timeout(self, _req: &mut client::Request, _scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        self.fail();
+        None
+    }
+    fn wakeup(self, req: &mut client::Request, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        if req.is_complete() {
+            Some(self)
+        } else {
+            self.send_body(req)
+        }
+    }
+    fn bad_response(self, err: &ResponseError, _scope: &mut Scope<Context>) {
+        println!("Bad response from upstream: {}", err);
+        self.fail();
+    }
+}
+
+fn main() {
+    env_logger::init().unwrap();
+    let mut upstream = "127.0.0.1:3000".to_string();
+    let mut listen = "127.0.0.1:8080".to_string();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.refer(&mut upstream)
+            .add_argument("upstream", Store, "Address of the upstream");
+        ap.refer(&mut listen)
+            .add_option(&["-l", "--listen"], Store, "Address to listen at");
+        ap.parse_args_or_exit();
+    }
+    let addr: SocketAddr = upstream.to_socket_addrs().unwrap().next()
+        .expect("upstream address");
+    println!("Proxying http://{}/ to http://{}/", listen, upstream);
+    let lst = TcpListener::bind(&listen.parse().unwrap()).unwrap();
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(Context {
+        queue: VecDeque::new(),
+        idle: Vec::new(),
+    });
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<Proxy, _>::new(lst, (), scope).wrap(Compose2::A)
+    }).unwrap();
+    for _ in 0..CONNECTIONS {
+        loop_inst.add_machine_with(|scope| {
+            connect_tcp::<Upstream>(scope, &addr, ()).wrap(Compose2::B)
+        }).unwrap();
+    }
+    loop_inst.run().unwrap();
+}
@@ -0,0 +1,90 @@
+extern crate rotor;
+extern crate rotor_http;
+
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor::mio::tcp::TcpListener;
+use rotor_http::server::{RecvMode, Server, Head, Response, Fsm};
+use rotor_http::mime;
+
+
+struct Context;
+
+/// Sends ten server-sent events, one per second
+struct Events(usize);
+
+impl Events {
+    fn send_event(self, res: &mut Response, scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        res.write_body(format!("data: event {}\n\n", self.0).as_bytes());
+        if self.0 == 9 {
+            res.done();
+        }
+        // Make sure the event reaches the client right now
+        res.flush();
+        Some((Events(self.0 + 1), scope.now() + Duration::new(1, 0)))
+    }
+}
+
+impl Server for Events {
+    type Seed = ();
+    type Context = Context;
+    fn headers_received(_seed: (), _head: Head, _res: &mut Response,
+        scope: &mut Scope<Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        Some((Events(0), RecvMode::Buffered(1024),
+            scope.now() + Duration::new(1, 0)))
+    }
+    fn request_received(self, _data: &[u8], res: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        res.status(200, "OK");
+        res.content_type(mime::TEXT_EVENT_STREAM).unwrap();
+        res.add_header("Cache-Control", b"no-cache").unwrap();
+        res.add_chunked().unwrap();
+        res.done_headers().unwrap();
+        // The first event is sent on timeout
+        Some(self)
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn timeout(self, res: &mut Response, scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        self.send_event(res, scope)
+    }
+    fn wakeup(self, res: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        // Called when the event is flushed, wait for the next timeout
+        if res.is_complete() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+fn main() {
+    println!("Starting http server on http://127.0.0.1:3000/");
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(Context);
+    let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<Events, _>::new(lst, (), scope)
+    }).unwrap();
+    loop_inst.run().unwrap();
+}
@@ -0,0 +1,148 @@
+//! Static file server with `Range` support
+//!
+//! Run with `cargo run --example static_files -- <dir>` and try
+//! `curl -r 0-99 http://127.0.0.1:3000/<file>`.
+extern crate rotor;
+extern crate rotor_http;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rotor::{Scope, Time};
+use rotor::mio::tcp::TcpListener;
+use rotor_http::server::{RecvMode, Server, Head, Response, Fsm, Method};
+use rotor_http::server::HttpDate;
+
+
+struct Context {
+    root: PathBuf,
+}
+
+struct StaticFile;
+
+// Rejects `..` and absolute paths, so only files under the root are served
+fn file_path(root: &Path, head: &Head) -> Option<PathBuf> {
+    let mut result = root.to_path_buf();
+    for segment in head.decoded_path().split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            x if x.contains('\\') || x.contains('\0') => return None,
+            x => result.push(x),
+        }
+    }
+    Some(result)
+}
+
+fn serve(head: &Head, res: &mut Response, root: &Path) {
+    let method = head.method_enum();
+    if method != Method::Get && method != Method::Head {
+        res.method_not_allowed(&["GET", "HEAD"]);
+        return;
+    }
+    let path = match file_path(root, head) {
+        Some(path) => path,
+        None => return res.send_status(403, "Forbidden"),
+    };
+    let file = File::open(&path).and_then(|f| f.metadata().map(|m| (f, m)));
+    let (mut file, meta) = match file {
+        Ok((f, m)) => (f, m),
+        Err(_) => return res.send_status(404, "Not Found"),
+    };
+    if !meta.is_file() {
+        return res.send_status(404, "Not Found");
+    }
+    let total_len = meta.len();
+    let modified = meta.modified().map(HttpDate::from).ok();
+    let range = match modified {
+        // If-Range can't be checked without a date, so send a full file
+        Some(date) if head.if_range_satisfied(None, date) => {
+            head.range(total_len)
+        }
+        _ => None,
+    };
+    let result = match range {
+        Some(ref range) => res.send_file_range(b"application/octet-stream",
+                                               &mut file, total_len, range),
+        None => {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map(|_| {
+                res.status(200, "OK");
+                res.add_header("Content-Type", b"application/octet-stream")
+                    .unwrap();
+                res.add_header("Accept-Ranges", b"bytes").unwrap();
+                if let Some(date) = modified {
+                    res.add_header("Last-Modified",
+                        date.to_string().as_bytes()).unwrap();
+                }
+                res.add_length(data.len() as u64).unwrap();
+                res.done_headers().unwrap();
+                res.write_body(&data);
+                res.done();
+            })
+        }
+    };
+    if let Err(e) = result {
+        // nothing is written to the response on error
+        println!("Error reading {:?}: {}", path, e);
+        res.send_status(500, "Internal Server Error");
+    }
+}
+
+impl Server for StaticFile {
+    type Seed = ();
+    type Context = Context;
+    fn headers_received(_seed: (), head: Head, res: &mut Response,
+        scope: &mut Scope<Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        serve(&head, res, &scope.root);
+        Some((StaticFile, RecvMode::Buffered(1024),
+            scope.now() + Duration::new(10, 0)))
+    }
+    fn request_received(self, _data: &[u8], _res: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        // the response is already written in `headers_received`
+        None
+    }
+    fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+        _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn request_end(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unreachable!();
+    }
+    fn timeout(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<(Self, Time)>
+    {
+        unimplemented!();
+    }
+    fn wakeup(self, _response: &mut Response, _scope: &mut Scope<Context>)
+        -> Option<Self>
+    {
+        unimplemented!();
+    }
+}
+
+fn main() {
+    let root = PathBuf::from(env::args().nth(1).unwrap_or(".".to_string()));
+    println!("Serving {:?} on http://127.0.0.1:3000/", root);
+    let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
+    let mut loop_inst = event_loop.instantiate(Context {
+        root: root,
+    });
+    let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap()).unwrap();
+    loop_inst.add_machine_with(|scope| {
+        Fsm::<StaticFile, _>::new(lst, (), scope)
+    }).unwrap();
+    loop_inst.run().unwrap();
+}
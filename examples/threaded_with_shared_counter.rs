@@ -38,14 +38,6 @@ enum HelloWorld {
     PageNotFound,
 }
 
-fn send_string(res: &mut Response, data: &[u8]) {
-    res.status(200, "OK");
-    res.add_length(data.len() as u64).unwrap();
-    res.done_headers().unwrap();
-    res.write_body(data);
-    res.done();
-}
-
 impl Server for HelloWorld {
     type Seed = ();
     type Context = Context;
@@ -69,24 +61,21 @@ impl Server for HelloWorld {
         use self::HelloWorld::*;
         match self {
             Hello => {
-                send_string(res, b"Hello World!");
+                res.send_body(200, "OK", b"text/plain", b"Hello World!");
             }
             GetNum => {
-                send_string(res,
+                res.send_body(200, "OK", b"text/plain",
                     format!("This host has been visited {} times",
                         scope.get())
                     .as_bytes());
             }
             HelloName(name) => {
-                send_string(res, format!("Hello {}!", name).as_bytes());
+                res.send_body(200, "OK", b"text/plain",
+                    format!("Hello {}!", name).as_bytes());
             }
             PageNotFound => {
-                let data = b"404 - Page not found";
-                res.status(404, "Not Found");
-                res.add_length(data.len() as u64).unwrap();
-                res.done_headers().unwrap();
-                res.write_body(data);
-                res.done();
+                res.send_body(404, "Not Found", b"text/plain",
+                    b"404 - Page not found");
             }
         }
         None
@@ -31,6 +31,7 @@ use std::time::Duration;
 use rotor::{Scope, Time};
 use rotor::mio::tcp::TcpListener;
 use rotor_http::server::{self, Fsm, Head, RecvMode, Response, Server};
+use rotor_http::server::Method;
 
 /// Represents a single Todo entry.
 ///
@@ -165,22 +166,24 @@ impl Server for TodoBackend {
         -> Option<(Self, RecvMode, Time)>
     {
         use TodoBackend::*;
-        Some((if head.method == "OPTIONS" {
+        let method = head.method_enum();
+        let path = head.decoded_path();
+        Some((if method == Method::Options {
                 Preflight
-            } else if head.path == "/" {
-                match head.method {
-                    "GET" => List,
-                    "POST" => Create,
-                    "DELETE" => Clear,
+            } else if path == "/" {
+                match method {
+                    Method::Get => List,
+                    Method::Post => Create,
+                    Method::Delete => Clear,
                     _ => MethodNotAllowed(b"GET, POST, DELETE"),
                 }
-            } else if head.path.starts_with("/todo/") {
-                let id = head.path[6..].parse().unwrap();
-                match head.method {
-                    "GET" => Get(id),
-                    "PATCH" => Patch(id),
-                    "DELETE" => Delete(id),
-                    _ => MethodNotAllowed(b"GET, PATCH, DELETE"),
+            } else if path.starts_with("/todo/") {
+                match (path[6..].parse(), method) {
+                    (Ok(id), Method::Get) => Get(id),
+                    (Ok(id), Method::Patch) => Patch(id),
+                    (Ok(id), Method::Delete) => Delete(id),
+                    (Ok(_), _) => MethodNotAllowed(b"GET, PATCH, DELETE"),
+                    (Err(_), _) => NotFound,
                 }
             } else {
                 NotFound
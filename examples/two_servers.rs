@@ -32,14 +32,6 @@ struct Incr;
 
 struct Get;
 
-fn send_string(res: &mut Response, data: &[u8]) {
-    res.status(200, "OK");
-    res.add_length(data.len() as u64).unwrap();
-    res.done_headers().unwrap();
-    res.write_body(data);
-    res.done();
-}
-
 impl Server for Incr {
     type Seed = ();
     type Context = Context;
@@ -55,7 +47,7 @@ impl Server for Incr {
         _scope: &mut Scope<Context>)
         -> Option<Self>
     {
-        send_string(res, b"Hello World!");
+        res.send_body(200, "OK", b"text/plain", b"Hello World!");
         None
     }
     fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
@@ -98,7 +90,7 @@ impl Server for Get {
         scope: &mut Scope<Context>)
         -> Option<Self>
     {
-        send_string(res,
+        res.send_body(200, "OK", b"text/plain",
             format!("This host has been visited {} times",
                 scope.get())
             .as_bytes());
@@ -0,0 +1,223 @@
+//! WebSocket echo server
+//!
+//! Run with `cargo run --example websocket_echo --features=websocket` and
+//! open http://127.0.0.1:3000/ in the browser.
+extern crate rotor;
+extern crate rotor_http;
+
+#[cfg(feature="websocket")]
+mod echo {
+    use std::time::Duration;
+
+    use rotor::{Scope, Time};
+    use rotor::mio::tcp::TcpListener;
+    use rotor_http::server::{RecvMode, Server, Head, Response, Fsm};
+    use rotor_http::server::{BodyKind, HttpError};
+    use rotor_http::server::websocket;
+    use rotor_http::mime;
+
+    const PAGE: &'static str = r#"<!DOCTYPE html>
+<html><body>
+<input id="msg" value="hello"><button onclick="send()">Send</button>
+<pre id="log"></pre>
+<script>
+var ws = new WebSocket("ws://" + location.host + "/echo");
+ws.onmessage = function(e) {
+    document.getElementById("log").textContent += e.data + "\n";
+};
+function send() { ws.send(document.getElementById("msg").value); }
+</script>
+</body></html>
+"#;
+
+    /// Maximum size of the frame we accept
+    const MAX_FRAME: usize = 65536;
+
+    const OP_CLOSE: u8 = 0x8;
+    const OP_PING: u8 = 0x9;
+    const OP_PONG: u8 = 0xA;
+
+    enum Echo {
+        Page,
+        /// Incomplete frame received so far
+        Frames(Vec<u8>),
+    }
+
+    enum Frame {
+        /// First byte of the header (FIN bit and opcode) and payload
+        Complete(u8, Vec<u8>, usize),
+        Partial,
+        TooLarge,
+    }
+
+    /// Parses a masked frame sent by a client
+    fn parse_frame(buf: &[u8]) -> Frame {
+        if buf.len() < 2 {
+            return Frame::Partial;
+        }
+        let (len, mut off) = match buf[1] & 0x7F {
+            126 if buf.len() >= 4 => {
+                (((buf[2] as u64) << 8) | buf[3] as u64, 4)
+            }
+            127 if buf.len() >= 10 => {
+                (buf[2..10].iter().fold(0, |n, &x| (n << 8) | x as u64), 10)
+            }
+            126 | 127 => return Frame::Partial,
+            len => (len as u64, 2),
+        };
+        if len > MAX_FRAME as u64 {
+            return Frame::TooLarge;
+        }
+        let len = len as usize;
+        let masked = buf[1] & 0x80 != 0;
+        let mask = if masked {
+            if buf.len() < off + 4 {
+                return Frame::Partial;
+            }
+            off += 4;
+            [buf[off-4], buf[off-3], buf[off-2], buf[off-1]]
+        } else {
+            [0; 4]
+        };
+        if buf.len() < off + len {
+            return Frame::Partial;
+        }
+        let payload = buf[off..off+len].iter().enumerate()
+            .map(|(i, &x)| x ^ mask[i % 4])
+            .collect();
+        Frame::Complete(buf[0], payload, off + len)
+    }
+
+    /// Writes an unmasked frame as a server must do
+    fn write_frame(res: &mut Response, first: u8, payload: &[u8]) {
+        let mut header = vec![first];
+        if payload.len() < 126 {
+            header.push(payload.len() as u8);
+        } else if payload.len() < 65536 {
+            header.push(126);
+            header.push((payload.len() >> 8) as u8);
+            header.push(payload.len() as u8);
+        } else {
+            header.push(127);
+            for i in (0..8).rev() {
+                header.push(((payload.len() as u64) >> (i*8)) as u8);
+            }
+        }
+        res.write_raw(&header);
+        res.write_raw(payload);
+    }
+
+    impl Server for Echo {
+        type Seed = ();
+        type Context = ();
+        fn headers_received(_seed: (), head: Head, res: &mut Response,
+            scope: &mut Scope<()>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            if head.body_kind != BodyKind::Upgrade {
+                return Some((Echo::Page, RecvMode::Buffered(1024),
+                    scope.now() + Duration::new(10, 0)));
+            }
+            match websocket::accept(&head, None, res) {
+                Ok(()) => {
+                    Some((Echo::Frames(Vec::new()), RecvMode::Upgrade,
+                        scope.now() + Duration::new(30, 0)))
+                }
+                Err(e) => {
+                    let (code, reason) = e.http_status();
+                    res.status(code, reason);
+                    res.add_header("Sec-WebSocket-Version",
+                        websocket::VERSION.as_bytes()).unwrap();
+                    res.add_length(0).unwrap();
+                    res.done_headers().unwrap();
+                    res.done();
+                    None
+                }
+            }
+        }
+        fn request_received(self, _data: &[u8], res: &mut Response,
+            _scope: &mut Scope<()>)
+            -> Option<Self>
+        {
+            res.send_body(200, "OK", mime::TEXT_HTML.as_bytes(),
+                PAGE.as_bytes());
+            None
+        }
+        fn request_chunk(self, chunk: &[u8], res: &mut Response,
+            _scope: &mut Scope<()>)
+            -> Option<Self>
+        {
+            let mut buf = match self {
+                Echo::Frames(buf) => buf,
+                Echo::Page => unreachable!(),
+            };
+            buf.extend(chunk);
+            loop {
+                let (first, payload, len) = match parse_frame(&buf) {
+                    Frame::Complete(first, payload, len) => {
+                        (first, payload, len)
+                    }
+                    Frame::Partial => return Some(Echo::Frames(buf)),
+                    Frame::TooLarge => return None,
+                };
+                buf.drain(..len);
+                match first & 0x0F {
+                    OP_CLOSE => {
+                        write_frame(res, 0x80 | OP_CLOSE, &payload);
+                        return None;
+                    }
+                    OP_PING => write_frame(res, 0x80 | OP_PONG, &payload),
+                    OP_PONG => {}
+                    // text, binary and continuation frames are echoed
+                    _ => write_frame(res, first, &payload),
+                }
+            }
+        }
+        fn request_end(self, _res: &mut Response, _scope: &mut Scope<()>)
+            -> Option<Self>
+        {
+            // client closed the connection
+            None
+        }
+        fn timeout(self, res: &mut Response, scope: &mut Scope<()>)
+            -> Option<(Self, Time)>
+        {
+            match self {
+                Echo::Page => None,
+                Echo::Frames(buf) => {
+                    // keep the connection alive
+                    write_frame(res, 0x80 | OP_PING, b"");
+                    Some((Echo::Frames(buf),
+                          scope.now() + Duration::new(30, 0)))
+                }
+            }
+        }
+        fn wakeup(self, _res: &mut Response, _scope: &mut Scope<()>)
+            -> Option<Self>
+        {
+            unreachable!();
+        }
+    }
+
+    pub fn main() {
+        println!("Starting http server on http://127.0.0.1:3000/");
+        let event_loop = ::rotor::Loop::new(&::rotor::Config::new()).unwrap();
+        let mut loop_inst = event_loop.instantiate(());
+        let lst = TcpListener::bind(&"127.0.0.1:3000".parse().unwrap())
+            .unwrap();
+        loop_inst.add_machine_with(|scope| {
+            Fsm::<Echo, _>::new(lst, (), scope)
+        }).unwrap();
+        loop_inst.run().unwrap();
+    }
+}
+
+#[cfg(feature="websocket")]
+fn main() {
+    echo::main();
+}
+
+#[cfg(not(feature="websocket"))]
+fn main() {
+    println!("Build with `--features=websocket` to run this example");
+}
@@ -0,0 +1,173 @@
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+use std::time::Duration;
+
+use headers;
+
+
+/// The value of the `Warning` header for a stale response
+///
+/// A cache must add it when it serves a stale response, e.g.
+/// `Warning: 110 - "Response is Stale"`.
+pub const STALE_WARNING: &'static str = "110 - \"Response is Stale\"";
+
+/// Parsed directives of the `Cache-Control` response header(s)
+///
+/// See `Head::cache_control`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// The `max-age` directive
+    pub max_age: Option<Duration>,
+    /// The `s-maxage` directive, which applies to shared caches only
+    pub s_maxage: Option<Duration>,
+    /// The `no-cache` directive, with or without the field names
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub no_transform: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub public: bool,
+    /// The `private` directive, with or without the field names
+    pub private: bool,
+}
+
+// Splits the list at commas which are not inside of quoted strings
+fn split_list(value: &[u8]) -> Vec<&[u8]> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, &ch) in value.iter().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quoted && ch == b'\\' {
+            escaped = true;
+        } else if ch == b'"' {
+            quoted = !quoted;
+        } else if ch == b',' && !quoted {
+            result.push(&value[start..idx]);
+            start = idx + 1;
+        }
+    }
+    result.push(&value[start..]);
+    result
+}
+
+// Malformed value is treated as zero, so the response is considered stale
+fn parse_seconds(value: &[u8]) -> Duration {
+    let value = headers::trim(value);
+    let value = if value.len() >= 2 && value[0] == b'"' &&
+        value[value.len()-1] == b'"'
+    {
+        &value[1..value.len()-1]
+    } else {
+        value
+    };
+    if value.len() == 0 || !value.iter().all(|&x| x >= b'0' && x <= b'9') {
+        return Duration::new(0, 0);
+    }
+    // values too large to parse are capped as RFC 7234 recommends
+    Duration::new(from_utf8(value).unwrap().parse().unwrap_or(2147483648), 0)
+}
+
+impl CacheControl {
+    /// Parses the values of all `Cache-Control` headers of the response
+    ///
+    /// Directives are case-insensitive, unknown ones are ignored. If
+    /// a directive with a value is repeated, the first one is used.
+    pub fn parse<'x, I>(values: I) -> CacheControl
+        where I: IntoIterator<Item=&'x [u8]>
+    {
+        let mut result = CacheControl::default();
+        for value in values {
+            for item in split_list(value) {
+                let mut pair = item.splitn(2, |&x| x == b'=');
+                let name = headers::trim(pair.next().unwrap());
+                let arg = pair.next();
+                if name.eq_ignore_ascii_case(b"max-age") {
+                    if result.max_age.is_none() {
+                        result.max_age = Some(parse_seconds(
+                            arg.unwrap_or(&b""[..])));
+                    }
+                } else if name.eq_ignore_ascii_case(b"s-maxage") {
+                    if result.s_maxage.is_none() {
+                        result.s_maxage = Some(parse_seconds(
+                            arg.unwrap_or(&b""[..])));
+                    }
+                } else if name.eq_ignore_ascii_case(b"no-cache") {
+                    result.no_cache = true;
+                } else if name.eq_ignore_ascii_case(b"no-store") {
+                    result.no_store = true;
+                } else if name.eq_ignore_ascii_case(b"no-transform") {
+                    result.no_transform = true;
+                } else if name.eq_ignore_ascii_case(b"must-revalidate") {
+                    result.must_revalidate = true;
+                } else if name.eq_ignore_ascii_case(b"proxy-revalidate") {
+                    result.proxy_revalidate = true;
+                } else if name.eq_ignore_ascii_case(b"public") {
+                    result.public = true;
+                } else if name.eq_ignore_ascii_case(b"private") {
+                    result.private = true;
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::CacheControl;
+
+    fn parse(values: &[&str]) -> CacheControl {
+        CacheControl::parse(values.iter().map(|x| x.as_bytes()))
+    }
+
+    #[test]
+    fn test_simple() {
+        assert_eq!(parse(&[]), CacheControl::default());
+        assert_eq!(parse(&["max-age=60, Must-Revalidate"]), CacheControl {
+            max_age: Some(Duration::new(60, 0)),
+            must_revalidate: true,
+            ..Default::default()
+        });
+        assert_eq!(parse(&["no-cache", "no-store,,public"]), CacheControl {
+            no_cache: true,
+            no_store: true,
+            public: true,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(parse(&["private=\"Set-Cookie, X-Token\", max-age=\"5\""]),
+            CacheControl {
+                max_age: Some(Duration::new(5, 0)),
+                private: true,
+                ..Default::default()
+            });
+        assert_eq!(parse(&["no-cache=\"a\\\",max-age=1\", s-maxage=10"]),
+            CacheControl {
+                s_maxage: Some(Duration::new(10, 0)),
+                no_cache: true,
+                ..Default::default()
+            });
+    }
+
+    #[test]
+    fn test_odd_values() {
+        // malformed value means the response is stale
+        assert_eq!(parse(&["max-age=soon"]).max_age,
+                   Some(Duration::new(0, 0)));
+        assert_eq!(parse(&["max-age"]).max_age, Some(Duration::new(0, 0)));
+        assert_eq!(parse(&["max-age=99999999999999999999999"]).max_age,
+                   Some(Duration::new(2147483648, 0)));
+        // first value wins
+        assert_eq!(parse(&["max-age=10", "max-age=20"]).max_age,
+                   Some(Duration::new(10, 0)));
+        assert_eq!(parse(&["x-custom=1, immutable"]),
+                   CacheControl::default());
+    }
+}
@@ -3,6 +3,8 @@ use std::io;
 use httparse;
 use rotor_stream;
 
+use recvmode::RecvMode;
+
 
 quick_error!{
     /// Error type which is passed to bad_response
@@ -27,6 +29,23 @@ quick_error!{
             description("connection error")
             display("connection error: {}", err)
         }
+        BodyTooLong(limit: usize) {
+            description("response body is larger than allowed")
+            display("response body is larger than {} bytes", limit)
+        }
+        HeadersTimeout {
+            description("timeout waiting for response headers")
+        }
+        BodyTimeout {
+            description("timeout reading response body")
+        }
+        RequestTimeout {
+            description("timeout sending the request")
+        }
+        UnsupportedRecvMode(mode: RecvMode) {
+            description("receive mode is not supported by the client")
+            display("receive mode {:?} is not supported by the client", mode)
+        }
     }
 }
 
@@ -52,6 +71,14 @@ quick_error!{
             description("error when writing to stream")
             display("write error: {}", err)
         }
+        /// Server sent more data than declared in the response headers
+        ///
+        /// For example a body in response to `HEAD` request. The response
+        /// itself is delivered, but the connection can't be reused.
+        UnexpectedData {
+            description("unexpected data after the response")
+            display("unexpected data after the response")
+        }
     }
 }
 
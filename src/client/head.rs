@@ -1,5 +1,11 @@
+use std::str::from_utf8;
+use std::time::Duration;
+
 use httparse;
+use date::HttpDate;
+use headers::{self, HeaderValues};
 use version::Version;
+use super::CacheControl;
 
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -17,3 +23,69 @@ pub struct Head<'a> {
     pub body_kind: BodyKind,
     pub close: bool,
 }
+
+impl<'a> Head<'a> {
+    /// Returns the value of the first header with the name
+    ///
+    /// Name is compared case-insensitively. The value is returned exactly
+    /// as received.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.get_all(name).next()
+    }
+    /// Returns an iterator over values of all headers with the name
+    pub fn get_all<'n>(&self, name: &'n str) -> HeaderValues<'a, 'n> {
+        headers::header_values(self.headers, name)
+    }
+    /// Returns parsed directives of the `Cache-Control` header(s)
+    ///
+    /// Note that the `Pragma: no-cache` isn't taken into account.
+    pub fn cache_control(&self) -> CacheControl {
+        CacheControl::parse(self.get_all("Cache-Control"))
+    }
+    /// Returns the delay requested by the `Retry-After` header
+    ///
+    /// Both delay in seconds and HTTP-date are supported, the date in the
+    /// past means zero delay. Returns `None` when there is no header or
+    /// the value is malformed.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.get("Retry-After")
+            .and_then(|value| parse_retry_after(value, HttpDate::now()))
+    }
+}
+
+fn parse_retry_after(value: &[u8], now: HttpDate) -> Option<Duration> {
+    let value = match from_utf8(headers::trim(value)) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    if value.len() > 0 && value.bytes().all(|x| x >= b'0' && x <= b'9') {
+        return value.parse().ok().map(|secs| Duration::new(secs, 0));
+    }
+    value.parse::<HttpDate>().ok().map(|date| {
+        Duration::new(date.timestamp().saturating_sub(now.timestamp()), 0)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use date::HttpDate;
+    use super::parse_retry_after;
+
+    #[test]
+    fn test_retry_after() {
+        let now: HttpDate = "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap();
+        assert_eq!(parse_retry_after(b"120", now),
+                   Some(Duration::new(120, 0)));
+        assert_eq!(parse_retry_after(b" 0 ", now), Some(Duration::new(0, 0)));
+        assert_eq!(parse_retry_after(b"Sun, 06 Nov 1994 08:51:07 GMT", now),
+                   Some(Duration::new(90, 0)));
+        // the date in the past, retry immediately
+        assert_eq!(parse_retry_after(b"Sun, 06 Nov 1994 08:00:00 GMT", now),
+                   Some(Duration::new(0, 0)));
+        assert_eq!(parse_retry_after(b"-5", now), None);
+        assert_eq!(parse_retry_after(b"1.5", now), None);
+        assert_eq!(parse_retry_after(b"", now), None);
+        assert_eq!(parse_retry_after(b"tomorrow", now), None);
+    }
+}
@@ -4,10 +4,12 @@
 //! provide HTTP/2.0 and TLS implementation with exactly the same protocol.
 //! But it's yet unproven if it is possible.
 //!
-//! Also DNS resolving is not implemented yet.
+//! Name resolution in `connect_host` is blocking, it uses the resolver of
+//! the operating system.
 //!
 
-use std::net::SocketAddr;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 use rotor::{Scope, Response, Void};
 use rotor::mio::tcp::TcpStream;
@@ -19,13 +21,20 @@ mod protocol;
 mod parser;
 mod connection;
 mod error;
+mod timings;
+mod cache_control;
 
 pub use version::Version;
 pub use self::request::{Request};
 pub use self::protocol::{Client, Requester, Task};
 pub use self::head::Head;
 pub use self::error::{ResponseError, ProtocolError};
+pub use self::timings::Timings;
+pub use self::cache_control::{CacheControl, STALE_WARNING};
 pub use recvmode::RecvMode;
+pub use date::{HttpDate, InvalidDate};
+pub use headers::HeaderValues;
+pub use server::Method;
 
 use self::parser::Parser;
 
@@ -73,3 +82,33 @@ pub fn connect_tcp<P: Client>(
     };
     rotor_stream::Stream::new(sock, seed, scope)
 }
+
+/// Resolves the host name and connects to the first address
+///
+/// If connecting to an address fails immediately, the next one is tried.
+/// Note that connection is non-blocking, so most failures (e.g. the
+/// connection refused) are reported to `Client::connection_error` later
+/// and the rest of the addresses are not tried in this case.
+///
+/// The name is resolved synchronously, so it blocks the main loop until
+/// resolver replies. Use `connect_tcp` with the address resolved
+/// elsewhere to avoid that.
+pub fn connect_host<P: Client>(
+    scope: &mut Scope<<P::Requester as Requester>::Context>,
+    host: &str, port: u16, seed: P::Seed)
+    -> Response<Fsm<P, TcpStream>, Void>
+{
+    let addrs = match (host, port).to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(e) => return Response::error(Box::new(e)),
+    };
+    let mut error = io::Error::new(io::ErrorKind::AddrNotAvailable,
+        format!("no addresses found for host {:?}", host));
+    for addr in addrs {
+        match TcpStream::connect(&addr) {
+            Ok(sock) => return rotor_stream::Stream::new(sock, seed, scope),
+            Err(e) => error = e,
+        }
+    }
+    Response::error(Box::new(error))
+}
@@ -3,6 +3,8 @@ use std::str::from_utf8;
 use std::cmp::min;
 use std::fmt;
 use std::error::Error;
+use std::u64;
+use std::time::Duration;
 
 use rotor::{Scope, Time};
 use rotor_stream::{Protocol, StreamSocket, Exception};
@@ -12,13 +14,14 @@ use httparse;
 use httparse::parse_chunk_size;
 
 use super::{MAX_HEADERS_SIZE, MAX_HEADERS_NUM, MAX_CHUNK_HEAD};
-use super::{Client, Requester, Connection, Task, ResponseError};
+use super::{Client, Requester, Connection, Task, ResponseError, Timings};
 use super::head::Head;
-use super::request::{Request, state};
+use super::request::{Request, state, discard_limit};
 use super::head::BodyKind;
 use message::{MessageState};
+use server::Method;
 use recvmode::RecvMode;
-use headers;
+use headers::{self, ConnectionToken};
 use version::Version;
 
 
@@ -40,10 +43,32 @@ pub enum BodyProgress {
     ProgressiveChunked(usize, usize, u64),
 }
 
+/// The `Time` is when the connection was established
 #[derive(Debug)]
-pub struct Parser<M, S>(M, ParserImpl<M::Requester>, PhantomData<*const S>)
+pub struct Parser<M, S>(M, ParserImpl<M::Requester>, Time,
+                        PhantomData<*const S>)
     where M: Client, S: StreamSocket;
 
+/// Numbers of redirects followed and retries done for a request
+#[derive(Debug, Clone, Copy, Default)]
+struct Attempts {
+    redirects: usize,
+    retries: usize,
+}
+
+/// The request sent when the response body is discarded
+struct Redirect<M: Requester> {
+    machine: M,
+    /// The method and location of the redirect, or `None` when the same
+    /// request is retried after `503 Service Unavailable` or
+    /// `429 Too Many Requests`
+    target: Option<(Method<'static>, String)>,
+    /// Attempts done including this one
+    attempts: Attempts,
+    /// The delay before the retry requested by the server
+    retry_after: Option<Duration>,
+}
+
 enum ParserImpl<M: Requester> {
     Connecting(Time),
     Idle(Time),
@@ -51,17 +76,28 @@ enum ParserImpl<M: Requester> {
         machine: M,
         request: MessageState,
         is_head: Option<bool>,
+        attempts: Attempts,
+        /// When the request was written
+        sent: Time,
     },
+    /// The `machine` is `None` when the rest of the body is discarded,
+    /// and `discard` is the number of bytes which may still be dropped
     Response {
         progress: BodyProgress,
-        machine: M,
+        machine: Option<M>,
         deadline: Time,
         request: MessageState,
+        discard: u64,
+        redirect: Option<Redirect<M>>,
+        timings: Timings,
     },
     // This state is mostly useful to switch between states easier, but
     // in fact if request is not flushed yet when response is fully received
     // this is actually useful thing
     Flushing(Time),
+    /// Previous request is flushed before the redirected one is sent, or
+    /// the delay before the retry
+    Redirecting(Time, Redirect<M>),
 }
 
 impl<M: Requester> fmt::Debug for ParserImpl<M> {
@@ -75,6 +111,11 @@ impl<M: Requester> fmt::Debug for ParserImpl<M> {
                 fmt.debug_tuple("Flushing").field(&tm).finish()
             }
             Idle(tm) => fmt.debug_tuple("Idle").field(&tm).finish(),
+            Redirecting(tm, ref r) => {
+                fmt.debug_tuple("Redirecting").field(&tm)
+                .field(&r.target.as_ref().map(|&(_, ref loc)| loc))
+                .finish()
+            }
             ReadHeaders { ref request, ref is_head, .. } => {
                 fmt.debug_struct("ReadHeaders")
                 .field("request", request)
@@ -100,18 +141,22 @@ fn scan_headers(is_head: bool, code: u16, headers: &[httparse::Header])
     ///
     /// Algorithm:
     ///
-    /// 1. For HEAD, 1xx, 204, 304 -- no body
+    /// 1. For HEAD, 1xx, 204, 304 -- no body, regardless of the
+    ///    `Content-Length` and `Transfer-Encoding` headers
     /// 2. If last transfer encoding is chunked -> Chunked
     /// 3. If Content-Length -> Fixed
     /// 4. Else Eof
     use super::head::BodyKind::*;
     let mut has_content_length = false;
     let mut close = false;
-    if is_head || (code > 100 && code < 200) || code == 204 || code == 304 {
+    if is_head || (code >= 100 && code < 200) || code == 204 || code == 304
+    {
         for header in headers.iter() {
             // TODO(tailhook) check for transfer encoding and content-length
             if headers::is_connection(header.name) {
-                if header.value.split(|&x| x == b',').any(headers::is_close) {
+                if headers::connection_tokens(header.value)
+                    .any(|t| t == ConnectionToken::Close)
+                {
                     close = true;
                 }
             }
@@ -137,7 +182,11 @@ fn scan_headers(is_head: bool, code: u16, headers: &[httparse::Header])
             }
             has_content_length = true;
             if result != Chunked {
-                let s = try!(from_utf8(header.value).map_err(|_| ()));
+                let value = headers::trim_ows(header.value);
+                if !headers::is_digits(value) {
+                    return Err(());
+                }
+                let s = try!(from_utf8(value).map_err(|_| ()));
                 let len = try!(s.parse().map_err(|_| ()));
                 result = Fixed(len);
             } else {
@@ -145,7 +194,9 @@ fn scan_headers(is_head: bool, code: u16, headers: &[httparse::Header])
                 close = true;
             }
         } else if headers::is_connection(header.name) {
-            if header.value.split(|&x| x == b',').any(headers::is_close) {
+            if headers::connection_tokens(header.value)
+                .any(|t| t == ConnectionToken::Close)
+            {
                 close = true;
             }
         }
@@ -161,12 +212,13 @@ fn consumed(off: usize) -> usize {
     if off > 0 { off+2 } else { 0 }
 }
 
-fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
+// Returns `None` for the modes which are supported by the server only
+fn start_body(mode: RecvMode, body: BodyKind) -> Option<BodyProgress> {
     use recvmode::RecvMode::*;
     use super::head::BodyKind::*;
     use self::BodyProgress::*;
 
-    match (mode, body) {
+    Some(match (mode, body) {
         // The size of Fixed(x) is checked in parse_headers
         (Buffered(_), Fixed(y)) => BufferFixed(y as usize),
         (Buffered(x), Chunked) => BufferChunked(x, 0, 0),
@@ -174,20 +226,44 @@ fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
         (Progressive(x), Fixed(y)) => ProgressiveFixed(x, y),
         (Progressive(x), Chunked) => ProgressiveChunked(x, 0, 0),
         (Progressive(x), Eof) => ProgressiveEOF(x),
+        (Frames(..), _) | (Upgrade, _) => return None,
+    })
+}
+
+fn is_redirect(code: u16) -> bool {
+    match code {
+        301 | 302 | 303 | 307 | 308 => true,
+        _ => false,
     }
 }
 
+fn is_interim(code: u16) -> bool {
+    // 101 Switching Protocols is final, the connection is taken over by
+    // the new protocol after it
+    code >= 100 && code < 200 && code != 101
+}
+
 fn parse_headers<M>(buffer: &mut Buf, end: usize,
     proto: M, mut req: Request, is_head: bool,
+    attempts: Attempts, limits: Attempts, connected: Time, sent: Time,
     scope: &mut Scope<M::Context>)
     -> Result<ParserImpl<M>, ()>
     where M: Requester
 {
     let resp = {
+        // Unlike the server, which rejects requests with obs-fold, we
+        // accept such responses, as some legacy servers still send them
+        let unfolded;
+        let data = if headers::has_obs_fold(&buffer[..end+4]) {
+            unfolded = headers::unfold(&buffer[..end+4]);
+            &unfolded[..]
+        } else {
+            &buffer[..end+4]
+        };
         let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS_NUM];
         let (ver, code, reason, headers) = {
             let mut raw = httparse::Response::new(&mut headers);
-            match raw.parse(&buffer[..end+4]) {
+            match raw.parse(data) {
                 Ok(httparse::Status::Complete(x)) => {
                     assert!(x == end+4);
                     let ver = raw.version.unwrap();
@@ -215,31 +291,163 @@ fn parse_headers<M>(buffer: &mut Buf, end: usize,
             // but hopefully it's rare enough to ignore nowadays
             close: close || ver == 0,
         };
-        let hdr = proto.headers_received(head, &mut req, scope);
-        let (mach, mode, dline) = match hdr {
-            Some(triple) => triple,
-            None => return Err(()),
+        let discard_deadline = scope.now() + proto.body_timeout(scope);
+        // The `response_complete` is updated when the response is done
+        let timings = Timings {
+            connected: connected,
+            request_sent: sent,
+            headers_received: scope.now(),
+            response_complete: scope.now(),
+        };
+        // Redirect is followed on the same connection, so the body must
+        // be delimited and the connection must be kept alive
+        let reusable = !head.close && body != BodyKind::Eof;
+        let redirect = if is_redirect(code) && reusable &&
+            attempts.redirects < limits.redirects
+        {
+            proto.on_redirect(&head, scope)
+        } else {
+            None
         };
-        let progress = start_body(mode, body);
-        ParserImpl::Response {
-            machine: mach,
-            deadline: dline,
-            progress: progress,
-            request: state(req),
+        // Retried on the same connection for the same reasons
+        let retry = (code == 503 || code == 429) && reusable &&
+            attempts.retries < limits.retries && proto.is_idempotent();
+        let retry_after = if retry { head.retry_after() } else { None };
+        if is_interim(code) {
+            // The final response follows on the same connection, so we
+            // go on reading headers. The deadline is recomputed by
+            // `intent()` and the delimiter is searched from the start of
+            // the buffer after the interim head is consumed below.
+            match proto.informational(head, scope) {
+                Some(m) => ParserImpl::ReadHeaders {
+                    machine: m,
+                    request: state(req),
+                    is_head: Some(is_head),
+                    attempts: attempts,
+                    sent: sent,
+                },
+                None => return Err(()),
+            }
+        } else if redirect.is_some() || retry {
+            let target = redirect.map(|(method, location)| {
+                if code == 303 && method != Method::Head {
+                    (Method::Get, location)
+                } else {
+                    (method, location)
+                }
+            });
+            let attempts = if target.is_some() {
+                Attempts { redirects: attempts.redirects + 1, ..attempts }
+            } else {
+                Attempts { retries: attempts.retries + 1, ..attempts }
+            };
+            let progress = match body {
+                BodyKind::Fixed(n) => BodyProgress::ProgressiveFixed(1, n),
+                _ => BodyProgress::ProgressiveChunked(1, 0, 0),
+            };
+            ParserImpl::Response {
+                machine: None,
+                deadline: discard_deadline,
+                progress: progress,
+                request: state(req),
+                discard: u64::MAX,
+                redirect: Some(Redirect {
+                    machine: proto,
+                    target: target,
+                    attempts: attempts,
+                    retry_after: retry_after,
+                }),
+                timings: timings,
+            }
+        } else {
+            let hdr = proto.headers_received(head, &mut req, scope);
+            let limit = discard_limit(&req);
+            let (mach, progress, dline) = match (hdr, limit, body) {
+                (Some((mach, mode, dline)), _, _) => {
+                    match start_body(mode, body) {
+                        Some(progress) => (Some(mach), progress, dline),
+                        None => {
+                            mach.bad_response(
+                                &ResponseError::UnsupportedRecvMode(mode),
+                                scope);
+                            return Err(());
+                        }
+                    }
+                }
+                (None, Some(limit), BodyKind::Fixed(n)) if n <= limit => {
+                    (None, BodyProgress::ProgressiveFixed(1, n),
+                     discard_deadline)
+                }
+                (None, Some(_), BodyKind::Chunked) => {
+                    (None, BodyProgress::ProgressiveChunked(1, 0, 0),
+                     discard_deadline)
+                }
+                (None, _, _) => return Err(()),
+            };
+            ParserImpl::Response {
+                machine: mach,
+                deadline: dline,
+                progress: progress,
+                request: state(req),
+                discard: limit.unwrap_or(0),
+                redirect: None,
+                timings: timings,
+            }
         }
     };
     buffer.consume(end+4);
     Ok(resp)
 }
 
+// Reports timings of the complete response to the requester
+fn report_timings<M: Requester>(machine: &Option<M>, timings: Timings,
+    scope: &mut Scope<M::Context>)
+{
+    if let Some(ref m) = *machine {
+        m.response_timings(&Timings {
+            response_complete: scope.now(),
+            ..timings
+        }, scope);
+    }
+}
+
+// Passes a chunk of the response body to the requester, or drops it when
+// the body is discarded. Returns `Err` if the discard limit is exceeded.
+fn deliver<M: Requester>(machine: Option<M>, chunk: &[u8], req: &mut Request,
+    discard: &mut u64, scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, ()>
+{
+    match machine {
+        Some(m) => Ok(m.response_chunk(chunk, req, scope)),
+        None if chunk.len() as u64 <= *discard => {
+            *discard -= chunk.len() as u64;
+            Ok(None)
+        }
+        None => Err(()),
+    }
+}
+
 impl<M: Client, S: StreamSocket> Parser<M, S> {
-    fn finish(cli: M, req: Request,
+    fn finish(cli: M, connected: Time, req: Request,
+        redirect: Option<Redirect<M::Requester>>,
         scope: &mut Scope<<M::Requester as Requester>::Context>)
         -> Intent<Parser<M, S>>
     {
         if req.is_complete() {
-            ParserImpl::Flushing(scope.now() + cli.idle_timeout(scope))
-                .intent(cli, scope)
+            let deadline = match redirect {
+                Some(ref r) if r.target.is_none() => {
+                    let delay = match r.retry_after {
+                        Some(delay) => delay,
+                        None => cli.retry_delay(r.attempts.retries, scope),
+                    };
+                    scope.now() + delay
+                }
+                _ => scope.now() + cli.idle_timeout(scope),
+            };
+            match redirect {
+                Some(r) => ParserImpl::Redirecting(deadline, r),
+                None => ParserImpl::Flushing(deadline),
+            }.intent(cli, connected, scope)
         } else {
             // Response is done before request is sent fully, let's close
             // the connectoin
@@ -250,12 +458,13 @@ impl<M: Client, S: StreamSocket> Parser<M, S> {
 }
 
 impl<M: Requester> ParserImpl<M> {
-    fn wrap<S: StreamSocket, T: Client<Requester=M>>(self, cli: T)
+    fn wrap<S: StreamSocket, T: Client<Requester=M>>(self, cli: T,
+        connected: Time)
         -> Parser<T, S>
     {
-        Parser(cli, self, PhantomData)
+        Parser(cli, self, connected, PhantomData)
     }
-    fn intent<S, T>(self, cli: T,
+    fn intent<S, T>(self, cli: T, connected: Time,
         scope: &mut Scope<<T::Requester as Requester>::Context>)
         -> Intent<Parser<T, S>>
         where S: StreamSocket, T: Client<Requester=M>
@@ -264,14 +473,18 @@ impl<M: Requester> ParserImpl<M> {
         use self::ParserImpl::*;
         use self::BodyProgress::*;
         let (exp, dline) = match self {
-            Connecting(dline) | Flushing(dline) => (E::Flush(0), dline),
+            // Output is still flushed while waiting for the retry
+            Redirecting(dline, Redirect { target: None, .. })
+            => (Sleep, dline),
+            Connecting(dline) | Flushing(dline) | Redirecting(dline, _)
+            => (E::Flush(0), dline),
             ReadHeaders { ref machine, ..} => (
                         E::Delimiter(0, b"\r\n\r\n", MAX_HEADERS_SIZE),
-                        scope.now() + machine.byte_timeout(scope)),
+                        scope.now() + machine.header_timeout(scope)),
             Response { ref progress, ref deadline, ref machine, .. } => {
                 let exp = match *progress {
                     BufferFixed(x) => Bytes(x),
-                    BufferEOF(x) => Bytes(x),
+                    BufferEOF(x) => Bytes(x.saturating_add(1)),
                     BufferChunked(_, off, 0) => {
                         Delimiter(consumed(off), b"\r\n",
                                   consumed(off) + MAX_CHUNK_HEAD)
@@ -285,42 +498,93 @@ impl<M: Requester> ParserImpl<M> {
                     ProgressiveChunked(hint, off, left)
                     => Bytes(min(hint as u64, off as u64 +left) as usize + 2)
                 };
-                (exp, min(*deadline, scope.now() + machine.byte_timeout(scope)))
+                let dline = match *machine {
+                    Some(ref m) => {
+                        min(*deadline, scope.now() + m.body_timeout(scope))
+                    }
+                    None => *deadline,
+                };
+                (exp, dline)
             }
             Idle(x) => (Sleep, x),
         };
-        Intent::of(self.wrap(cli)).expect(exp).deadline(dline)
+        Intent::of(self.wrap(cli, connected)).expect(exp).deadline(dline)
     }
 }
 
 fn maybe_new_request<M: Client, S: StreamSocket>(
-    transport: &mut Transport<S>, task: Task<M>,
+    transport: &mut Transport<S>, task: Task<M>, connected: Time,
     scope: &mut Scope<<M::Requester as Requester>::Context>)
     -> Intent<Parser<M, S>>
 {
-    let (cli, m) = match task {
+    let (cli, m, retries) = match task {
         Task::Close => return Intent::done(),
         Task::Sleep(cli, deadline) => {
-            return ParserImpl::Idle(deadline).intent(cli, scope);
+            return ParserImpl::Idle(deadline).intent(cli, connected, scope);
         }
-        Task::Request(cli, m) => (cli, m)
+        Task::Request(cli, m) => (cli, m, 0),
+        Task::Retry(cli, m, attempt) => (cli, m, attempt),
     };
+    let start = transport.output().len();
     let mut req = Request::new(transport.output());
     match m.prepare_request(&mut req, scope) {
         Some(m) => {
-            let deadline = scope.now() + m.byte_timeout(scope);
-            Intent::of(Parser(cli, ParserImpl::ReadHeaders {
-                    machine: m,
-                    is_head: req.1,
-                    request: state(req),
-                }, PhantomData))
-            .expect_delimiter(b"\r\n\r\n", MAX_HEADERS_SIZE)
-            .deadline(deadline)
+            let is_head = req.1;
+            let request = state(req);
+            m.on_request_sent(&transport.output()[start..], scope);
+            read_headers(cli, connected, m, request, is_head,
+                Attempts { redirects: 0, retries: retries }, scope)
         }
         None => unimplemented!(),
     }
 }
 
+fn follow_redirect<M: Client, S: StreamSocket>(
+    transport: &mut Transport<S>, cli: M, connected: Time,
+    r: Redirect<M::Requester>,
+    scope: &mut Scope<<M::Requester as Requester>::Context>)
+    -> Intent<Parser<M, S>>
+{
+    let start = transport.output().len();
+    let mut req = Request::new(transport.output());
+    let m = match r.target {
+        Some((method, location)) => {
+            r.machine.prepare_redirect(method, &location, &mut req, scope)
+        }
+        None => r.machine.prepare_request(&mut req, scope),
+    };
+    match m {
+        Some(m) => {
+            let is_head = req.1;
+            let request = state(req);
+            m.on_request_sent(&transport.output()[start..], scope);
+            read_headers(cli, connected, m, request, is_head, r.attempts,
+                scope)
+        }
+        // The request may be partially written, so we can't reuse
+        // the connection
+        None => Intent::done(),
+    }
+}
+
+fn read_headers<M: Client, S: StreamSocket>(cli: M, connected: Time,
+    m: M::Requester, request: MessageState, is_head: Option<bool>,
+    attempts: Attempts,
+    scope: &mut Scope<<M::Requester as Requester>::Context>)
+    -> Intent<Parser<M, S>>
+{
+    let deadline = scope.now() + m.header_timeout(scope);
+    Intent::of(Parser(cli, ParserImpl::ReadHeaders {
+            machine: m,
+            is_head: is_head,
+            request: request,
+            attempts: attempts,
+            sent: scope.now(),
+        }, connected, PhantomData))
+    .expect_delimiter(b"\r\n\r\n", MAX_HEADERS_SIZE)
+    .deadline(deadline)
+}
+
 impl<M, S> Protocol for Parser<M, S>
     where M: Client, S: StreamSocket
 {
@@ -333,7 +597,9 @@ impl<M, S> Protocol for Parser<M, S>
     {
         let cli = M::create(seed, scope);
         let deadline = scope.now() + cli.connect_timeout(scope);
-        ParserImpl::Connecting(deadline).intent(cli, scope)
+        // The connection time is updated when it's established
+        let now = scope.now();
+        ParserImpl::Connecting(deadline).intent(cli, now, scope)
     }
     fn bytes_read(self, transport: &mut Transport<Self::Socket>,
         end: usize, scope: &mut Scope<Self::Context>)
@@ -342,29 +608,50 @@ impl<M, S> Protocol for Parser<M, S>
         use self::ParserImpl::*;
         use self::BodyProgress::*;
         use super::ResponseError::*;
+        use super::ProtocolError::UnexpectedData;
         match self.1 {
-            ReadHeaders { machine, request, is_head } => {
+            ReadHeaders { machine, request, is_head, attempts, sent } => {
+                let limits = Attempts {
+                    redirects: self.0.max_redirects(scope),
+                    retries: self.0.max_retries(scope),
+                };
                 let (inb, outb) = transport.buffers();
                 let is_head = is_head.unwrap();
                 let hdr = parse_headers(inb, end, machine,
-                    request.with(outb), is_head, scope);
+                    request.with(outb), is_head, attempts, limits,
+                    self.2, sent, scope);
                 match hdr {
-                    Ok(me) => me.intent(self.0, scope),
+                    Ok(me) => me.intent(self.0, self.2, scope),
                     Err(()) => Intent::done(), // Close the connection
                 }
             }
-            Response { progress, machine, deadline, request }  => {
+            Response { progress, machine, deadline, request, mut discard,
+                       redirect, timings } => {
                 use httparse::Status::*;
                 let (inp, out) = transport.buffers();
                 let mut req = request.with(out);
+                let discarding = machine.is_none();
                 let (m, progress) = match progress {
                     BufferFixed(x) => {
-                        machine.response_received(
-                                  &inp[..x], &mut req, scope);
+                        report_timings(&machine, timings, scope);
+                        machine.map(|m| m.response_received(
+                                  &inp[..x], &mut req, scope));
                         inp.consume(x);
-                        return Parser::finish(self.0, req, scope);
+                        if inp.len() > 0 {
+                            // e.g. a body after the response to HEAD
+                            self.0.connection_error(&UnexpectedData, scope);
+                            return Intent::done();
+                        }
+                        return Parser::finish(self.0, self.2, req, redirect,
+                            scope);
+                    }
+                    BufferEOF(limit) => {
+                        // The expectation is one byte over the limit, so
+                        // reaching here means the body is too long
+                        machine.map(|m| m.bad_response(
+                            &ResponseError::BodyTooLong(limit), scope));
+                        return Intent::done();
                     }
-                    BufferEOF(_) => unreachable!(),
                     BufferChunked(limit, off, 0) => {
                         let lenstart = consumed(off);
                         match parse_chunk_size(
@@ -372,55 +659,69 @@ impl<M, S> Protocol for Parser<M, S>
                         {
                             Ok(Complete((_, 0))) => {
                                 inp.remove_range(off..lenstart + end + 2);
-                                machine.response_received(
-                                    &inp[..off], &mut req, scope);
+                                report_timings(&machine, timings, scope);
+                                machine.map(|m| m.response_received(
+                                    &inp[..off], &mut req, scope));
                                 inp.consume(off);
-                                return Parser::finish(self.0, req, scope);
+                                return Parser::finish(self.0, self.2, req,
+                                    redirect, scope);
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 if off as u64 + chunk_len > limit as u64 {
                                     inp.consume(end+2);
-                                    machine.bad_response(
+                                    machine.map(|m| m.bad_response(
                                         &ChunkIsTooLarge(
                                             off as u64 + chunk_len, limit),
-                                        scope);
+                                        scope));
                                     return Intent::done();
                                 }
                                 inp.remove_range(off..lenstart + end + 2);
-                                (Some(machine),
+                                (machine,
                                  BufferChunked(limit, off, chunk_len as usize))
                             }
                             Ok(Partial) => unreachable!(),
                             Err(e) => {
                                 inp.consume(end+2);
-                                machine.bad_response(&ResponseError::from(e),
-                                                     scope);
+                                machine.map(|m| m.bad_response(
+                                    &ResponseError::from(e), scope));
                                 return Intent::done();
                             }
                         }
                     }
                     BufferChunked(limit, off, bytes) => {
                         debug_assert_eq!(off + bytes, end - 2);
-                        (Some(machine),
+                        (machine,
                          BufferChunked(limit, off + bytes, 0))
                     }
                     ProgressiveFixed(hint, mut left) => {
                         let real_bytes = min(inp.len() as u64, left) as usize;
-                        let m = machine.response_chunk(
-                                    &inp[..real_bytes], &mut req, scope);
+                        let m = match deliver(machine, &inp[..real_bytes],
+                                              &mut req, &mut discard, scope)
+                        {
+                            Ok(m) => m,
+                            Err(()) => return Intent::done(),
+                        };
                         inp.consume(real_bytes);
                         left -= real_bytes as u64;
                         if left == 0 {
+                            report_timings(&m, timings, scope);
                             m.map(|x| x.response_end(&mut req, scope));
-                            return Parser::finish(self.0, req, scope);
+                            if inp.len() > 0 {
+                                self.0.connection_error(&UnexpectedData,
+                                                        scope);
+                                return Intent::done();
+                            }
+                            return Parser::finish(self.0, self.2, req,
+                                redirect, scope);
                         } else {
                             (m, ProgressiveFixed(hint, left))
                         }
                     }
                     ProgressiveEOF(hint) => {
                         let ln = inp.len();
-                        let m = machine.response_chunk(
-                                    &inp[..ln], &mut req, scope);
+                        let m = machine.and_then(|m| m.response_chunk(
+                                    &inp[..ln], &mut req, scope));
+                        inp.consume(ln);
                         (m, ProgressiveEOF(hint))
                     }
                     ProgressiveChunked(hint, off, 0) => {
@@ -428,22 +729,28 @@ impl<M, S> Protocol for Parser<M, S>
                         match parse_chunk_size(&inp[off..off + end + 2]) {
                             Ok(Complete((_, 0))) => {
                                 inp.remove_range(off..off+end+2);
-                                let m = machine.response_chunk(
-                                    &inp[..off], &mut req, scope);
+                                let m = match deliver(machine, &inp[..off],
+                                    &mut req, &mut discard, scope)
+                                {
+                                    Ok(m) => m,
+                                    Err(()) => return Intent::done(),
+                                };
+                                report_timings(&m, timings, scope);
                                 m.map(|m| m.response_end(&mut req, scope));
                                 inp.consume(off);
-                                return Parser::finish(self.0, req, scope);
+                                return Parser::finish(self.0, self.2, req,
+                                    redirect, scope);
                             }
                             Ok(Complete((_, chunk_len))) => {
                                 inp.remove_range(off..off+end+2);
-                                (Some(machine),
+                                (machine,
                                  ProgressiveChunked(hint, off, chunk_len))
                             }
                             Ok(Partial) => unreachable!(),
                             Err(e) => {
                                 inp.consume(off + end + 2);
-                                machine.bad_response(&ResponseError::from(e),
-                                                     scope);
+                                machine.map(|m| m.bad_response(
+                                    &ResponseError::from(e), scope));
                                 return Intent::done();
                             }
                         }
@@ -460,34 +767,54 @@ impl<M, S> Protocol for Parser<M, S>
                         };
                         left -= (ln - off) as u64;
                         if ln < hint {
-                            (Some(machine),
+                            (machine,
                              ProgressiveChunked(hint, ln, left))
                         } else {
-                            let m = machine.response_chunk(&inp[..ln],
-                                                &mut req, scope);
+                            let m = match deliver(machine, &inp[..ln],
+                                &mut req, &mut discard, scope)
+                            {
+                                Ok(m) => m,
+                                Err(()) => return Intent::done(),
+                            };
                             inp.consume(ln);
                             (m, ProgressiveChunked(hint, 0, left))
                         }
                     }
                 };
-                match m {
-                    None => {
-                        unimplemented!();
-                    }
-                    Some(m) => {
-                        Response {
-                            machine: m,
-                            deadline: deadline,
-                            progress: progress,
-                            request: state(req),
-                        }.intent(self.0, scope)
-                    }
-                }
+                let m = match m {
+                    Some(m) => Some(m),
+                    None if discarding => None,
+                    // Requester is not interested in the rest of the body
+                    None => match (discard_limit(&req), &progress) {
+                        (None, _) | (_, &ProgressiveEOF(..)) => {
+                            return Intent::done();
+                        }
+                        (Some(limit), _) => {
+                            discard = limit;
+                            None
+                        }
+                    },
+                };
+                Response {
+                    machine: m,
+                    deadline: deadline,
+                    progress: progress,
+                    request: state(req),
+                    discard: discard,
+                    redirect: redirect,
+                    timings: timings,
+                }.intent(self.0, self.2, scope)
             }
             // TODO(tailhook) turn this into some error, or log it?
             Idle(..) => Intent::done(),
             Connecting(..) => unreachable!(),
             Flushing(..) => unreachable!(),
+            // Nothing is expected from the server before the retry
+            Redirecting(_, r) => {
+                r.machine.bad_response(
+                    &ResponseError::Connection(UnexpectedData), scope);
+                Intent::done()
+            }
         }
     }
     fn bytes_flushed(self, transport: &mut Transport<Self::Socket>,
@@ -496,11 +823,21 @@ impl<M, S> Protocol for Parser<M, S>
     {
         use self::ParserImpl::*;
         match self.1 {
-            Connecting(..) | Flushing(..) => {
+            Connecting(..) => {
+                let connected = scope.now();
                 maybe_new_request(transport,
                     self.0.connection_idle(&Connection {
                         idle: true,
-                    }, scope), scope)
+                    }, scope), connected, scope)
+            }
+            Flushing(..) => {
+                maybe_new_request(transport,
+                    self.0.connection_idle(&Connection {
+                        idle: true,
+                    }, scope), self.2, scope)
+            }
+            Redirecting(_, r) => {
+                follow_redirect(transport, self.0, self.2, r, scope)
             }
             Idle(..) => unreachable!(),
             ReadHeaders {..} => unreachable!(),
@@ -509,23 +846,75 @@ impl<M, S> Protocol for Parser<M, S>
             }
         }
     }
-    fn exception(self, _transport: &mut Transport<Self::Socket>,
+    fn exception(self, transport: &mut Transport<Self::Socket>,
         reason: Exception, scope: &mut Scope<Self::Context>)
         -> Intent<Self>
     {
         use self::ParserImpl::*;
-        let mut reason = reason.into();
-        match self.1 {
-            ReadHeaders { machine, .. } | Response { machine, .. } => {
-                let err = ResponseError::Connection(reason);
-                machine.bad_response(&err, scope);
-                reason = if let ResponseError::Connection(r) = err {
-                    r
+        use self::BodyProgress::*;
+        let (state, reason) = match (self.1, reason) {
+            // The body delimited by the end of stream is complete
+            (Response { progress: BufferEOF(_), machine, request, timings,
+                        .. },
+             Exception::EndOfStream) => {
+                let (inp, out) = transport.buffers();
+                let mut req = request.with(out);
+                report_timings(&machine, timings, scope);
+                machine.map(|m| m.response_received(
+                    &inp[..], &mut req, scope));
+                let ln = inp.len();
+                inp.consume(ln);
+                return Intent::done();
+            }
+            (Response { progress: ProgressiveEOF(_), machine, request,
+                        timings, .. },
+             Exception::EndOfStream) => {
+                let (inp, out) = transport.buffers();
+                let mut req = request.with(out);
+                let ln = inp.len();
+                let m = if ln > 0 {
+                    machine.and_then(|m| m.response_chunk(
+                        &inp[..ln], &mut req, scope))
                 } else {
-                    unreachable!();
+                    machine
+                };
+                inp.consume(ln);
+                report_timings(&m, timings, scope);
+                m.map(|m| m.response_end(&mut req, scope));
+                return Intent::done();
+            }
+            other => other,
+        };
+        let mut reason = reason.into();
+        // The request may be retried if the response isn't started yet
+        let (machine, attempts) = match state {
+            ReadHeaders { machine, attempts, .. }
+            | Redirecting(_, Redirect { machine, target: None,
+                                        attempts, .. }) => {
+                (Some(machine), Some(attempts))
+            }
+            Response { machine: Some(machine), .. }
+            | Response { redirect: Some(Redirect { machine, .. }), .. }
+            | Redirecting(_, Redirect { machine, .. }) => {
+                (Some(machine), None)
+            }
+            _ => (None, None),
+        };
+        if let Some(machine) = machine {
+            let err = ResponseError::Connection(reason);
+            match attempts {
+                Some(a) if a.retries < self.0.max_retries(scope) &&
+                           machine.is_idempotent()
+                => {
+                    self.0.retry_request(machine, a.retries + 1, &err, scope);
                 }
+                _ => machine.bad_response(&err, scope),
+            }
+            reason = if let ResponseError::Connection(r) = err {
+                r
+            } else {
+                unreachable!();
             }
-            _ => {}
         }
         self.0.connection_error(&reason, scope);
         Intent::done()
@@ -548,7 +937,52 @@ impl<M, S> Protocol for Parser<M, S>
                 maybe_new_request(transport,
                     self.0.timeout(&Connection {
                         idle: true,
-                    }, scope), scope)
+                    }, scope), self.2, scope)
+            }
+            ReadHeaders { machine, .. } => {
+                machine.bad_response(&ResponseError::HeadersTimeout, scope);
+                Intent::done()
+            }
+            Response { progress, machine: Some(machine), deadline, request,
+                       discard, redirect, timings } => {
+                if scope.now() >= deadline {
+                    let mut req = request.with(transport.output());
+                    match machine.timeout(&mut req, scope) {
+                        Some((m, deadline)) => {
+                            Response {
+                                machine: Some(m),
+                                deadline: deadline,
+                                progress: progress,
+                                request: state(req),
+                                discard: discard,
+                                redirect: redirect,
+                                timings: timings,
+                            }.intent(self.0, self.2, scope)
+                        }
+                        None => Intent::done(),
+                    }
+                } else {
+                    // Body timeout is shorter than the whole deadline
+                    machine.bad_response(&ResponseError::BodyTimeout, scope);
+                    Intent::done()
+                }
+            }
+            // Body is not discarded in time
+            Response { redirect: Some(r), .. } => {
+                r.machine.bad_response(&ResponseError::BodyTimeout, scope);
+                Intent::done()
+            }
+            Response { machine: None, .. } => Intent::done(),
+            Redirecting(_, r) => {
+                if r.target.is_none() {
+                    // The delay before the retry is over
+                    follow_redirect(transport, self.0, self.2, r, scope)
+                } else {
+                    // The previous request is not flushed in time
+                    r.machine.bad_response(&ResponseError::RequestTimeout,
+                        scope);
+                    Intent::done()
+                }
             }
             _ => {
                 unimplemented!();
@@ -562,15 +996,16 @@ impl<M, S> Protocol for Parser<M, S>
         use self::ParserImpl::*;
         match self.1 {
             // skip the event, will child state machine when connected
-            me@Connecting(..) => me.intent(self.0, scope),
+            me@Connecting(..) => me.intent(self.0, self.2, scope),
             // skip the event, will child state machine when connected
-            me@Flushing(..) => me.intent(self.0, scope),
+            me@Flushing(..) => me.intent(self.0, self.2, scope),
+            me@Redirecting(..) => me.intent(self.0, self.2, scope),
             Idle(..) => {
                 // TODO(tailhook) propagate same idle deadline
                 maybe_new_request(transport,
                     self.0.wakeup(&Connection {
                         idle: true,
-                    }, scope), scope)
+                    }, scope), self.2, scope)
             }
             _ => {
                 unimplemented!();
@@ -586,21 +1021,38 @@ mod test {
     use rotor_test::{MemIo, MockLoop};
     use client::{Client, Requester, Connection, Task, Request, Version};
     use client::{Head, RecvMode, Fsm, ResponseError, ProtocolError};
+    use client::{Method, Timings};
 
     #[derive(Debug, Default, PartialEq, Eq)]
     struct Context {
         progressive: bool,
+        /// Overrides `progressive` if set
+        mode: Option<RecvMode>,
+        head: bool,
         requests: usize,
         headers_received: usize,
         responses_received: usize,
         chunks_received: usize,
         bytes_received: usize,
         errors: usize,
+        header_timeout: Option<Duration>,
+        body_timeout: Option<Duration>,
+        sent: Option<Vec<u8>>,
+        discard: bool,
+        conditional: bool,
+        not_modified: usize,
+        follow_redirects: bool,
+        redirects: usize,
+        interim: usize,
+        max_retries: usize,
+        retried: Option<(Req, usize)>,
+        record_timings: bool,
+        timings: Option<Timings>,
     }
 
     #[derive(Debug)]
     struct Cli(usize);
-    #[derive(Debug)]
+    #[derive(Debug, PartialEq, Eq)]
     struct Req;
 
     impl Client for Cli {
@@ -616,7 +1068,9 @@ mod test {
             scope: &mut Scope<Context>)
             -> Task<Cli>
         {
-            if self.0 > 0 {
+            if let Some((req, attempt)) = scope.retried.take() {
+                Task::Retry(self, req, attempt)
+            } else if self.0 > 0 {
                 self.0 -= 1;
                 Task::Request(self, Req)
             } else {
@@ -642,6 +1096,14 @@ mod test {
         {
             unimplemented!();
         }
+        fn max_retries(&self, scope: &mut Scope<Context>) -> usize {
+            scope.max_retries
+        }
+        fn retry_request(&self, request: Req, attempt: usize,
+            _error: &ResponseError, scope: &mut Scope<Context>)
+        {
+            scope.retried = Some((request, attempt));
+        }
     }
 
     impl Requester for Req {
@@ -651,18 +1113,61 @@ mod test {
             -> Option<Self>
         {
             scope.requests += 1;
-            req.start("GET", "/", Version::Http11);
+            let method = if scope.head { "HEAD" } else { "GET" };
+            req.start(method, "/", Version::Http11);
             req.add_header("Host", b"localhost").unwrap();
+            if scope.conditional {
+                req.add_conditional(Some("\"v1\""), None).unwrap();
+            }
             req.done_headers().unwrap();
             req.done();
             Some(self)
         }
-        fn headers_received(self, _head: Head, _request: &mut Request,
+        fn informational(self, _head: Head, scope: &mut Scope<Self::Context>)
+            -> Option<Self>
+        {
+            scope.interim += 1;
+            Some(self)
+        }
+        fn is_idempotent(&self) -> bool {
+            true
+        }
+        fn on_redirect(&self, head: &Head, scope: &mut Scope<Self::Context>)
+            -> Option<(Method<'static>, String)>
+        {
+            if !scope.follow_redirects {
+                return None;
+            }
+            let location = head.get("Location").unwrap();
+            Some((Method::Post,
+                  String::from_utf8(location.to_vec()).unwrap()))
+        }
+        fn prepare_redirect(self, method: Method, location: &str,
+            req: &mut Request, scope: &mut Scope<Self::Context>)
+            -> Option<Self>
+        {
+            scope.redirects += 1;
+            req.start(method.as_str(), location, Version::Http11);
+            req.add_header("Host", b"localhost").unwrap();
+            req.done_headers().unwrap();
+            req.done();
+            Some(self)
+        }
+        fn headers_received(self, head: Head, request: &mut Request,
             scope: &mut Scope<Self::Context>)
             -> Option<(Self, RecvMode, Time)>
         {
             scope.headers_received += 1;
-            if scope.progressive {
+            if head.code == 304 {
+                scope.not_modified += 1;
+            }
+            if scope.discard {
+                request.discard_body(10);
+                return None;
+            }
+            if let Some(mode) = scope.mode {
+                Some((Req, mode, scope.now() + Duration::new(10, 0)))
+            } else if scope.progressive {
                 Some((Req, RecvMode::Progressive(1000),
                     scope.now() + Duration::new(10, 0)))
             } else {
@@ -706,6 +1211,30 @@ mod test {
         {
             scope.errors += 1;
         }
+        fn on_request_sent(&self, bytes: &[u8],
+            scope: &mut Scope<Self::Context>)
+        {
+            if let Some(ref mut sent) = scope.sent {
+                sent.extend(bytes);
+            }
+        }
+        fn response_timings(&self, timings: &Timings,
+            scope: &mut Scope<Self::Context>)
+        {
+            if scope.record_timings {
+                scope.timings = Some(*timings);
+            }
+        }
+        fn header_timeout(&self, scope: &mut Scope<Self::Context>)
+            -> Duration
+        {
+            scope.header_timeout.unwrap_or(Duration::new(120, 0))
+        }
+        fn body_timeout(&self, scope: &mut Scope<Self::Context>)
+            -> Duration
+        {
+            scope.body_timeout.unwrap_or(Duration::new(120, 0))
+        }
     }
 
     #[test]
@@ -726,6 +1255,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
     }
 
@@ -747,6 +1277,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
         io.push_bytes("0\r\n\r\n".as_bytes());
         m.ready(EventSet::readable(), &mut lp.scope(1))
@@ -759,6 +1290,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
     }
 
@@ -780,6 +1312,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
         io.push_bytes("5\r\nrotor\r\n0\r\n\r\n".as_bytes());
         m.ready(EventSet::readable(), &mut lp.scope(1))
@@ -792,6 +1325,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 5,
             errors: 0,
+            ..Default::default()
         });
     }
 
@@ -813,6 +1347,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
         io.push_bytes("4\r\n\
                        Wiki\r\n\
@@ -833,6 +1368,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 23,
             errors: 0,
+            ..Default::default()
         });
     }
 
@@ -855,6 +1391,7 @@ mod test {
             chunks_received: 0,
             bytes_received: 0,
             errors: 0,
+            ..Default::default()
         });
         io.push_bytes("4\r\n\
                        Wiki\r\n\
@@ -875,6 +1412,491 @@ mod test {
             chunks_received: 1,
             bytes_received: 23,
             errors: 0,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_header_timeout() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            header_timeout: Some(Duration::new(0, 0)),
+            ..Default::default()
+        });
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::writable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().requests, 1);
+        assert_eq!(lp.ctx().errors, 0);
+        m.timeout(&mut lp.scope(1));
+        assert_eq!(lp.ctx().headers_received, 0);
+        assert_eq!(lp.ctx().errors, 1);
+    }
+
+    #[test]
+    fn test_body_timeout() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            body_timeout: Some(Duration::new(0, 0)),
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n\
+                       rotor".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().headers_received, 1);
+        assert_eq!(lp.ctx().errors, 0);
+        m.timeout(&mut lp.scope(1));
+        assert_eq!(lp.ctx().responses_received, 0);
+        assert_eq!(lp.ctx().errors, 1);
+    }
+
+    #[test]
+    fn test_request_sent() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            sent: Some(Vec::new()),
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().sent.as_ref().map(|x| &x[..]),
+            Some(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"[..]));
+    }
+
+    #[test]
+    fn test_connection_reuse() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            sent: Some(Vec::new()),
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 2, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // The second request is sent on the same socket as soon as
+        // the first response is read
+        assert_eq!(lp.ctx().requests, 2);
+        assert_eq!(lp.ctx().responses_received, 1);
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nworld"
+                      .as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().requests, 2);
+        assert_eq!(lp.ctx().headers_received, 2);
+        assert_eq!(lp.ctx().responses_received, 2);
+        assert_eq!(lp.ctx().bytes_received, 10);
+        assert_eq!(lp.ctx().errors, 0);
+        assert_eq!(lp.ctx().sent.as_ref().map(|x| &x[..]),
+            Some(&concat!("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                          "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                 .as_bytes()[..]));
+    }
+
+    #[test]
+    fn test_redirect() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            sent: Some(Vec::new()),
+            follow_redirects: true,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 303 See Other\r\nLocation: /new\r\n\
+                       Content-Length: 5\r\n\r\nmoved".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // 303 is followed with GET, and the body is not delivered
+        assert_eq!(lp.ctx().redirects, 1);
+        assert_eq!(lp.ctx().headers_received, 0);
+        assert_eq!(lp.ctx().sent.as_ref().map(|x| &x[..]),
+            Some(&concat!("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                          "GET /new HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                 .as_bytes()[..]));
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                      .as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().requests, 1);
+        assert_eq!(lp.ctx().headers_received, 1);
+        assert_eq!(lp.ctx().responses_received, 1);
+        assert_eq!(lp.ctx().bytes_received, 2);
+        assert_eq!(lp.ctx().errors, 0);
+    }
+
+    #[test]
+    fn test_redirect_not_followed() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            follow_redirects: true,
+            ..Default::default()
+        });
+        // can't follow as the connection is closed after the response
+        io.push_bytes("HTTP/1.1 302 Found\r\nLocation: /new\r\n\
+                       Content-Length: 0\r\nConnection: close\r\n\r\n"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().redirects, 0);
+        assert_eq!(lp.ctx().headers_received, 1);
+        assert_eq!(lp.ctx().responses_received, 1);
+    }
+
+    #[test]
+    fn test_continue() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("HTTP/1.1 100 Continue\r\n\r\n".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().interim, 1);
+        assert_eq!(lp.ctx().headers_received, 0);
+        io.push_bytes("HTTP/1.1 103 Early Hints\r\nLink: </style.css>\r\n\r\n\
+                       HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+                      .as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            interim: 2,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 5,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            sent: Some(Vec::new()),
+            conditional: true,
+            ..Default::default()
+        });
+        // no body even though there is no Content-Length
+        io.push_bytes("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().sent.as_ref().map(|x| &x[..]),
+            Some(&b"GET / HTTP/1.1\r\nHost: localhost\r\n\
+                    If-None-Match: \"v1\"\r\n\r\n"[..]));
+        assert_eq!(lp.ctx().headers_received, 1);
+        assert_eq!(lp.ctx().not_modified, 1);
+        assert_eq!(lp.ctx().responses_received, 1);
+        assert_eq!(lp.ctx().bytes_received, 0);
+        assert_eq!(lp.ctx().errors, 0);
+    }
+
+    #[test]
+    fn test_discard_body() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            discard: true,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 3, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        io.push_bytes("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       5\r\nhello\r\n0\r\n\r\n".as_bytes());
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            discard: true,
+            requests: 3,
+            headers_received: 2,
+            ..Default::default()
+        });
+        // too large to discard, so the connection is closed
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n"
+                      .as_bytes());
+        assert!(m.ready(EventSet::readable(), &mut lp.scope(1)).is_stopped());
+        assert_eq!(lp.ctx().headers_received, 3);
+    }
+
+    #[test]
+    fn test_body_until_eof() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("HTTP/1.0 200 OK\r\nConnection: close\r\n\r\n\
+                       hello".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().headers_received, 1);
+        assert_eq!(lp.ctx().responses_received, 0);
+        io.push_bytes(" world".as_bytes());
+        io.shutdown_input();
+        assert!(m.ready(EventSet::readable(), &mut lp.scope(1)).is_stopped());
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 11,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_premature_eof() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n\
+                       hello".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        io.shutdown_input();
+        assert!(m.ready(EventSet::readable(), &mut lp.scope(1)).is_stopped());
+        // both `bad_response` and `connection_error` are called
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            errors: 2,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_retry_unavailable() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            max_retries: 1,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 503 Service Unavailable\r\n\
+                       Content-Length: 4\r\n\r\nbusy".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // the response is skipped, and request is sent after a delay
+        assert_eq!(lp.ctx().requests, 1);
+        assert_eq!(lp.ctx().headers_received, 0);
+        let m = m.timeout(&mut lp.scope(1)).expect_machine();
+        assert_eq!(lp.ctx().requests, 2);
+        io.push_bytes("HTTP/1.1 503 Service Unavailable\r\n\
+                       Content-Length: 4\r\n\r\nbusy".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // retries are exhausted, so the response is delivered
+        assert_eq!(*lp.ctx(), Context {
+            max_retries: 1,
+            requests: 2,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 4,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            max_retries: 1,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 429 Too Many Requests\r\n\
+                       Retry-After: 0\r\nContent-Length: 0\r\n\r\n"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().headers_received, 0);
+        m.timeout(&mut lp.scope(1)).expect_machine();
+        assert_eq!(lp.ctx().requests, 2);
+    }
+
+    #[test]
+    fn test_retry_connection_closed() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            max_retries: 2,
+            ..Default::default()
+        });
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::writable(), &mut lp.scope(1))
+            .expect_machine();
+        io.shutdown_input();
+        assert!(m.ready(EventSet::readable(), &mut lp.scope(1)).is_stopped());
+        // only `connection_error` is called, the request is kept for retry
+        assert_eq!(lp.ctx().errors, 1);
+        assert_eq!(lp.ctx().retried, Some((Req, 1)));
+
+        let mut io = MemIo::new();
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 0, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            max_retries: 2,
+            requests: 2,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 2,
+            errors: 1,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            record_timings: true,
+            progressive: true,
+            ..Default::default()
+        });
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::writable(), &mut lp.scope(1))
+            .expect_machine();
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n\
+                       hello".as_bytes());
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // not reported until the response is complete
+        assert_eq!(lp.ctx().timings, None);
+        io.push_bytes("world".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().responses_received, 1);
+        let t = lp.ctx().timings.expect("timings reported");
+        assert!(t.connected <= t.request_sent);
+        assert!(t.request_sent <= t.headers_received);
+        assert!(t.headers_received <= t.response_complete);
+    }
+
+    #[test]
+    fn test_head() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            head: true,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n"
+                      .as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            head: true,
+            requests: 1,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 0,
+            errors: 0,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_obs_fold() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context::default());
+        io.push_bytes("HTTP/1.1 200 OK\r\nX-Note: folded\r\n value\r\n\
+                       Content-Length: 2\r\n\r\nok".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            requests: 1,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 2,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_bad_content_length() {
+        for value in &["+2", "-2", "0x2", "2 2"] {
+            let mut io = MemIo::new();
+            let mut lp = MockLoop::new(Context::default());
+            io.push_bytes(format!("HTTP/1.1 200 OK\r\n\
+                                   Content-Length: {}\r\n\r\nok", value)
+                          .as_bytes());
+            let m = Fsm::<Cli, MemIo>::connected(
+                io.clone(), 1, &mut lp.scope(1)).expect_machine();
+            assert!(m.ready(EventSet::readable(), &mut lp.scope(1))
+                .is_stopped());
+            assert_eq!(lp.ctx().headers_received, 0);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_mode() {
+        for mode in &[RecvMode::Frames(4, 10), RecvMode::Upgrade] {
+            let mut io = MemIo::new();
+            let mut lp = MockLoop::new(Context {
+                mode: Some(*mode),
+                ..Default::default()
+            });
+            io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                          .as_bytes());
+            let m = Fsm::<Cli, MemIo>::connected(
+                io.clone(), 1, &mut lp.scope(1)).expect_machine();
+            assert!(m.ready(EventSet::readable(), &mut lp.scope(1))
+                .is_stopped());
+            assert_eq!(lp.ctx().headers_received, 1);
+            assert_eq!(lp.ctx().responses_received, 0);
+            // `bad_response` is called
+            assert!(lp.ctx().errors >= 1);
+        }
+    }
+
+    #[test]
+    fn test_head_with_body() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context {
+            head: true,
+            ..Default::default()
+        });
+        io.push_bytes("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+                       rotor".as_bytes());
+        let m = Fsm::<Cli, MemIo>::connected(
+            io.clone(), 1, &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // response is delivered but connection is closed
+        assert_eq!(*lp.ctx(), Context {
+            head: true,
+            requests: 1,
+            headers_received: 1,
+            responses_received: 1,
+            bytes_received: 0,
+            errors: 1,
+            ..Default::default()
         });
     }
 }
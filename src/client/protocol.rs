@@ -1,14 +1,19 @@
+use std::cmp::min;
 use std::time::Duration;
 
 use rotor::{Scope, Time};
 
 use recvmode::RecvMode;
+use server::Method;
 use super::{Head, Request, ResponseError, ProtocolError};
-use super::{Connection};
+use super::{Connection, Timings};
 
 pub enum Task<M: Client> {
     Sleep(M, Time),
     Request(M, M::Requester),
+    /// Sends the request passed to `Client::retry_request` again, the
+    /// number is the `attempt` passed there
+    Retry(M, M::Requester, usize),
     Close,
 }
 
@@ -79,6 +84,60 @@ pub trait Client: Sized {
     {
         Duration::new(120, 0)
     }
+    /// Maximum number of redirects followed for a single request
+    ///
+    /// See `Requester::on_redirect`. When the limit is reached the redirect
+    /// response is passed to `headers_received` as usual.
+    fn max_redirects(&self,
+        _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+        -> usize
+    {
+        5
+    }
+    /// Maximum number of retries of an idempotent request
+    ///
+    /// Requests for which `Requester::is_idempotent` returns `true` are
+    /// sent again if the connection fails before the response is
+    /// received, or if the response is `503 Service Unavailable` or
+    /// `429 Too Many Requests`. Default is zero, i.e. requests are never
+    /// retried.
+    fn max_retries(&self,
+        _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+        -> usize
+    {
+        0
+    }
+    /// Delay before the retry number `attempt` (starting from one)
+    ///
+    /// When the `503` or `429` response has a valid `Retry-After` header,
+    /// the delay from the header is used instead (see `Head::retry_after`).
+    ///
+    /// Default is 100 milliseconds doubled for each attempt
+    fn retry_delay(&self, attempt: usize,
+        _scope: &mut Scope<<Self::Requester as Requester>::Context>)
+        -> Duration
+    {
+        Duration::from_millis(100 << min(attempt.saturating_sub(1), 10))
+    }
+    /// Called when the connection fails before the response is received
+    ///
+    /// Only called for idempotent requests, when the number of `attempt`
+    /// is within `max_retries`. The response with `503` or `429` status is
+    /// retried on the same connection instead, after the `retry_delay`.
+    ///
+    /// The connection is closed after this handler (and
+    /// `connection_error`) is called, so it's your responsibility to wait
+    /// for `retry_delay`, establish a new connection and return
+    /// `Task::Retry` from its `connection_idle` with this `request` and
+    /// `attempt`. The requester may be stored in the context meanwhile.
+    ///
+    /// Default is to call `request.bad_response(error)`, i.e. to give up
+    fn retry_request(&self, request: Self::Requester, _attempt: usize,
+        error: &ResponseError,
+        scope: &mut Scope<<Self::Requester as Requester>::Context>)
+    {
+        request.bad_response(error, scope)
+    }
 }
 
 /// A handler of a single client-side HTTP
@@ -132,6 +191,72 @@ pub trait Requester: Sized {
         scope: &mut Scope<Self::Context>)
         -> Option<(Self, RecvMode, Time)>;
 
+    /// Called for an interim (1xx) response
+    ///
+    /// For example `100 Continue` when the request was sent with
+    /// `Expect: 100-continue`, or `103 Early Hints`. The interim response
+    /// has no body, after the hook the parser waits for the next response
+    /// head (which is passed to `headers_received` as usual), with the
+    /// `header_timeout` restarted. `101 Switching Protocols` is a final
+    /// response and is not passed here.
+    ///
+    /// Return `None` to close the connection. Default is to ignore
+    /// the interim response.
+    fn informational(self, _head: Head, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Returns `true` if the request may be safely sent again
+    ///
+    /// This is the case for `GET`, `HEAD`, `PUT` and `DELETE` requests
+    /// which don't have side effects when repeated. Retries are
+    /// configured by `Client::max_retries`, the `prepare_request` is
+    /// called again to write the retried request. `POST` requests should
+    /// never be retried.
+    ///
+    /// Default is `false`
+    fn is_idempotent(&self) -> bool {
+        false
+    }
+
+    /// Called instead of `headers_received` for a redirect response
+    ///
+    /// The hook is called for `301`, `302`, `303`, `307` and `308` status
+    /// codes. Return the method and the location to follow the redirect:
+    /// the response body is discarded and `prepare_redirect` is called
+    /// to send the new request over the same connection. For `303 See
+    /// Other` the method is replaced by `GET` (unless it's `HEAD`) as
+    /// RFC 7231 requires.
+    ///
+    /// The hook isn't called if the connection can't be reused (i.e. on
+    /// `Connection: close` or a body delimited by the end of stream), or
+    /// after `Client::max_redirects` redirects in a row.
+    ///
+    /// Default is to not follow redirects
+    fn on_redirect(&self, _head: &Head, _scope: &mut Scope<Self::Context>)
+        -> Option<(Method<'static>, String)>
+    {
+        None
+    }
+
+    /// Populates a request following the redirect
+    ///
+    /// Similarly to `prepare_request` the whole request must be written
+    /// here, the `method` and the `location` returned by `on_redirect` are
+    /// passed as a hint. The location is passed as is, so if it points to
+    /// a different host, return `None` and initiate the request over a new
+    /// connection. The current connection is closed in this case.
+    ///
+    /// Default returns `None`
+    fn prepare_redirect(self, _method: Method, _location: &str,
+        _req: &mut Request, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        None
+    }
+
     /// Called when full response is received in buffered mode
     ///
     /// Note: you can't continue with connection here. But you can finish
@@ -146,6 +271,11 @@ pub trait Requester: Sized {
     /// anything. Note this event doesnt' relate to any HTTP status codes.
     /// They are treated as normal responses by the state machine.
     ///
+    /// Errors of the connection, including the connection closed before
+    /// the response is complete, are passed as `ResponseError::Connection`.
+    /// Closing the connection is not an error if the response has neither
+    /// `Content-Length` nor chunked encoding, as it marks the end of body.
+    ///
     /// It's never called on a timeout.
     fn bad_response(self, _error: &ResponseError,
         _scope: &mut Scope<Self::Context>)
@@ -182,6 +312,31 @@ pub trait Requester: Sized {
     fn wakeup(self, request: &mut Request, scope: &mut Scope<Self::Context>)
         -> Option<Self>;
 
+    /// Called after `prepare_request` with the bytes of the request
+    ///
+    /// The `bytes` is the part of the output buffer written by
+    /// `prepare_request`, i.e. exactly what is going to be sent to the
+    /// server. If the body is sent later (e.g. in `wakeup`) only the part
+    /// written so far is included. This is useful for debugging
+    /// misbehaving servers.
+    ///
+    /// Default does nothing
+    fn on_request_sent(&self, _bytes: &[u8],
+        _scope: &mut Scope<Self::Context>)
+    {}
+
+    /// Called when the response is complete with the timings of request
+    ///
+    /// It's called just before `response_received` or `response_end`, so
+    /// is not called for failed requests and for the requests whose
+    /// response body was discarded. Useful for monitoring latency of the
+    /// upstream server.
+    ///
+    /// Default does nothing
+    fn response_timings(&self, _timings: &Timings,
+        _scope: &mut Scope<Self::Context>)
+    {}
+
     /// Returns number of seconds between any read/write operation to wait
     /// until connection is closed as stalled
     ///
@@ -190,4 +345,29 @@ pub trait Requester: Sized {
     fn byte_timeout(&self, _scope: &mut Scope<Self::Context>) -> Duration {
         Duration::new(120, 0)
     }
+
+    /// Returns number of seconds to wait for response headers
+    ///
+    /// This timeout is used after request is started and until full
+    /// response headers are received, so it's basically a time to the
+    /// first byte of response. When it fires, `bad_response` is called with
+    /// `ResponseError::HeadersTimeout` and connection is closed.
+    ///
+    /// Default is `byte_timeout()`
+    fn header_timeout(&self, scope: &mut Scope<Self::Context>) -> Duration {
+        self.byte_timeout(scope)
+    }
+
+    /// Returns number of seconds between reads of response body to wait
+    /// until connection is closed as stalled
+    ///
+    /// When it fires, `bad_response` is called with
+    /// `ResponseError::BodyTimeout` and connection is closed. Note that
+    /// deadline returned from `headers_received` is tracked separately and
+    /// `timeout()` handler is called when it's reached.
+    ///
+    /// Default is `byte_timeout()`
+    fn body_timeout(&self, scope: &mut Scope<Self::Context>) -> Duration {
+        self.byte_timeout(scope)
+    }
 }
@@ -2,13 +2,14 @@ use rotor_stream::Buf;
 
 use message::{MessageState, Message, HeaderError};
 use version::Version;
+use date::HttpDate;
 
 
-pub struct Request<'a>(Message<'a>, pub Option<bool>);
+pub struct Request<'a>(Message<'a>, pub Option<bool>, Option<u64>);
 
 impl<'a> From<Message<'a>> for Request<'a> {
     fn from(msg: Message) -> Request {
-        Request(msg, None)
+        Request(msg, None, None)
     }
 }
 
@@ -56,6 +57,30 @@ impl<'a> Request<'a> {
     {
         self.0.add_header(name, value)
     }
+    /// Add `If-None-Match` and `If-Modified-Since` headers
+    ///
+    /// Pass the `ETag` (including quotes) and the `Last-Modified` date of
+    /// the cached response, either of them may be omitted. If the resource
+    /// is not changed the server replies with `304 Not Modified` which has
+    /// no body, so `headers_received` can check `head.code == 304` and use
+    /// the cached copy.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called in the wrong state (same as `add_header`).
+    pub fn add_conditional(&mut self, etag: Option<&str>,
+        last_modified: Option<HttpDate>)
+        -> Result<(), HeaderError>
+    {
+        if let Some(etag) = etag {
+            try!(self.0.add_header("If-None-Match", etag.as_bytes()));
+        }
+        if let Some(date) = last_modified {
+            try!(self.0.add_header("If-Modified-Since",
+                                   date.to_string().as_bytes()));
+        }
+        Ok(())
+    }
     /// Add a content length to the message.
     ///
     /// The `Content-Length` header is written to the output buffer immediately.
@@ -84,6 +109,25 @@ impl<'a> Request<'a> {
     {
         self.0.add_chunked()
     }
+    /// Enables streaming gzip compression of the request body
+    ///
+    /// Writes `Content-Encoding: gzip` and `Transfer-Encoding: chunked`
+    /// headers to the output buffer immediately. The data passed to
+    /// `write_body` is compressed incrementally, and the compressed stream
+    /// is finalized in `done()`. Since the compressed length isn't known
+    /// upfront neither `add_length` nor `add_chunked` may be used with it.
+    ///
+    /// Note: the server must support compressed request bodies.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `enable_gzip` is called in the wrong state.
+    #[cfg(feature="gzip")]
+    pub fn enable_gzip(&mut self)
+        -> Result<(), HeaderError>
+    {
+        self.0.enable_gzip()
+    }
     /// Returns true if at least `status()` method has been called
     ///
     /// This is mostly useful to find out whether we can build an error page
@@ -144,8 +188,112 @@ impl<'a> Request<'a> {
     pub fn done(&mut self) {
         self.0.done()
     }
+    /// Writes a complete fixed-size body and finishes the request
+    ///
+    /// This is a shortcut for `add_length`, `done_headers`, `write_body`
+    /// and `done`, so call it after all other headers are added. Note that
+    /// `Content-Length: 0` is sent for the empty `data`, use `empty_body`
+    /// to omit the header (e.g. for `GET` requests).
+    ///
+    /// # Panics
+    ///
+    /// When headers are already finished.
+    pub fn body(&mut self, data: &[u8]) -> Result<(), HeaderError> {
+        try!(self.0.add_length(data.len() as u64));
+        try!(self.0.done_headers());
+        self.0.write_body(data);
+        self.0.done();
+        Ok(())
+    }
+    /// Finishes the request without a body
+    ///
+    /// No body length header is written, which means an empty body for
+    /// requests. Call it after all other headers are added.
+    ///
+    /// # Panics
+    ///
+    /// When headers are already finished.
+    pub fn empty_body(&mut self) -> Result<(), HeaderError> {
+        try!(self.0.done_headers());
+        self.0.done();
+        Ok(())
+    }
+    /// Read and drop the rest of the response body instead of closing
+    ///
+    /// Usually when `headers_received` or `response_chunk` returns `None`
+    /// the connection is closed. If this method was called before, the
+    /// remaining body is read and ignored instead, so the connection can be
+    /// reused for the next request. This is useful for clients which only
+    /// need the status code.
+    ///
+    /// If the body is larger than `limit` bytes, or the body length is
+    /// not known (read until the connection is closed), the connection is
+    /// closed anyway, as it's probably cheaper to open a new one. The whole
+    /// body must be discarded before the `body_timeout()`.
+    pub fn discard_body(&mut self, limit: u64) {
+        self.2 = Some(limit);
+    }
 }
 
 pub fn state(resp: Request) -> MessageState {
     resp.0.state()
 }
+
+pub fn discard_limit(req: &Request) -> Option<u64> {
+    req.2
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use version::Version;
+    use date::HttpDate;
+    use super::Request;
+
+    #[test]
+    fn test_body() {
+        let mut buf = Buf::new();
+        {
+            let mut req = Request::new(&mut buf);
+            req.start("POST", "/items", Version::Http11);
+            req.add_header("Host", b"example.com").unwrap();
+            req.body(b"hello").unwrap();
+            assert!(req.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("POST /items HTTP/1.1\r\n",
+            "Host: example.com\r\n",
+            "Content-Length: 5\r\n\r\n",
+            "hello").as_bytes());
+    }
+
+    #[test]
+    fn test_empty_body() {
+        let mut buf = Buf::new();
+        {
+            let mut req = Request::new(&mut buf);
+            req.start("GET", "/", Version::Http11);
+            req.add_header("Host", b"example.com").unwrap();
+            req.empty_body().unwrap();
+            assert!(req.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("GET / HTTP/1.1\r\n",
+            "Host: example.com\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut buf = Buf::new();
+        {
+            let mut req = Request::new(&mut buf);
+            req.start("GET", "/", Version::Http11);
+            req.add_conditional(Some("\"abc\""),
+                Some("Sun, 06 Nov 1994 08:49:37 GMT".parse::<HttpDate>()
+                     .unwrap())).unwrap();
+            req.empty_body().unwrap();
+        }
+        assert_eq!(&buf[..], concat!("GET / HTTP/1.1\r\n",
+            "If-None-Match: \"abc\"\r\n",
+            "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n")
+            .as_bytes());
+    }
+}
@@ -0,0 +1,26 @@
+use rotor::Time;
+
+
+/// Timestamps of the stages of a single request
+///
+/// Passed to `Requester::response_timings` when the response is complete.
+/// The timestamps are taken from the event loop (`scope.now()`), so they
+/// have the resolution of the loop iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    /// The connection was established
+    ///
+    /// It's the same for all requests sent over a keep-alive connection.
+    pub connected: Time,
+    /// The request was written to the output buffer
+    ///
+    /// For redirected or retried requests it's the time of the last one.
+    pub request_sent: Time,
+    /// The response headers were received
+    ///
+    /// Parser is woken up only when the whole response head is received,
+    /// so this is a close approximation of time to first byte.
+    pub headers_received: Time,
+    /// The whole response body was received
+    pub response_complete: Time,
+}
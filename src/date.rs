@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rotor::Time;
+
+
+const WEEKDAYS: [&'static str; 7] = [
+    "Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&'static str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// A timestamp with one second precision as used in HTTP headers
+///
+/// It's displayed in the preferred IMF-fixdate format, for example
+/// `Sun, 06 Nov 1994 08:49:37 GMT`. Parsing also accepts obsolete RFC 850
+/// and asctime formats as required by RFC 7231. Dates before unix epoch are
+/// not supported.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HttpDate(u64);
+
+impl HttpDate {
+    /// Current time
+    pub fn now() -> HttpDate {
+        HttpDate::from(SystemTime::now())
+    }
+    /// Current time for the event loop time `now`, normally `scope.now()`
+    ///
+    /// The system clock is read at most once a second in each thread,
+    /// until the next second starts the cached value is returned.
+    pub fn cached_now(now: Time) -> HttpDate {
+        CACHED_NOW.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            match *cache {
+                Some((valid_until, date)) if now < valid_until => date,
+                _ => {
+                    let tm = SystemTime::now().duration_since(UNIX_EPOCH)
+                        .unwrap_or(Duration::new(0, 0));
+                    let date = HttpDate(tm.as_secs());
+                    let left = Duration::new(0,
+                        1_000_000_000 - tm.subsec_nanos());
+                    *cache = Some((now + left, date));
+                    date
+                }
+            }
+        })
+    }
+    /// Number of seconds since unix epoch
+    pub fn timestamp(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<SystemTime> for HttpDate {
+    fn from(tm: SystemTime) -> HttpDate {
+        HttpDate(tm.duration_since(UNIX_EPOCH)
+                   .map(|d| d.as_secs()).unwrap_or(0))
+    }
+}
+
+thread_local!(static CACHED_NOW: RefCell<Option<(Time, HttpDate)>>
+    = RefCell::new(None));
+thread_local!(static CACHED_HEADER: RefCell<(u64, Vec<u8>)>
+    = RefCell::new((0, Vec::new())));
+
+/// Writes the `Date` header line with the specified time
+///
+/// The line is formatted at most once a second in each thread.
+pub fn write_header(out: &mut Write, now: HttpDate) {
+    CACHED_HEADER.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.0 != now.0 || cache.1.len() == 0 {
+            cache.1.clear();
+            write!(&mut cache.1, "Date: {}\r\n", now).unwrap();
+            cache.0 = now.0;
+        }
+        out.write_all(&cache.1).unwrap();
+    });
+}
+
+// Converts days since epoch into (year, month, day), the algorithm is
+// taken from http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: u64) -> (u64, usize, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as usize, day)
+}
+
+// Converts (year, month, day) into days since epoch, the inverse of the
+// function above
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153*mp + 2)/5 + day - 1;
+    let doe = yoe * 365 + yoe/4 - yoe/100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Error parsing `HttpDate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidDate;
+
+impl Display for InvalidDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl Error for InvalidDate {
+    fn description(&self) -> &str {
+        "invalid http date"
+    }
+}
+
+fn number(val: &str, min: u64, max: u64) -> Result<u64, InvalidDate> {
+    if val.len() == 0 || !val.bytes().all(|x| x >= b'0' && x <= b'9') {
+        return Err(InvalidDate);
+    }
+    match val.parse() {
+        Ok(x) if x >= min && x <= max => Ok(x),
+        _ => Err(InvalidDate),
+    }
+}
+
+fn month(val: &str) -> Result<u64, InvalidDate> {
+    MONTHS.iter().position(|&m| m == val)
+        .map(|x| x as u64 + 1).ok_or(InvalidDate)
+}
+
+// Parses "08:49:37" into seconds since midnight
+fn time(val: &str) -> Result<u64, InvalidDate> {
+    let mut parts = val.split(':');
+    let (h, m, s) = match (parts.next(), parts.next(), parts.next(),
+                           parts.next())
+    {
+        (Some(h), Some(m), Some(s), None) => (h, m, s),
+        _ => return Err(InvalidDate),
+    };
+    if h.len() != 2 || m.len() != 2 || s.len() != 2 {
+        return Err(InvalidDate);
+    }
+    // allow leap second
+    Ok(try!(number(h, 0, 23))*3600 + try!(number(m, 0, 59))*60 +
+       try!(number(s, 0, 60)))
+}
+
+fn date(year: u64, month: u64, day: u64, time: u64)
+    -> Result<HttpDate, InvalidDate>
+{
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if day > days_in_month {
+        return Err(InvalidDate);
+    }
+    Ok(HttpDate(days_from_civil(year, month, day)*86400 + time))
+}
+
+impl FromStr for HttpDate {
+    type Err = InvalidDate;
+    fn from_str(val: &str) -> Result<HttpDate, InvalidDate> {
+        let parts = val.split(' ').filter(|x| x.len() > 0)
+            .collect::<Vec<_>>();
+        match parts.len() {
+            // IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+            6 if parts[0].ends_with(',') && parts[5] == "GMT" => {
+                if parts[1].len() != 2 || parts[3].len() != 4 {
+                    return Err(InvalidDate);
+                }
+                date(try!(number(parts[3], 1970, 9999)),
+                     try!(month(parts[2])),
+                     try!(number(parts[1], 1, 31)),
+                     try!(time(parts[4])))
+            }
+            // RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+            4 if parts[0].ends_with(',') && parts[3] == "GMT" => {
+                let dparts = parts[1].split('-').collect::<Vec<_>>();
+                if dparts.len() != 3 || dparts[0].len() != 2 ||
+                    dparts[2].len() != 2
+                {
+                    return Err(InvalidDate);
+                }
+                let year = try!(number(dparts[2], 0, 99));
+                // RFC 7231 suggests to interpret the year which appears
+                // to be more than 50 years in the future as the past one,
+                // we simplify it a little
+                let year = if year < 70 { 2000 + year } else { 1900 + year };
+                date(year, try!(month(dparts[1])),
+                     try!(number(dparts[0], 1, 31)),
+                     try!(time(parts[2])))
+            }
+            // asctime: Sun Nov  6 08:49:37 1994
+            5 => {
+                if parts[4].len() != 4 {
+                    return Err(InvalidDate);
+                }
+                date(try!(number(parts[4], 1970, 9999)),
+                     try!(month(parts[1])),
+                     try!(number(parts[2], 1, 31)),
+                     try!(time(parts[3])))
+            }
+            _ => Err(InvalidDate),
+        }
+    }
+}
+
+impl Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days = self.0 / 86400;
+        let secs = self.0 % 86400;
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[(days % 7) as usize], day, MONTHS[month - 1], year,
+            secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HttpDate, InvalidDate, write_header};
+
+    #[test]
+    fn test_format() {
+        assert_eq!(HttpDate(0).to_string(),
+                   "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(HttpDate(784111777).to_string(),
+                   "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(HttpDate(951782400).to_string(),
+                   "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_parse() {
+        let date = Ok(HttpDate(784111777));
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT".parse(), date);
+        assert_eq!("Sunday, 06-Nov-94 08:49:37 GMT".parse(), date);
+        assert_eq!("Sun Nov  6 08:49:37 1994".parse(), date);
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT".parse(), Ok(HttpDate(0)));
+        assert_eq!("Tue, 29 Feb 2000 00:00:00 GMT".parse(),
+                   Ok(HttpDate(951782400)));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for &ts in &[0, 68169600, 951782400, 1456790400, 4102444799] {
+            let date = HttpDate(ts);
+            assert_eq!(date.to_string().parse(), Ok(date));
+        }
+    }
+
+    #[test]
+    fn test_write_header() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        write_header(&mut first, HttpDate(784111777));
+        write_header(&mut second, HttpDate(784111777));
+        assert_eq!(&first[..],
+                   &b"Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"[..]);
+        assert_eq!(first, second);
+        let mut third = Vec::new();
+        write_header(&mut third, HttpDate(784111778));
+        assert_eq!(&third[..],
+                   &b"Date: Sun, 06 Nov 1994 08:49:38 GMT\r\n"[..]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for val in &["", "Sun, 06 Nov 1994 08:49:37 UTC",
+                     "Sun, 6 Nov 1994 08:49:37 GMT",
+                     "Sun, 06 Nov 1994 08:49 GMT",
+                     "Sun, 06 Nov 1994 24:00:00 GMT",
+                     "Sun, 31 Nov 1994 08:49:37 GMT",
+                     "Mon, 29 Feb 2100 00:00:00 GMT",
+                     "Sun, 06 Foo 1994 08:49:37 GMT",
+                     "Sun, 06 Nov +994 08:49:37 GMT",
+                     "\"xyzzy\""]
+        {
+            assert_eq!(val.parse::<HttpDate>(), Err(InvalidDate));
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+use std::io::Write;
+use std::mem::replace;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+
+/// A streaming gzip compressor for the message body
+///
+/// It's boxed to keep `MessageState` small.
+pub struct Gzip(Box<GzEncoder<Vec<u8>>>);
+
+impl Gzip {
+    pub fn new() -> Gzip {
+        Gzip(Box::new(GzEncoder::new(Vec::new(), Compression::Default)))
+    }
+    /// Feeds data to the compressor and returns compressed bytes if any
+    ///
+    /// Compressor buffers data internally, so the returned data is often
+    /// empty for small writes.
+    pub fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        // writing to a vector never fails
+        self.0.write_all(data).unwrap();
+        replace(self.0.get_mut(), Vec::new())
+    }
+    /// Finalizes the stream and returns the rest of compressed bytes
+    pub fn finish(self) -> Vec<u8> {
+        self.0.finish().unwrap()
+    }
+}
+
+impl fmt::Debug for Gzip {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("Gzip")
+    }
+}
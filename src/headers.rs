@@ -1,4 +1,7 @@
 use std::ascii::AsciiExt;
+use std::slice;
+
+use httparse::Header;
 
 pub fn is_transfer_encoding(val: &str) -> bool {
     val.eq_ignore_ascii_case("Transfer-Encoding")
@@ -12,104 +15,242 @@ pub fn is_connection(val: &str) -> bool {
     val.eq_ignore_ascii_case("Connection")
 }
 
+pub fn is_upgrade(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Upgrade")
+}
+
+pub fn is_host(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Host")
+}
+
 pub fn is_expect(val: &str) -> bool {
     val.eq_ignore_ascii_case("Expect")
 }
 
-// header value is byte sequence
-// we need case insensitive comparison and strip out of the whitespace
-pub fn is_close(val: &[u8]) -> bool {
-    if val.len() < "close".len() {
-        return false;
-    }
-    let mut iter = val.iter();
-    for (idx, &ch) in iter.by_ref().enumerate() {
-        match ch {
-            b'\r' | b'\n' | b' ' | b'\t' => continue,
-            b'c' | b'C' => {
-                if idx + "close".len() > val.len() {
-                    return false;
-                }
-                break;
-            }
-            _ => return false,
+pub fn is_range(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Range")
+}
+
+pub fn is_if_range(val: &str) -> bool {
+    val.eq_ignore_ascii_case("If-Range")
+}
+
+pub fn is_accept_language(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Accept-Language")
+}
+
+pub fn is_accept_encoding(val: &str) -> bool {
+    val.eq_ignore_ascii_case("Accept-Encoding")
+}
+
+pub fn is_te(val: &str) -> bool {
+    val.eq_ignore_ascii_case("TE")
+}
+
+fn is_whitespace(ch: u8) -> bool {
+    matches!(ch, b'\r' | b'\n' | b' ' | b'\t')
+}
+
+pub fn trim(val: &[u8]) -> &[u8] {
+    let start = val.iter().position(|&x| !is_whitespace(x))
+        .unwrap_or(val.len());
+    let end = val.iter().rposition(|&x| !is_whitespace(x))
+        .map(|x| x + 1).unwrap_or(start);
+    &val[start..end]
+}
+
+/// Returns true if the value is a non-empty sequence of ASCII digits
+///
+/// This is stricter than `u64::from_str` which also accepts a sign.
+pub fn is_digits(val: &[u8]) -> bool {
+    val.len() > 0 && val.iter().all(|&x| x >= b'0' && x <= b'9')
+}
+
+/// Strips optional whitespace (spaces and tabs) around the value
+///
+/// Unlike `trim` this doesn't touch CR and LF, as RFC 7230 allows only
+/// spaces and tabs in `OWS`.
+pub fn trim_ows(val: &[u8]) -> &[u8] {
+    let start = val.iter().position(|&x| x != b' ' && x != b'\t')
+        .unwrap_or(val.len());
+    let end = val.iter().rposition(|&x| x != b' ' && x != b'\t')
+        .map(|x| x + 1).unwrap_or(start);
+    &val[start..end]
+}
+
+// parses qvalue into thousandths, i.e. "0.5" is 500
+fn parse_qvalue(val: &[u8]) -> Option<u16> {
+    let (first, rest) = match val.split_first() {
+        Some((&ch, rest)) => (ch, rest),
+        None => return None,
+    };
+    if first != b'0' && first != b'1' {
+        return None;
+    }
+    let mut result = (first - b'0') as u16 * 1000;
+    if rest.len() == 0 {
+        return Some(result);
+    }
+    if rest[0] != b'.' || rest.len() > 4 {
+        return None;
+    }
+    let mut mul = 100;
+    for &ch in &rest[1..] {
+        if ch < b'0' || ch > b'9' {
+            return None;
         }
+        result += (ch - b'0') as u16 * mul;
+        mul /= 10;
+    }
+    if result > 1000 {
+        return None;
     }
-    for (idx, ch) in iter.by_ref().take(4).enumerate() {
-        if b"lose"[idx] != ch.to_ascii_lowercase() {
-            return false;
+    return Some(result);
+}
+
+// splits an item of a comma-separated list like `en-US;q=0.5` into
+// value and quality in thousandths, returns None if quality is malformed
+pub fn split_quality(item: &[u8]) -> Option<(&[u8], u16)> {
+    let mut parts = item.split(|&x| x == b';');
+    let value = trim(parts.next().unwrap());
+    let mut quality = 1000;
+    for param in parts {
+        let param = trim(param);
+        if param.len() >= 2 && (param[0] == b'q' || param[0] == b'Q')
+            && param[1] == b'='
+        {
+            quality = match parse_qvalue(&param[2..]) {
+                Some(q) => q,
+                None => return None,
+            };
         }
     }
-    for &ch in iter {
-        if !matches!(ch, b'\r' | b'\n' | b' ' | b'\t') {
-            return false;
+    Some((value, quality))
+}
+
+/// Iterator over values of the headers with the same name
+///
+/// See `Head::get_all`
+#[derive(Debug, Clone)]
+pub struct HeaderValues<'a, 'n> {
+    headers: slice::Iter<'a, Header<'a>>,
+    name: &'n str,
+}
+
+// Finds all headers with the name, compared case-insensitively
+pub fn header_values<'a, 'n>(headers: &'a [Header<'a>], name: &'n str)
+    -> HeaderValues<'a, 'n>
+{
+    HeaderValues { headers: headers.iter(), name: name }
+}
+
+impl<'a, 'n> Iterator for HeaderValues<'a, 'n> {
+    type Item = &'a [u8];
+    fn next(&mut self) -> Option<&'a [u8]> {
+        for header in &mut self.headers {
+            if header.name.eq_ignore_ascii_case(self.name) {
+                return Some(header.value);
+            }
         }
+        None
     }
-    return true;
 }
 
 // header value is byte sequence
 // we need case insensitive comparison and strip out of the whitespace
-pub fn is_chunked(val: &[u8]) -> bool {
-    if val.len() < "chunked".len() {
-        return false;
-    }
-    let mut iter = val.iter();
-    for (idx, &ch) in iter.by_ref().enumerate() {
-        match ch {
-            b'\r' | b'\n' | b' ' | b'\t' => continue,
-            b'c' | b'C' => {
-                if idx + "chunked".len() > val.len() {
-                    return false;
-                }
-                break;
+pub fn is_close(val: &[u8]) -> bool {
+    trim(val).eq_ignore_ascii_case(b"close")
+}
+
+/// A single token of the `Connection` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionToken<'a> {
+    Close,
+    KeepAlive,
+    Upgrade,
+    /// Usually a name of the hop-by-hop header
+    Other(&'a [u8]),
+}
+
+/// Iterator over tokens of the `Connection` header, see `connection_tokens`
+pub struct ConnectionTokens<'a>(&'a [u8]);
+
+// Splits comma-separated `Connection` header into tokens, empty list
+// elements (like in `close,,upgrade`) are skipped as RFC 7230 requires
+pub fn connection_tokens(val: &[u8]) -> ConnectionTokens {
+    ConnectionTokens(val)
+}
+
+impl<'a> Iterator for ConnectionTokens<'a> {
+    type Item = ConnectionToken<'a>;
+    fn next(&mut self) -> Option<ConnectionToken<'a>> {
+        use self::ConnectionToken::*;
+        while self.0.len() > 0 {
+            let (item, rest) = match self.0.iter().position(|&x| x == b',') {
+                Some(idx) => (&self.0[..idx], &self.0[idx+1..]),
+                None => (self.0, &b""[..]),
+            };
+            self.0 = rest;
+            let item = trim(item);
+            if item.len() == 0 {
+                continue;
             }
-            _ => return false,
+            return Some(if is_close(item) {
+                Close
+            } else if item.eq_ignore_ascii_case(b"keep-alive") {
+                KeepAlive
+            } else if item.eq_ignore_ascii_case(b"upgrade") {
+                Upgrade
+            } else {
+                Other(item)
+            });
         }
+        None
     }
-    for (idx, ch) in iter.by_ref().take(6).enumerate() {
-        if b"hunked"[idx] != ch.to_ascii_lowercase() {
-            return false;
-        }
-    }
-    for &ch in iter {
-        if !matches!(ch, b'\r' | b'\n' | b' ' | b'\t') {
-            return false;
-        }
-    }
-    return true;
+}
+
+// header value is byte sequence
+// we need case insensitive comparison and strip out of the whitespace
+pub fn is_chunked(val: &[u8]) -> bool {
+    trim(val).eq_ignore_ascii_case(b"chunked")
 }
 
 // header value is byte sequence
 // we need case insensitive comparison and strip out of the whitespace
 pub fn is_continue(val: &[u8]) -> bool {
-    if val.len() < "100-continue".len() {
-        return false;
-    }
-    let mut iter = val.iter();
-    for (idx, &ch) in iter.by_ref().enumerate() {
-        match ch {
-            b'\r' | b'\n' | b' ' | b'\t' => continue,
-            b'1' => {
-                if idx + "100-continue".len() > val.len() {
-                    return false;
-                }
-                break;
+    trim(val).eq_ignore_ascii_case(b"100-continue")
+}
+
+// Checks for obsolete line folding, i.e. a header line which starts with
+// whitespace and continues the value of the previous header
+pub fn has_obs_fold(data: &[u8]) -> bool {
+    data.windows(2)
+        .any(|pair| pair[0] == b'\n' && (pair[1] == b' ' || pair[1] == b'\t'))
+}
+
+// Replaces each obs-fold with spaces as RFC 7230 allows for recipients of
+// responses. The length is kept, so the offsets in the head don't change.
+pub fn unfold(data: &[u8]) -> Vec<u8> {
+    let mut result = data.to_vec();
+    for idx in 1..result.len() {
+        if result[idx-1] == b'\n' &&
+            (result[idx] == b' ' || result[idx] == b'\t')
+        {
+            result[idx-1] = b' ';
+            if idx >= 2 && result[idx-2] == b'\r' {
+                result[idx-2] = b' ';
             }
-            _ => return false,
-        }
-    }
-    for (idx, ch) in iter.by_ref().take(11).enumerate() {
-        if b"00-continue"[idx] != ch.to_ascii_lowercase() {
-            return false;
-        }
-    }
-    for &ch in iter {
-        if !matches!(ch, b'\r' | b'\n' | b' ' | b'\t') {
-            return false;
         }
     }
-    return true;
+    result
+}
+
+// Checks whether `TE` header value contains `trailers`, parameters
+// (i.e. quality values) are ignored as the `trailers` has no ones
+pub fn has_trailers(val: &[u8]) -> bool {
+    val.split(|&x| x == b',')
+        .map(|item| trim(item.split(|&x| x == b';').next().unwrap()))
+        .any(|item| item.eq_ignore_ascii_case(b"trailers"))
 }
 
 #[cfg(test)]
@@ -117,6 +258,11 @@ mod test {
     use super::{is_content_length, is_transfer_encoding, is_connection};
     use super::{is_expect};
     use super::{is_chunked, is_close, is_continue};
+    use super::{split_quality};
+    use super::{connection_tokens, ConnectionToken};
+    use super::has_trailers;
+    use super::{has_obs_fold, unfold};
+    use super::{trim_ows, is_digits};
 
     #[test]
     fn test_content_len() {
@@ -161,6 +307,9 @@ mod test {
         assert!(is_chunked(b"chunked  "));
         assert!(is_chunked(b"   CHUNKED"));
         assert!(!is_chunked(b"   CHUNKED 1 "));
+        assert!(!is_chunked(b"chunkedextra"));
+        assert!(!is_chunked(b"chunke"));
+        assert!(!is_chunked(b"        "));
     }
 
     #[test]
@@ -173,6 +322,9 @@ mod test {
         assert!(is_close(b"   close   "));
         assert!(!is_close(b"Close  1 "));
         assert!(!is_close(b" xclose   "));
+        assert!(!is_close(b"closed"));
+        assert!(!is_close(b"closely-related"));
+        assert!(!is_close(b"     "));
     }
 
     #[test]
@@ -185,5 +337,76 @@ mod test {
         assert!(is_continue(b"   100-continue   "));
         assert!(!is_continue(b"100-continue y  "));
         assert!(!is_continue(b"100-coztinue   "));
+        assert!(!is_continue(b"100-continued"));
+        assert!(!is_continue(b"              "));
+    }
+
+    #[test]
+    fn test_quality() {
+        assert_eq!(split_quality(b"en"), Some((&b"en"[..], 1000)));
+        assert_eq!(split_quality(b" en-US ; q=0.5"), Some((&b"en-US"[..], 500)));
+        assert_eq!(split_quality(b"de;Q=0.123"), Some((&b"de"[..], 123)));
+        assert_eq!(split_quality(b"*;q=0"), Some((&b"*"[..], 0)));
+        assert_eq!(split_quality(b"fr;q=1.000"), Some((&b"fr"[..], 1000)));
+        assert_eq!(split_quality(b"fr;q=1.5"), None);
+        assert_eq!(split_quality(b"fr;q=0.1234"), None);
+        assert_eq!(split_quality(b"fr;q=abc"), None);
+        assert_eq!(split_quality(b"fr;q="), None);
+    }
+
+    #[test]
+    fn test_connection_tokens() {
+        use super::ConnectionToken::*;
+        fn tokens(val: &[u8]) -> Vec<ConnectionToken> {
+            connection_tokens(val).collect()
+        }
+        assert_eq!(tokens(b"close"), vec![Close]);
+        assert_eq!(tokens(b"Keep-Alive"), vec![KeepAlive]);
+        assert_eq!(tokens(b"close, Upgrade"), vec![Close, Upgrade]);
+        assert_eq!(tokens(b" keep-alive ,UPGRADE,  TE "),
+                   vec![KeepAlive, Upgrade, Other(b"TE")]);
+        assert_eq!(tokens(b",,close,, "), vec![Close]);
+        assert_eq!(tokens(b"closed"), vec![Other(b"closed")]);
+        assert_eq!(tokens(b""), vec![]);
+    }
+
+    #[test]
+    fn test_te_trailers() {
+        assert!(has_trailers(b"trailers"));
+        assert!(has_trailers(b"deflate;q=0.5, Trailers "));
+        assert!(!has_trailers(b"deflate"));
+        assert!(!has_trailers(b"trailersx"));
+        assert!(!has_trailers(b""));
+    }
+
+    #[test]
+    fn test_trim_ows() {
+        assert_eq!(trim_ows(b"  42 "), &b"42"[..]);
+        assert_eq!(trim_ows(b"\t42\t \t"), &b"42"[..]);
+        assert_eq!(trim_ows(b"4 2"), &b"4 2"[..]);
+        assert_eq!(trim_ows(b" \t "), &b""[..]);
+        assert_eq!(trim_ows(b""), &b""[..]);
+        assert_eq!(trim_ows(b"42\r\n"), &b"42\r\n"[..]);
+    }
+
+    #[test]
+    fn test_digits() {
+        assert!(is_digits(b"0"));
+        assert!(is_digits(b"0042"));
+        assert!(!is_digits(b""));
+        assert!(!is_digits(b"+5"));
+        assert!(!is_digits(b"-5"));
+        assert!(!is_digits(b"0x5"));
+        assert!(!is_digits(b" 5"));
+    }
+
+    #[test]
+    fn test_unfold() {
+        let head = b"X-A: a\r\n b\r\nX-B: c\n\td\r\n\r\n";
+        assert!(has_obs_fold(head));
+        let result = unfold(head);
+        assert_eq!(&result[..], &b"X-A: a   b\r\nX-B: c \td\r\n\r\n"[..]);
+        assert!(!has_obs_fold(&result));
+        assert!(!has_obs_fold(b"X-A: a\r\nX-B: b\r\n\r\n"));
     }
 }
@@ -3,15 +3,23 @@
 extern crate rotor;
 extern crate httparse;
 extern crate rotor_stream;
+extern crate net2;
 #[cfg(feature="nightly")] extern crate test;
 #[cfg(test)] extern crate rotor_test;
 #[macro_use] extern crate quick_error;
 #[macro_use] extern crate matches;
 #[macro_use] extern crate log;
+#[cfg(feature="gzip")] extern crate flate2;
+#[cfg(feature="websocket")] extern crate sha1;
+#[cfg(feature="websocket")] extern crate rustc_serialize;
 
 pub mod server;
 pub mod client;
+pub mod mime;
 mod message;
 mod recvmode;
 mod headers;
 mod version;
+mod date;
+mod status;
+#[cfg(feature="gzip")] mod gzip;
@@ -1,9 +1,39 @@
 use std::io::Write;
 use std::ascii::AsciiExt;
+use std::mem::replace;
 
 use rotor_stream::Buf;
 
 use version::Version;
+use date::{self, HttpDate};
+use server::ErrorFormat;
+use status::reason_phrase;
+#[cfg(feature="gzip")] use gzip::Gzip;
+
+/// Maximum size of the body buffered by `auto_framing` mode
+///
+/// If the body is smaller it's sent with `Content-Length`, otherwise
+/// chunked encoding is used.
+pub const AUTO_FRAMING_THRESHOLD: usize = 16384;
+
+/// Returns a strong entity tag for the body as used by `auto_etag`
+///
+/// The tag is a 64-bit FNV-1a hash of the body formatted as 16 lowercase
+/// hex digits in double quotes, e.g. `"cbf29ce484222325"` for the empty
+/// body. The algorithm is stable across versions and platforms, so the
+/// value may be compared with the `If-None-Match` header of the request.
+pub fn body_etag(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("\"{:016x}\"", hash)
+}
+
+/// The value of `Server` header written when `AutoHeaders::server` is set
+pub const SERVER_NAME: &'static str = concat!("rotor-http/",
+                                              env!("CARGO_PKG_VERSION"));
 
 quick_error! {
     #[derive(Debug)]
@@ -32,23 +62,87 @@ quick_error! {
         RequireBodyless {
             description("This message must not contain body length fields.")
         }
+        AutoFraming {
+            description("Body length is determined automatically")
+        }
+        TrailersRequireChunked {
+            description("Trailers can only be sent with chunked encoding")
+        }
+        UndeclaredTrailer {
+            description("Trailer field is not declared in the Trailer header")
+        }
+        TrailersNotAccepted {
+            description("Client doesn't accept trailers (no `TE: trailers`)")
+        }
+        IncompatibleVersion {
+            description("Response version is higher than the request version")
+        }
     }
 }
 
+/// Headers which are written by the library itself
+///
+/// The `Server` header is written right after the status line, before any
+/// header added by the handler. The `Date` header is written by
+/// `done_headers()`, unless the handler has added its own one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AutoHeaders {
+    /// Write `Date` header with the specified time
+    ///
+    /// Use `HttpDate::cached_now(scope.now())` to get the current time
+    /// without reading the system clock for every response.
+    pub date: Option<HttpDate>,
+    /// Write `Server` header with the name and version of the library
+    pub server: bool,
+}
+
+/// What to do with trailer fields of the chunked body
+///
+/// Clients must signal with `TE: trailers` that they accept trailers in
+/// the response, requests may contain trailers unconditionally.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TrailerPolicy {
+    /// Trailers are sent
+    Allow,
+    /// Trailers are sent but a warning is logged
+    Warn,
+    /// `add_trailer` returns an error
+    Deny,
+}
+
+/// Returns a reason phrase for the status code, see `Server::reason_phrase`
+pub type ReasonPhrases = fn(u16) -> Option<&'static str>;
+
 #[derive(Debug)]
 pub enum MessageState {
     /// Nothing has been sent.
-    ResponseStart { version: Version, body: Body, close: bool },
+    ///
+    /// The `errors` is the format of the error page negotiated from the
+    /// request headers, the `reasons` looks up reason phrases for
+    /// `response_status_code()`.
+    ResponseStart { version: Version, body: Body, close: bool,
+                    auto: AutoHeaders, te: TrailerPolicy,
+                    errors: ErrorFormat, reasons: ReasonPhrases },
     /// A continuation line has been sent.
-    FinalResponseStart { version: Version, body: Body, close: bool },
+    FinalResponseStart { version: Version, body: Body, close: bool,
+                         auto: AutoHeaders, te: TrailerPolicy,
+                         errors: ErrorFormat, reasons: ReasonPhrases },
     /// Nothing has been sent.
     RequestStart,
     /// Status line is already in the buffer.
-    Headers { body: Body, close: bool },
+    ///
+    /// In this and other `*Headers` states the `date` means that the
+    /// automatic `Date` header is still to be written.
+    Headers { body: Body, close: bool, date: Option<HttpDate>,
+              te: TrailerPolicy },
     /// The message contains a fixed size body.
-    FixedHeaders { is_head: bool, close: bool, content_length: u64 },
+    FixedHeaders { is_head: bool, close: bool, date: Option<HttpDate>,
+                   content_length: u64 },
     /// The message contains a chunked body.
-    ChunkedHeaders { is_head: bool, close: bool },
+    ///
+    /// The `trailers` are field names passed to `declare_trailers`.
+    ChunkedHeaders { is_head: bool, close: bool, date: Option<HttpDate>,
+                     te: TrailerPolicy, trailers: Option<Box<Vec<String>>> },
     /// The message contains no body.
     ///
     /// A request without a `Content-Length` or `Transfer-Encoding`
@@ -60,7 +154,23 @@ pub enum MessageState {
     /// The message contains a body with the given length.
     FixedBody { is_head: bool, content_length: u64 },
     /// The message contains a chunked body.
-    ChunkedBody { is_head: bool },
+    ChunkedBody { is_head: bool, te: TrailerPolicy,
+                  trailers: Option<Box<Vec<String>>> },
+    /// The last chunk is sent, trailer fields are being written.
+    Trailers { is_head: bool, declared: Option<Box<Vec<String>>> },
+    /// The body length is determined when the body is written.
+    AutoFramingHeaders { is_head: bool, close: bool,
+                         date: Option<HttpDate>, etag: bool,
+                         te: TrailerPolicy },
+    /// The body is being buffered to determine its length.
+    AutoFramingBody { is_head: bool, etag: bool, te: TrailerPolicy,
+                      data: Box<Vec<u8>> },
+    /// The message contains a gzip-compressed chunked body.
+    #[cfg(feature="gzip")]
+    GzipHeaders { close: bool, date: Option<HttpDate> },
+    /// The gzip-compressed chunked body is being written.
+    #[cfg(feature="gzip")]
+    GzipBody(Gzip),
     /// A message in final state.
     Done,
 }
@@ -84,12 +194,28 @@ pub enum Body {
 /// This type is private for the crate.
 pub struct Message<'a>(&'a mut Buf, MessageState);
 
+fn write_chunk(buf: &mut Buf, data: &[u8]) {
+    // Empty chunk would mean the end of the body
+    if data.len() > 0 {
+        write!(buf, "{:x}\r\n", data.len()).unwrap();
+        buf.write(data).unwrap();
+        buf.write(b"\r\n").unwrap();
+    }
+}
+
 impl MessageState {
     pub fn with<'x, I>(self, out_buf: &'x mut Buf) -> I
         where I: From<Message<'x>>
     {
         Message(out_buf, self).into()
     }
+    /// Returns true if anything of the message is written to the buffer
+    pub fn is_started(&self) -> bool {
+        !matches!(*self,
+            MessageState::RequestStart |
+            MessageState::ResponseStart { .. } |
+            MessageState::FinalResponseStart { .. })
+    }
 }
 
 impl<'a> Message<'a> {
@@ -109,12 +235,19 @@ impl<'a> Message<'a> {
         use self::Body::*;
         use self::MessageState::*;
         match self.1 {
-            ResponseStart { version, mut body, close } |
-            FinalResponseStart { version, mut body, close } => {
+            ResponseStart { version, mut body, close, auto, te, .. } |
+            FinalResponseStart { version, mut body, close, auto, te, .. } => {
                 // 100 (Continue) interim status code is not allowed as
                 // a final response status.
                 assert!(code != 100);
                 write!(self.0, "{} {} {}\r\n", version, code, reason).unwrap();
+                if auto.server {
+                    self.write_header("Server", SERVER_NAME.as_bytes());
+                }
+                // HTTP/1.0 connection is only kept alive on explicit request
+                if version == Version::Http10 && !close {
+                    self.write_header("Connection", b"keep-alive");
+                }
                 // Responses without body:
                 //
                 // * 1xx (Informational)
@@ -123,7 +256,8 @@ impl<'a> Message<'a> {
                 if (code >= 100 && code < 200) || code == 204 || code == 304 {
                     body = Denied
                 }
-                self.1 = Headers { body: body, close: close };
+                self.1 = Headers { body: body, close: close,
+                                   date: auto.date, te: te };
             }
             ref state => {
                 panic!("Called response_status() method on response in state {:?}",
@@ -150,7 +284,8 @@ impl<'a> Message<'a> {
                 write!(self.0, "{} {} {}\r\n", method, path, version).unwrap();
                 // All requests may contain a body although it is uncommon for
                 // GET and HEAD requests to contain one.
-                self.1 = Headers { body: Request, close: false };
+                self.1 = Headers { body: Request, close: false,
+                                   date: None, te: TrailerPolicy::Allow };
             }
             ref state => {
                 panic!("Called request_line() method on request in state {:?}",
@@ -171,11 +306,16 @@ impl<'a> Message<'a> {
     pub fn response_continue(&mut self) {
         use self::MessageState::*;
         match self.1 {
-            ResponseStart { version, body, close } => {
+            ResponseStart { version, body, close, auto, te, errors,
+                            reasons } => {
                 write!(self.0, "{} 100 Continue\r\n\r\n", version).unwrap();
                 self.1 = FinalResponseStart { version: version,
                                               body: body,
-                                              close: close }
+                                              close: close,
+                                              auto: auto,
+                                              te: te,
+                                              errors: errors,
+                                              reasons: reasons }
             }
             ref state => {
                 panic!("Called continue_line() method on response in state {:?}",
@@ -203,6 +343,9 @@ impl<'a> Message<'a> {
     /// Note that there is currently no way to use a transfer encoding other
     /// than chunked.
     ///
+    /// The `Date` header added here replaces the automatic one (see
+    /// `AutoHeaders`).
+    ///
     /// We return Result here to make implementing proxies easier. In the
     /// application handler it's okay to unwrap the result and to get
     /// a meaningful panic (that is basically an assertion).
@@ -220,15 +363,28 @@ impl<'a> Message<'a> {
             return Err(BodyLengthHeader)
         }
         match self.1 {
-            Headers { .. } | FixedHeaders { .. } | ChunkedHeaders { .. } => {
-                self.write_header(name, value);
-                Ok(())
+            Headers { ref mut date, .. } |
+            FixedHeaders { ref mut date, .. } |
+            ChunkedHeaders { ref mut date, .. } |
+            AutoFramingHeaders { ref mut date, .. } => {
+                // handler's own `Date` replaces the automatic one
+                if name.eq_ignore_ascii_case("Date") {
+                    *date = None;
+                }
+            }
+            #[cfg(feature="gzip")]
+            GzipHeaders { ref mut date, .. } => {
+                if name.eq_ignore_ascii_case("Date") {
+                    *date = None;
+                }
             }
             ref state => {
                 panic!("Called add_header() method on a message in state {:?}",
                        state)
             }
         }
+        self.write_header(name, value);
+        Ok(())
     }
 
     /// Add a content length to the message.
@@ -248,12 +404,16 @@ impl<'a> Message<'a> {
         match self.1 {
             FixedHeaders { .. } => Err(DuplicateContentLength),
             ChunkedHeaders { .. } => Err(ContentLengthAfterTransferEncoding),
+            #[cfg(feature="gzip")]
+            GzipHeaders { .. } => Err(ContentLengthAfterTransferEncoding),
+            AutoFramingHeaders { .. } => Err(AutoFraming),
             Headers { body: Denied, .. } => Err(RequireBodyless),
-            Headers { body, close } => {
+            Headers { body, close, date, .. } => {
                 self.write_header("Content-Length",
                                   &n.to_string().into_bytes()[..]);
                 self.1 = FixedHeaders { is_head: body == Head,
                                         close: close,
+                                        date: date,
                                         content_length: n };
                 Ok(())
             }
@@ -281,11 +441,17 @@ impl<'a> Message<'a> {
             match self.1 {
                 FixedHeaders { .. } => Err(TransferEncodingAfterContentLength),
                 ChunkedHeaders { .. } => Err(DuplicateTransferEncoding),
+                #[cfg(feature="gzip")]
+                GzipHeaders { .. } => Err(DuplicateTransferEncoding),
+                AutoFramingHeaders { .. } => Err(AutoFraming),
                 Headers { body: Denied, .. } => Err(RequireBodyless),
-                Headers { body, close } => {
+                Headers { body, close, date, te } => {
                     self.write_header("Transfer-Encoding", b"chunked");
                     self.1 = ChunkedHeaders { is_head: body == Head,
-                                              close: close };
+                                              close: close,
+                                              date: date,
+                                              te: te,
+                                              trailers: None };
                     Ok(())
                 }
             ref state => {
@@ -295,15 +461,221 @@ impl<'a> Message<'a> {
         }
     }
 
+    /// Lets the body length be determined from the body itself
+    ///
+    /// The body is buffered until either `done()` is called, in this case
+    /// `Content-Length` is sent, or until it's larger than
+    /// `AUTO_FRAMING_THRESHOLD`, in this case chunked encoding is used.
+    /// No header is written until one of these happens.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `auto_framing` is called in the wrong state.
+    pub fn auto_framing(&mut self)
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        use self::HeaderError::*;
+        use self::Body::*;
+        match self.1 {
+            FixedHeaders { .. } => Err(DuplicateContentLength),
+            ChunkedHeaders { .. } => Err(DuplicateTransferEncoding),
+            #[cfg(feature="gzip")]
+            GzipHeaders { .. } => Err(DuplicateTransferEncoding),
+            AutoFramingHeaders { .. } => Ok(()),
+            Headers { body: Denied, .. } => Err(RequireBodyless),
+            Headers { body, close, date, te } => {
+                self.1 = AutoFramingHeaders { is_head: body == Head,
+                                              close: close, date: date,
+                                              etag: false, te: te };
+                Ok(())
+            }
+            ref state => {
+                panic!("Called auto_framing() method on message in state {:?}",
+                       state)
+            }
+        }
+    }
+
+    /// Adds `ETag` computed over the body, see `body_etag`
+    ///
+    /// This implies `auto_framing()`, but the whole body is buffered
+    /// regardless of its size, because the header can only be written
+    /// when the body is complete, i.e. in `done()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `auto_etag` is called in the wrong state.
+    pub fn auto_etag(&mut self)
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        try!(self.auto_framing());
+        if let AutoFramingHeaders { ref mut etag, .. } = self.1 {
+            *etag = true;
+        }
+        Ok(())
+    }
+
+    /// Enables streaming gzip compression of the body
+    ///
+    /// Writes `Content-Encoding: gzip` and `Transfer-Encoding: chunked`
+    /// headers to the output buffer immediately. The data passed to
+    /// `write_body` is compressed incrementally and the compressed stream
+    /// is finalized in `done()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `enable_gzip` is called in the wrong state.
+    #[cfg(feature="gzip")]
+    pub fn enable_gzip(&mut self)
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        use self::HeaderError::*;
+        use self::Body::*;
+        match self.1 {
+            FixedHeaders { .. } => Err(TransferEncodingAfterContentLength),
+            ChunkedHeaders { .. } | GzipHeaders { .. }
+            => Err(DuplicateTransferEncoding),
+            AutoFramingHeaders { .. } => Err(AutoFraming),
+            Headers { body: Denied, .. } | Headers { body: Head, .. }
+            => Err(RequireBodyless),
+            Headers { close, date, .. } => {
+                self.write_header("Content-Encoding", b"gzip");
+                self.write_header("Transfer-Encoding", b"chunked");
+                self.1 = GzipHeaders { close: close, date: date };
+                Ok(())
+            }
+            ref state => {
+                panic!("Called enable_gzip() method on message in state {:?}",
+                       state)
+            }
+        }
+    }
+
+    /// Writes the `Trailer` header and restricts trailers to these names
+    ///
+    /// Must be called after `add_chunked()`. Afterwards `add_trailer()`
+    /// returns an error for any field not declared here. Without this call
+    /// any trailer is allowed (you may still add `Trailer` header manually).
+    ///
+    /// # Panics
+    ///
+    /// Panics when `declare_trailers` is called in the wrong state.
+    pub fn declare_trailers(&mut self, names: &[&str])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        use self::HeaderError::*;
+        match self.1 {
+            ChunkedHeaders { .. } => {}
+            Headers { .. } | FixedHeaders { .. } |
+            AutoFramingHeaders { .. } => return Err(TrailersRequireChunked),
+            #[cfg(feature="gzip")]
+            GzipHeaders { .. } => return Err(TrailersRequireChunked),
+            ref state => {
+                panic!("Called declare_trailers() method on message \
+                    in state {:?}", state)
+            }
+        }
+        self.write_header("Trailer", names.join(", ").as_bytes());
+        if let ChunkedHeaders { ref mut trailers, .. } = self.1 {
+            if trailers.is_none() {
+                *trailers = Some(Box::new(Vec::new()));
+            }
+            trailers.as_mut().unwrap()
+                .extend(names.iter().map(|x| x.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Adds a trailer field, i.e. a header sent after the chunked body
+    ///
+    /// The first call terminates the body, so `write_body()` can't be
+    /// called afterwards. Call `done()` to finish the trailer section.
+    /// For responses to HEAD requests nothing is written.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_trailer` is called in the wrong state.
+    pub fn add_trailer(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        use self::MessageState::*;
+        use self::HeaderError::*;
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(BodyLengthHeader)
+        }
+        let allowed = match self.1 {
+            ChunkedBody { trailers: Some(ref list), .. } |
+            Trailers { declared: Some(ref list), .. } => {
+                list.iter().any(|x| x.eq_ignore_ascii_case(name))
+            }
+            ChunkedBody { .. } | Trailers { .. } => true,
+            ref state => {
+                panic!("Called add_trailer() method on message in state {:?}",
+                       state)
+            }
+        };
+        if !allowed {
+            return Err(UndeclaredTrailer);
+        }
+        match self.1 {
+            ChunkedBody { te: TrailerPolicy::Deny, .. } => {
+                return Err(TrailersNotAccepted);
+            }
+            ChunkedBody { te: TrailerPolicy::Warn, .. } => {
+                warn!("Sending trailer {:?} although the client didn't \
+                       send `TE: trailers`", name);
+            }
+            _ => {}
+        }
+        if let ChunkedBody { .. } = self.1 {
+            if let ChunkedBody { is_head, trailers, .. }
+                = replace(&mut self.1, Done)
+            {
+                if !is_head {
+                    self.0.write(b"0\r\n").unwrap();
+                }
+                self.1 = Trailers { is_head: is_head, declared: trailers };
+            }
+        }
+        let is_head = match self.1 {
+            Trailers { is_head, .. } => is_head,
+            _ => unreachable!(),
+        };
+        if !is_head {
+            self.write_header(name, value);
+        }
+        Ok(())
+    }
+
     /// Returns true if at least `status()` method has been called
     ///
     /// This is mostly useful to find out whether we can build an error page
     /// or it's already too late.
     pub fn is_started(&self) -> bool {
-        !matches!(self.1,
-            MessageState::RequestStart |
-            MessageState::ResponseStart { .. } |
-            MessageState::FinalResponseStart { .. })
+        self.1.is_started()
+    }
+
+    /// Returns true if `Connection: close` is going to be sent
+    ///
+    /// Only meaningful before `done_headers()`, afterwards it's `false`.
+    pub fn is_closing(&self) -> bool {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { close, .. } |
+            FinalResponseStart { close, .. } |
+            Headers { close, .. } |
+            FixedHeaders { close, .. } |
+            ChunkedHeaders { close, .. } |
+            AutoFramingHeaders { close, .. } => close,
+            #[cfg(feature="gzip")]
+            GzipHeaders { close, .. } => close,
+            _ => false,
+        }
     }
     
     /// Closes the HTTP header and returns `true` if entity body is expected.
@@ -320,10 +692,19 @@ impl<'a> Message<'a> {
     pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
         use self::Body::*;
         use self::MessageState::*;
-        if matches!(self.1,
-                    Headers { close: true, .. } |
-                    FixedHeaders { close: true, .. } |
-                    ChunkedHeaders { close: true, .. }) {
+        let (close, date) = match self.1 {
+            Headers { close, date, .. } |
+            FixedHeaders { close, date, .. } |
+            ChunkedHeaders { close, date, .. } |
+            AutoFramingHeaders { close, date, .. } => (close, date),
+            #[cfg(feature="gzip")]
+            GzipHeaders { close, date } => (close, date),
+            _ => (false, None),
+        };
+        if let Some(now) = date {
+            date::write_header(self.0, now);
+        }
+        if close {
             self.add_header("Connection", b"close").unwrap();
         }
         let expect_body = match self.1 {
@@ -343,9 +724,28 @@ impl<'a> Message<'a> {
                                      content_length: content_length };
                 !is_head
             }
-            ChunkedHeaders { is_head, .. } => {
-                self.1 = ChunkedBody { is_head: is_head };
-                !is_head
+            ChunkedHeaders { .. } => {
+                if let ChunkedHeaders { is_head, te, trailers, .. }
+                    = replace(&mut self.1, Done)
+                {
+                    self.1 = ChunkedBody { is_head: is_head, te: te,
+                                           trailers: trailers };
+                    !is_head
+                } else {
+                    unreachable!();
+                }
+            }
+            AutoFramingHeaders { is_head, etag, te, .. } => {
+                // Headers are finished when body length is known
+                self.1 = AutoFramingBody { is_head: is_head, etag: etag,
+                                           te: te,
+                                           data: Box::new(Vec::new()) };
+                return Ok(!is_head);
+            }
+            #[cfg(feature="gzip")]
+            GzipHeaders { .. } => {
+                self.1 = GzipBody(Gzip::new());
+                true
             }
             ref state => {
                 panic!("Called done_headers() method on  in state {:?}",
@@ -391,11 +791,14 @@ impl<'a> Message<'a> {
                 }
                 *content_length -= data.len() as u64;
             }
-            ChunkedBody { is_head } => if !is_head && data.len() > 0 {
-                write!(self.0, "{:x}\r\n", data.len()).unwrap();
-                self.0.write(data).unwrap();
-                self.0.write(b"\r\n").unwrap();
+            ChunkedBody { is_head, .. } => if !is_head {
+                write_chunk(self.0, data);
             },
+            #[cfg(feature="gzip")]
+            GzipBody(ref mut gzip) => {
+                write_chunk(self.0, &gzip.compress(data));
+            }
+            AutoFramingBody { .. } => self.buffer_body(data),
             ref state => {
                 panic!("Called write_body() method on message \
                     in state {:?}", state)
@@ -403,6 +806,70 @@ impl<'a> Message<'a> {
         }
     }
     
+    /// Writes a message body consisting of several parts
+    ///
+    /// If called before `done_headers()`, the headers are finished here.
+    /// When there is no header which determines the body length yet,
+    /// `Content-Length` with the total length of the parts is added, so
+    /// the parts must be the whole body in this case. Otherwise each part
+    /// is written as if it was passed to `write_body`, in particular each
+    /// part is a separate chunk of the chunked body.
+    ///
+    /// # Panics
+    ///
+    /// Same as `write_body()`, and also when the message must not contain
+    /// a body (e.g. `204 No Content`).
+    pub fn write_body_parts<'x, I>(&mut self, parts: I)
+        where I: IntoIterator<Item=&'x [u8]>
+    {
+        use self::MessageState::*;
+        let parts = parts.into_iter().collect::<Vec<_>>();
+        match self.1 {
+            Headers { .. } => {
+                let total = parts.iter()
+                    .fold(0, |total, part| total + part.len() as u64);
+                self.add_length(total).unwrap();
+                self.done_headers().unwrap();
+            }
+            FixedHeaders { .. } | ChunkedHeaders { .. } |
+            AutoFramingHeaders { .. } => {
+                self.done_headers().unwrap();
+            }
+            #[cfg(feature="gzip")]
+            GzipHeaders { .. } => {
+                self.done_headers().unwrap();
+            }
+            _ => {}
+        }
+        for part in parts {
+            self.write_body(part);
+        }
+    }
+
+    fn buffer_body(&mut self, chunk: &[u8]) {
+        use self::MessageState::*;
+        let overflow = match self.1 {
+            AutoFramingBody { data: ref mut buffered, etag, .. } => {
+                buffered.extend(chunk);
+                !etag && buffered.len() > AUTO_FRAMING_THRESHOLD
+            }
+            _ => unreachable!(),
+        };
+        if overflow {
+            if let AutoFramingBody { is_head, te, data, .. }
+                = replace(&mut self.1, Done)
+            {
+                self.write_header("Transfer-Encoding", b"chunked");
+                self.0.write(b"\r\n").unwrap();
+                if !is_head {
+                    write_chunk(self.0, &data);
+                }
+                self.1 = ChunkedBody { is_head: is_head, te: te,
+                                       trailers: None };
+            }
+        }
+    }
+
     /// Returns true if `done()` method is already called-
     pub fn is_complete(&self) -> bool {
         matches!(self.1, MessageState::Done)
@@ -422,15 +889,42 @@ impl<'a> Message<'a> {
             Bodyless => self.1 = Done,
             // Don't check for responses to HEAD requests if body was actually sent.
             FixedBody {is_head: true, .. } |
-            ChunkedBody { is_head: true } => self.1 = Done,
+            ChunkedBody { is_head: true, .. } |
+            Trailers { is_head: true, .. } => self.1 = Done,
             FixedBody { is_head: false, content_length: 0 } => self.1 = Done,
             FixedBody { is_head: false, content_length } => 
                 panic!("Tried to close message with {} bytes remaining.",
                        content_length),
-            ChunkedBody { is_head: false } => {
+            ChunkedBody { is_head: false, .. } => {
                 self.0.write(b"0\r\n\r\n").unwrap();
                 self.1 = Done;
             }
+            Trailers { is_head: false, .. } => {
+                self.0.write(b"\r\n").unwrap();
+                self.1 = Done;
+            }
+            #[cfg(feature="gzip")]
+            GzipBody(..) => {
+                if let GzipBody(gzip) = replace(&mut self.1, Done) {
+                    write_chunk(self.0, &gzip.finish());
+                }
+                self.0.write(b"0\r\n\r\n").unwrap();
+            }
+            AutoFramingBody { .. } => {
+                if let AutoFramingBody { is_head, etag, data, .. }
+                    = replace(&mut self.1, Done)
+                {
+                    if etag {
+                        self.write_header("ETag", body_etag(&data).as_bytes());
+                    }
+                    self.write_header("Content-Length",
+                                      data.len().to_string().as_bytes());
+                    self.0.write(b"\r\n").unwrap();
+                    if !is_head {
+                        self.0.write(&data).unwrap();
+                    }
+                }
+            }
             Done => {}  // multiple invocations are okay.
             ref state => {
                 panic!("Called done() method on response in state {:?}",
@@ -439,6 +933,153 @@ impl<'a> Message<'a> {
         }
     }
 
+    /// Sets whether trailers may be sent, before the status line is written
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or the message is a request.
+    pub fn set_trailer_policy(&mut self, policy: TrailerPolicy) {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { ref mut te, .. } |
+            FinalResponseStart { ref mut te, .. } => *te = policy,
+            ref state => {
+                panic!("Called set_trailer_policy() method on message \
+                    in state {:?}", state)
+            }
+        }
+    }
+
+    /// Sets the format of the error page, before the status line is written
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or the message is a request.
+    pub fn set_error_format(&mut self, format: ErrorFormat) {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { ref mut errors, .. } |
+            FinalResponseStart { ref mut errors, .. } => *errors = format,
+            ref state => {
+                panic!("Called set_error_format() method on message \
+                    in state {:?}", state)
+            }
+        }
+    }
+
+    /// Returns the format of the error page negotiated for the request
+    ///
+    /// It's `Html` when the response is already started.
+    pub fn error_format(&self) -> ErrorFormat {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { errors, .. } |
+            FinalResponseStart { errors, .. } => errors,
+            _ => ErrorFormat::Html,
+        }
+    }
+
+    /// Sets the lookup of reason phrases used by `response_status_code()`
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or the message is a request.
+    pub fn set_reason_phrases(&mut self, fun: ReasonPhrases) {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { ref mut reasons, .. } |
+            FinalResponseStart { ref mut reasons, .. } => *reasons = fun,
+            ref state => {
+                panic!("Called set_reason_phrases() method on message \
+                    in state {:?}", state)
+            }
+        }
+    }
+
+    /// Write status line with the reason phrase looked up for the code
+    ///
+    /// The phrase set by `set_reason_phrases()` is used if there is one,
+    /// otherwise the standard one, for unknown codes it's empty.
+    ///
+    /// # Panics
+    ///
+    /// Same as `response_status()`
+    pub fn response_status_code(&mut self, code: u16) {
+        use self::MessageState::*;
+        let custom = match self.1 {
+            ResponseStart { reasons, .. } |
+            FinalResponseStart { reasons, .. } => reasons(code),
+            _ => None,
+        };
+        let reason = custom.or_else(|| reason_phrase(code)).unwrap_or("");
+        self.response_status(code, reason)
+    }
+
+    /// Overrides the version written in the status line
+    ///
+    /// Only downgrading is allowed, i.e. the response to an HTTP/1.0
+    /// request can't be an HTTP/1.1 one.
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or the message is a request.
+    pub fn set_version(&mut self, new: Version) -> Result<(), HeaderError> {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { ref mut version, .. } |
+            FinalResponseStart { ref mut version, .. } => {
+                if new > *version {
+                    return Err(HeaderError::IncompatibleVersion);
+                }
+                *version = new;
+                Ok(())
+            }
+            ref state => {
+                panic!("Called set_version() method on message \
+                    in state {:?}", state)
+            }
+        }
+    }
+
+    /// Writes a complete pre-rendered response and marks message as done
+    ///
+    /// # Panics
+    ///
+    /// When status line is already written or the message is a request.
+    pub fn send_raw(&mut self, data: &[u8]) {
+        use self::MessageState::*;
+        match self.1 {
+            ResponseStart { .. } | FinalResponseStart { .. } => {
+                self.0.write(data).unwrap();
+                self.1 = Done;
+            }
+            ref state => {
+                panic!("Called send_raw() method on message in state {:?}",
+                       state)
+            }
+        }
+    }
+
+    /// Writes raw data after the message is complete
+    ///
+    /// Used when the connection is switched to another protocol.
+    ///
+    /// # Panics
+    ///
+    /// When the message is not complete yet.
+    pub fn write_raw(&mut self, data: &[u8]) {
+        use self::MessageState::*;
+        match self.1 {
+            Done => {
+                self.0.write(data).unwrap();
+            }
+            ref state => {
+                panic!("Called write_raw() method on message in state {:?}",
+                       state)
+            }
+        }
+    }
+
     pub fn state(self) -> MessageState {
         self.1
     }
@@ -450,13 +1091,20 @@ impl<'a> Message<'a> {
 #[cfg(test)]
 mod test {
     use rotor_stream::Buf;
-    use super::{Message, MessageState, Body};
+    use super::{Message, MessageState, Body, AutoHeaders, SERVER_NAME};
+    use super::{HeaderError, AUTO_FRAMING_THRESHOLD, body_etag};
+    use super::TrailerPolicy;
+    use server::ErrorFormat;
     use version::Version;
 
+    fn no_reasons(_code: u16) -> Option<&'static str> {
+        None
+    }
+
     #[test]
     fn message_size() {
         // Just to keep track of size of structure
-        assert_eq!(::std::mem::size_of::<MessageState>(), 16);
+        assert_eq!(::std::mem::size_of::<MessageState>(), 40);
     }
 
     fn do_request<F: FnOnce(Message)>(fun: F) -> Buf {
@@ -470,6 +1118,10 @@ mod test {
             version: Version::Http10,
             body: Body::Normal,
             close: false,
+            auto: AutoHeaders::default(),
+            te: TrailerPolicy::Allow,
+            errors: ErrorFormat::Html,
+            reasons: no_reasons,
         }.with(&mut buf));
         return buf;
     }
@@ -479,6 +1131,10 @@ mod test {
             version: Version::Http11,
             body: Body::Normal,
             close: close,
+            auto: AutoHeaders::default(),
+            te: TrailerPolicy::Allow,
+            errors: ErrorFormat::Html,
+            reasons: no_reasons,
         }.with(&mut buf));
         return buf;
     }
@@ -489,6 +1145,10 @@ mod test {
             version: Version::Http11,
             body: Body::Head,
             close: close,
+            auto: AutoHeaders::default(),
+            te: TrailerPolicy::Allow,
+            errors: ErrorFormat::Html,
+            reasons: no_reasons,
         }.with(&mut buf));
         return buf;
     }
@@ -509,7 +1169,8 @@ mod test {
             msg.add_length(0).unwrap();
             msg.done_headers().unwrap();
             msg.done();
-        })[..], "HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes());
+        })[..], "HTTP/1.0 200 OK\r\nConnection: keep-alive\r\n\
+                  Content-Length: 0\r\n\r\n".as_bytes());
     }
 
     #[test]
@@ -522,6 +1183,30 @@ mod test {
         })[..], "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".as_bytes());
     }
 
+    #[test]
+    fn body_parts_fixed() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            let parts = [&b"Hello, "[..], &b""[..], &b"world!"[..]];
+            msg.write_body_parts(parts.iter().cloned());
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n",
+                        "Hello, world!").as_bytes());
+    }
+
+    #[test]
+    fn body_parts_chunked() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_chunked().unwrap();
+            let parts = [&b"Hello, "[..], &b""[..], &b"world!"[..]];
+            msg.write_body_parts(parts.iter().cloned());
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n",
+                        "\r\n7\r\nHello, \r\n6\r\nworld!\r\n0\r\n\r\n")
+                .as_bytes());
+    }
+
     #[test]
     fn close_response11() {
         assert_eq!(&do_response11(true, |mut msg| {
@@ -565,4 +1250,221 @@ mod test {
             msg.done();
         })[..], "HTTP/1.1 142 Foo\r\n\r\n".as_bytes());
     }
+
+    #[test]
+    fn auto_headers_first() {
+        let mut buf = Buf::new();
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap();
+        {
+            let mut msg: Message = MessageState::ResponseStart {
+                version: Version::Http11,
+                body: Body::Normal,
+                close: false,
+                auto: AutoHeaders { date: Some(date), server: true },
+                te: TrailerPolicy::Allow,
+                errors: ErrorFormat::Html,
+                reasons: no_reasons,
+            }.with(&mut buf);
+            msg.response_status(200, "OK");
+            msg.add_header("X-Test", b"1").unwrap();
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        }
+        let text = String::from_utf8(buf[..].to_vec()).unwrap();
+        let lines = text.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines[0], "HTTP/1.1 200 OK");
+        assert_eq!(lines[1], format!("Server: {}", SERVER_NAME));
+        assert_eq!(lines[2], "X-Test: 1");
+        assert_eq!(lines[3], "Content-Length: 0");
+        assert_eq!(lines[4], "Date: Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(lines[5], "");
+    }
+
+    #[test]
+    fn auto_date_overridden() {
+        let mut buf = Buf::new();
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap();
+        {
+            let mut msg: Message = MessageState::ResponseStart {
+                version: Version::Http11,
+                body: Body::Normal,
+                close: false,
+                auto: AutoHeaders { date: Some(date), server: false },
+                te: TrailerPolicy::Allow,
+                errors: ErrorFormat::Html,
+                reasons: no_reasons,
+            }.with(&mut buf);
+            msg.response_status(200, "OK");
+            msg.add_header("date", b"Thu, 01 Jan 1970 00:00:00 GMT")
+                .unwrap();
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "date: Thu, 01 Jan 1970 00:00:00 GMT\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn own_date() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.add_header("date", b"Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+            msg.add_length(0).unwrap();
+            msg.done_headers().unwrap();
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+            "date: Sun, 06 Nov 1994 08:49:37 GMT\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn auto_framing_small() {
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.auto_framing().unwrap();
+            msg.add_header("Content-Type", b"text/plain").unwrap();
+            assert!(msg.done_headers().unwrap());
+            msg.write_body(b"Hello");
+            msg.write_body(b" world!");
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Content-Type: text/plain\r\n",
+                        "Content-Length: 12\r\n\r\n",
+                        "Hello world!").as_bytes());
+    }
+
+    #[test]
+    fn auto_framing_head() {
+        assert_eq!(&do_head_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.auto_framing().unwrap();
+            assert!(!msg.done_headers().unwrap());
+            msg.write_body(b"Hello world!");
+            msg.done();
+        })[..], concat!("HTTP/1.1 200 OK\r\n",
+                        "Content-Length: 12\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn auto_framing_large() {
+        let chunk = vec![b'x'; AUTO_FRAMING_THRESHOLD];
+        let buf = do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.auto_framing().unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(&chunk);
+            msg.write_body(b"yz");
+            msg.write_body(b"end");
+            msg.done();
+        });
+        let mut expected = Vec::new();
+        expected.extend(concat!("HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: chunked\r\n\r\n",
+            "4002\r\n").as_bytes());
+        expected.extend(&chunk[..]);
+        expected.extend(b"yz\r\n3\r\nend\r\n0\r\n\r\n");
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn auto_framing_length_conflict() {
+        do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.auto_framing().unwrap();
+            assert!(matches!(msg.add_length(1),
+                             Err(HeaderError::AutoFraming)));
+            assert!(matches!(msg.add_chunked(),
+                             Err(HeaderError::AutoFraming)));
+        });
+    }
+
+    #[test]
+    fn test_body_etag() {
+        assert_eq!(body_etag(b""), "\"cbf29ce484222325\"");
+        assert_eq!(body_etag(b"a"), "\"af63dc4c8601ec8c\"");
+        assert_eq!(body_etag(b"hello"), body_etag(b"hello"));
+        assert!(body_etag(b"hello") != body_etag(b"hellO"));
+    }
+
+    #[test]
+    fn auto_etag() {
+        let response = |body: &'static [u8]| do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.auto_etag().unwrap();
+            msg.done_headers().unwrap();
+            msg.write_body(body);
+            msg.done();
+        });
+        let expected = format!("HTTP/1.1 200 OK\r\nETag: {}\r\n\
+                                Content-Length: 5\r\n\r\nhello",
+                               body_etag(b"hello"));
+        assert_eq!(&response(b"hello")[..], expected.as_bytes());
+        assert_eq!(&response(b"hello")[..], &response(b"hello")[..]);
+        assert!(&response(b"hello")[..] != &response(b"world")[..]);
+    }
+
+    #[test]
+    fn raw_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        assert_eq!(&do_response11(false, |mut msg| {
+            msg.send_raw(raw);
+            assert!(msg.is_complete());
+            msg.done();
+        })[..], &raw[..]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn raw_response_after_status() {
+        do_response11(false, |mut msg| {
+            msg.response_status(200, "OK");
+            msg.send_raw(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        });
+    }
+
+    #[cfg(feature="gzip")]
+    #[test]
+    fn gzip_request() {
+        use std::io::Read;
+        use flate2::read::GzDecoder;
+        use httparse::{parse_chunk_size, Status};
+
+        let buf = do_request(|mut msg| {
+            msg.request_line("POST", "/", Version::Http11);
+            msg.enable_gzip().unwrap();
+            msg.done_headers().unwrap();
+            for _ in 0..1000 {
+                msg.write_body(b"Hello rotor-http! ");
+            }
+            msg.done();
+        });
+        let hdr = "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\n\
+                   Transfer-Encoding: chunked\r\n\r\n";
+        assert!(buf[..].starts_with(hdr.as_bytes()));
+        // de-chunk the body as a server would do
+        let mut data = &buf[hdr.len()..];
+        let mut body = Vec::new();
+        loop {
+            let (off, size) = match parse_chunk_size(data).unwrap() {
+                Status::Complete(pair) => pair,
+                Status::Partial => panic!("partial chunk"),
+            };
+            if size == 0 {
+                assert_eq!(&data[off..], b"\r\n");
+                break;
+            }
+            let end = off + size as usize;
+            body.extend(&data[off..end]);
+            assert_eq!(&data[end..end+2], b"\r\n");
+            data = &data[end+2..];
+        }
+        assert!(body.len() < 18000);
+        let mut text = String::new();
+        GzDecoder::new(&body[..]).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, (0..1000).map(|_| "Hello rotor-http! ")
+                                  .collect::<String>());
+    }
 }
@@ -0,0 +1,34 @@
+//! Commonly used values of the `Content-Type` header
+//!
+//! Textual types include `charset=utf-8`, as browsers may guess another
+//! encoding otherwise. See `Response::content_type`.
+
+/// HTML document
+pub const TEXT_HTML: &'static str = "text/html; charset=utf-8";
+/// Plain text
+pub const TEXT_PLAIN: &'static str = "text/plain; charset=utf-8";
+/// CSS stylesheet
+pub const TEXT_CSS: &'static str = "text/css; charset=utf-8";
+/// JavaScript source (RFC 9239)
+pub const TEXT_JAVASCRIPT: &'static str = "text/javascript; charset=utf-8";
+/// Comma-separated values
+pub const TEXT_CSV: &'static str = "text/csv; charset=utf-8";
+/// Server-sent events stream (always utf-8, no charset is allowed)
+pub const TEXT_EVENT_STREAM: &'static str = "text/event-stream";
+/// JSON document (always utf-8, no charset parameter is defined)
+pub const APPLICATION_JSON: &'static str = "application/json";
+/// XML document
+pub const APPLICATION_XML: &'static str = "application/xml";
+/// Arbitrary binary data
+pub const APPLICATION_OCTET_STREAM: &'static str = "application/octet-stream";
+/// HTML form data
+pub const APPLICATION_WWW_FORM_URLENCODED: &'static str =
+    "application/x-www-form-urlencoded";
+/// PNG image
+pub const IMAGE_PNG: &'static str = "image/png";
+/// JPEG image
+pub const IMAGE_JPEG: &'static str = "image/jpeg";
+/// GIF image
+pub const IMAGE_GIF: &'static str = "image/gif";
+/// SVG image
+pub const IMAGE_SVG: &'static str = "image/svg+xml";
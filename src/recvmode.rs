@@ -20,7 +20,9 @@ pub enum RecvMode {
     /// in advance. Note this is just an upper limit it's neither buffer size
     /// nor *minimum* size of the body.
     ///
-    /// Note the buffer size is asserted on if it's bigger than max buffer size
+    /// The server rejects the request body larger than the limit with
+    /// `413 Payload Too Large`. For `Content-Length` bodies this happens
+    /// before reading the body.
     Buffered(usize),
     /// Fetch data chunk-by-chunk.
     ///
@@ -31,4 +33,31 @@ pub enum RecvMode {
     /// request body as a persistent connection for sending multiple messages
     /// on-demand)
     Progressive(usize),
+    /// Fetch data in fixed-size frames (frame size, maximum number of frames)
+    ///
+    /// Every `request_chunk` receives exactly one frame, except the last one
+    /// which may be shorter. Partial frames are buffered internally. This
+    /// is useful for record-oriented binary protocols tunneled over HTTP.
+    ///
+    /// The body larger than `frame_size * max_frames` is rejected with
+    /// `413 Payload Too Large`. Zero frame size is a bug in the handler, the
+    /// server replies with `500 Internal Server Error`.
+    ///
+    /// Only supported by the server for now. The client closes the
+    /// connection and calls `bad_response` with
+    /// `ResponseError::UnsupportedRecvMode`.
+    Frames(usize, usize),
+    /// Switch the connection to another protocol
+    ///
+    /// May only be returned for requests with `BodyKind::Upgrade`. The
+    /// handler is expected to write a complete response (usually
+    /// `101 Switching Protocols`) in `headers_received`. After that HTTP
+    /// framing is not used anymore: all received bytes, including ones
+    /// that arrived together with the headers, are passed to
+    /// `request_chunk` as is, and `request_end` is called when the peer
+    /// closes the connection. Use `Response::write_raw` to send data back.
+    ///
+    /// Only supported by the server, the client treats it like
+    /// `Frames`.
+    Upgrade,
 }
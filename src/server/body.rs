@@ -14,6 +14,26 @@ pub enum BodyKind {
     ///
     /// The size of the message body is not yet known.
     Chunked,
-    /// Reserved for future usage.
+    /// The request asks to switch protocols and has no body
+    ///
+    /// This is a request with `Connection: Upgrade` and `Upgrade` headers
+    /// (e.g. WebSocket handshake), or a `CONNECT` request. Return
+    /// `RecvMode::Upgrade` to take over the connection, with any other mode
+    /// the request is processed as a usual one with an empty body.
     Upgrade,
 }
+
+/// The framing of the request body as sent by the client
+///
+/// Unlike `BodyKind` it distinguishes between the request without body
+/// length headers and the request with `Content-Length: 0`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Framing {
+    /// Neither `Content-Length` nor `Transfer-Encoding` is used
+    None,
+    /// The body length is specified with `Content-Length` header
+    ContentLength(u64),
+    /// The body uses chunked `Transfer-Encoding`, so the size of the body
+    /// was unknown when headers were sent
+    Chunked,
+}
@@ -0,0 +1,523 @@
+//! Streaming of the request body to a worker thread
+//!
+//! The event loop must not block, so a handler which does expensive
+//! processing of the request body (e.g. parsing or compression) should
+//! move it to a separate thread. `BodyChannel` pushes chunks of a
+//! progressive request body into a bounded channel, and the worker drains
+//! it with `BodyReceiver::recv`. When the channel is full, reading of the
+//! body is paused until the worker catches up, so the memory used by
+//! a single request is bounded by the channel size.
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use std::sync::mpsc::{TrySendError, RecvError};
+use std::time::Duration;
+
+use httparse::Header;
+use rotor::{Scope, Time, Notifier};
+use rotor::mio::tcp::TcpStream;
+
+use recvmode::RecvMode;
+use message::AutoHeaders;
+use super::{Server, Head, Response, HttpError, RequestId, HeaderDecision};
+use super::ClientLimit;
+
+
+enum Item {
+    Chunk(Vec<u8>),
+    End,
+}
+
+/// A server which may offload request body to a `BodyChannel`
+pub trait BodyWorker: Server {
+    /// Maximum number of chunks queued in the channel
+    ///
+    /// Default is 16
+    fn channel_size(&self, _scope: &mut Scope<Self::Context>) -> usize {
+        16
+    }
+    /// Called with the receiving side of the channel
+    ///
+    /// It's called right after `headers_received`, when it returns
+    /// `Progressive` or `Frames` mode. Send the receiver to a worker
+    /// thread (or a thread pool) here.
+    ///
+    /// In this case `request_chunk` of the handler is never called, all
+    /// chunks are sent to the channel instead. `request_end` is called as
+    /// usual when the whole body is read from the network, which is
+    /// probably earlier than the worker receives the last chunk.
+    fn start_worker(&mut self, body: BodyReceiver,
+        scope: &mut Scope<Self::Context>);
+}
+
+/// The receiving side of the request body channel
+///
+/// It's `Send`, so can be moved to another thread.
+pub struct BodyReceiver {
+    rx: Receiver<Item>,
+    blocked: Arc<AtomicBool>,
+    notifier: Notifier,
+}
+
+struct BodySender {
+    tx: SyncSender<Item>,
+    blocked: Arc<AtomicBool>,
+    queue: VecDeque<Item>,
+}
+
+enum ChannelState<I> {
+    Streaming(I, BodySender),
+    Passthrough(I),
+}
+
+/// A server which sends request body to a worker thread
+///
+/// Body of the requests for which inner handler returns `Progressive`
+/// or `Frames` mode is sent to the `BodyReceiver` passed to
+/// `BodyWorker::start_worker`. Other requests are processed by the inner
+/// handler as usual.
+///
+/// Every time the worker frees space in the full channel, the connection
+/// is woken up to continue reading, and `wakeup` of the inner handler is
+/// called too. So the inner handler must be prepared to receive
+/// a `wakeup` when there is nothing to do yet. The usual way to send the
+/// response is to put the result of the worker in some shared place and
+/// wake up the connection with a `Notifier` too.
+pub struct BodyChannel<I>(ChannelState<I>);
+
+impl BodyReceiver {
+    /// Receives the next chunk of the body, blocking if necessary
+    ///
+    /// Returns `Ok(None)` when the whole body is received, and an error
+    /// if the request is aborted (e.g. by a timeout or a connection
+    /// close) before that.
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>, RecvError> {
+        let item = try!(self.rx.recv());
+        if self.blocked.swap(false, Ordering::SeqCst) {
+            // Fails only if the main loop is shutting down, in which case
+            // nobody is waiting for the wakeup anyway
+            self.notifier.wakeup().ok();
+        }
+        match item {
+            Item::Chunk(data) => Ok(Some(data)),
+            Item::End => Ok(None),
+        }
+    }
+}
+
+impl BodySender {
+    fn try_send(&self, item: Item) -> Result<(), TrySendError<Item>> {
+        match self.tx.try_send(item) {
+            Err(TrySendError::Full(item)) => {
+                self.blocked.store(true, Ordering::SeqCst);
+                // The worker might have drained the channel before it
+                // noticed the flag, so nobody would wake us up
+                self.tx.try_send(item)
+            }
+            res => res,
+        }
+    }
+    /// Sends queued items, returns `false` if the receiver is gone
+    fn flush(&mut self, response: &mut Response) -> bool {
+        while let Some(item) = self.queue.pop_front() {
+            match self.try_send(item) {
+                Ok(()) => {}
+                Err(TrySendError::Full(item)) => {
+                    self.queue.push_front(item);
+                    response.pause_body_read();
+                    return true;
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        response.resume_body_read();
+        true
+    }
+    fn send(&mut self, item: Item, response: &mut Response) -> bool {
+        self.queue.push_back(item);
+        self.flush(response)
+    }
+}
+
+impl<I: BodyWorker> BodyChannel<I> {
+    fn wrap<F>(self, fun: F) -> Option<Self>
+        where F: FnOnce(I) -> Option<I>
+    {
+        match self.0 {
+            ChannelState::Streaming(i, tx) => {
+                fun(i).map(|i| BodyChannel(ChannelState::Streaming(i, tx)))
+            }
+            ChannelState::Passthrough(i) => {
+                fun(i).map(|i| BodyChannel(ChannelState::Passthrough(i)))
+            }
+        }
+    }
+}
+
+impl<I: BodyWorker> Server for BodyChannel<I> {
+    type Context = I::Context;
+    type Seed = I::Seed;
+    fn headers_received(seed: Self::Seed, head: Head, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        I::headers_received(seed, head, response, scope)
+        .map(|(mut inner, mode, deadline)| {
+            match mode {
+                RecvMode::Progressive(_) | RecvMode::Frames(..) => {
+                    let size = inner.channel_size(scope);
+                    let (tx, rx) = sync_channel(size);
+                    let blocked = Arc::new(AtomicBool::new(false));
+                    inner.start_worker(BodyReceiver {
+                        rx: rx,
+                        blocked: blocked.clone(),
+                        notifier: scope.notifier(),
+                    }, scope);
+                    let sender = BodySender {
+                        tx: tx,
+                        blocked: blocked,
+                        queue: VecDeque::new(),
+                    };
+                    (BodyChannel(ChannelState::Streaming(inner, sender)),
+                     mode, deadline)
+                }
+                _ => {
+                    (BodyChannel(ChannelState::Passthrough(inner)),
+                     mode, deadline)
+                }
+            }
+        })
+    }
+    fn request_received(self, data: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.request_received(data, response, scope))
+    }
+    fn body_received(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.body_received(response, scope))
+    }
+    fn request_chunk(self, chunk: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            ChannelState::Streaming(i, mut tx) => {
+                if !tx.send(Item::Chunk(chunk.to_vec()), response) {
+                    // worker is gone, no way to process the request
+                    return None;
+                }
+                Some(BodyChannel(ChannelState::Streaming(i, tx)))
+            }
+            // upgraded connection
+            ChannelState::Passthrough(i) => {
+                i.request_chunk(chunk, response, scope)
+                    .map(|i| BodyChannel(ChannelState::Passthrough(i)))
+            }
+        }
+    }
+    fn request_end(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            ChannelState::Streaming(i, mut tx) => {
+                if !tx.send(Item::End, response) {
+                    return None;
+                }
+                i.request_end(response, scope)
+                    .map(|i| BodyChannel(ChannelState::Streaming(i, tx)))
+            }
+            ChannelState::Passthrough(i) => {
+                i.request_end(response, scope)
+                    .map(|i| BodyChannel(ChannelState::Passthrough(i)))
+            }
+        }
+    }
+    fn chunk_started(self, chunk_len: u64, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.chunk_started(chunk_len, response, scope))
+    }
+    fn request_trailers(self, trailers: &[Header],
+        response: &mut Response, scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.request_trailers(trailers, response, scope))
+    }
+    fn bad_request(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+    {
+        // Dropping the sender notifies the worker
+        match self.0 {
+            ChannelState::Streaming(i, _) | ChannelState::Passthrough(i) => {
+                i.bad_request(response, scope)
+            }
+        }
+    }
+    fn timeout(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Time)>
+    {
+        match self.0 {
+            ChannelState::Streaming(i, tx) => {
+                i.timeout(response, scope).map(|(i, tm)| {
+                    (BodyChannel(ChannelState::Streaming(i, tx)), tm)
+                })
+            }
+            ChannelState::Passthrough(i) => {
+                i.timeout(response, scope).map(|(i, tm)| {
+                    (BodyChannel(ChannelState::Passthrough(i)), tm)
+                })
+            }
+        }
+    }
+    fn wakeup(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            ChannelState::Streaming(i, mut tx) => {
+                if !tx.flush(response) {
+                    return None;
+                }
+                i.wakeup(response, scope)
+                    .map(|i| BodyChannel(ChannelState::Streaming(i, tx)))
+            }
+            ChannelState::Passthrough(i) => {
+                i.wakeup(response, scope)
+                    .map(|i| BodyChannel(ChannelState::Passthrough(i)))
+            }
+        }
+    }
+    fn emit_error_page(code: &HttpError, response: &mut Response,
+        seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+    {
+        I::emit_error_page(code, response, seed, scope)
+    }
+    fn reason_phrase(code: u16) -> Option<&'static str> {
+        I::reason_phrase(code)
+    }
+    fn error_page_footer(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<String>
+    {
+        I::error_page_footer(seed, scope)
+    }
+    fn idle_timeout(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::idle_timeout(seed, scope)
+    }
+    fn header_byte_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::header_byte_timeout(seed, scope)
+    }
+    fn send_response_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::send_response_timeout(seed, scope)
+    }
+    fn max_request_duration(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Duration>
+    {
+        I::max_request_duration(seed, scope)
+    }
+    fn add_date_header(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::add_date_header(seed, scope)
+    }
+    fn auto_headers(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> AutoHeaders
+    {
+        I::auto_headers(seed, scope)
+    }
+    fn catch_panics(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::catch_panics(seed, scope)
+    }
+    fn size_hint_header(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<&'static str>
+    {
+        I::size_hint_header(seed, scope)
+    }
+    fn request_id(seed: &Self::Seed, head: &Head,
+        scope: &mut Scope<Self::Context>)
+        -> Option<RequestId>
+    {
+        I::request_id(seed, head, scope)
+    }
+    fn max_duplicate_headers(seed: &Self::Seed, name: &str,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_duplicate_headers(seed, name, scope)
+    }
+    fn max_header_value_size(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_header_value_size(seed, scope)
+    }
+    fn configure_socket(seed: &Self::Seed, sock: &TcpStream,
+        scope: &mut Scope<Self::Context>)
+    {
+        I::configure_socket(seed, sock, scope)
+    }
+    fn max_chunks(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<usize>
+    {
+        I::max_chunks(seed, scope)
+    }
+    fn max_pipeline_depth(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_pipeline_depth(seed, scope)
+    }
+    fn client_limit(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Option<ClientLimit>
+    {
+        I::client_limit(seed, scope)
+    }
+    fn strict_line_endings(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::strict_line_endings(seed, scope)
+    }
+    fn strict_framing(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::strict_framing(seed, scope)
+    }
+    fn reject_obs_fold(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::reject_obs_fold(seed, scope)
+    }
+    fn inspect_headers(seed: &Self::Seed, headers: &[Header],
+        scope: &mut Scope<Self::Context>)
+        -> HeaderDecision
+    {
+        I::inspect_headers(seed, headers, scope)
+    }
+    fn rewrite_path<'x>(seed: &Self::Seed, path: &'x str,
+        scope: &mut Scope<Self::Context>)
+        -> Cow<'x, str>
+    {
+        I::rewrite_path(seed, path, scope)
+    }
+    fn allowed_methods(seed: &Self::Seed, path: &str,
+        scope: &mut Scope<Self::Context>)
+        -> Vec<String>
+    {
+        I::allowed_methods(seed, path, scope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use rotor_test::{MemIo, MockLoop};
+    use rotor_stream::{Stream, Accepted};
+    use rotor::{Scope, Time, EventSet, Machine};
+    use super::{BodyChannel, BodyWorker, BodyReceiver};
+    use super::super::{Server, Head, Response, RecvMode, Parser};
+
+    #[derive(Default)]
+    struct Context {
+        receiver: Option<BodyReceiver>,
+        ended: bool,
+        wakeups: usize,
+    }
+
+    struct Worker;
+
+    impl Server for Worker {
+        type Seed = ();
+        type Context = Context;
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Worker, RecvMode::Progressive(1),
+                  scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.ended = true;
+            Some(Worker)
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<(Self, Time)>
+        { unreachable!(); }
+        fn wakeup(self, _response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.wakeups += 1;
+            Some(Worker)
+        }
+    }
+
+    impl BodyWorker for Worker {
+        fn channel_size(&self, _scope: &mut Scope<Context>) -> usize {
+            1
+        }
+        fn start_worker(&mut self, body: BodyReceiver,
+            scope: &mut Scope<Context>)
+        {
+            scope.receiver = Some(body);
+        }
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 10\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<BodyChannel<Worker>, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        io.push_bytes("world".as_bytes());
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // the body is read from the network, but only the first chunk
+        // fits the channel, the rest is queued
+        assert!(lp.ctx().ended);
+        let mut rx = lp.scope(1).receiver.take().unwrap();
+        assert_eq!(rx.recv(), Ok(Some(b"hello".to_vec())));
+        let m = m.wakeup(&mut lp.scope(1)).expect_machine();
+        assert_eq!(lp.ctx().wakeups, 1);
+        assert_eq!(rx.recv(), Ok(Some(b"world".to_vec())));
+        m.wakeup(&mut lp.scope(1)).expect_machine();
+        assert_eq!(lp.ctx().wakeups, 2);
+        assert_eq!(rx.recv(), Ok(None));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+
+/// Counts active connections per client IP and caps them
+///
+/// Put this into the `Context` (it's cheap to clone, all clones share the
+/// same counters, so it works with the threaded server too) and return it
+/// from `Server::client_limit`. Connections from an address which already
+/// has `max` active connections are answered with
+/// `429 Too Many Requests` and closed.
+#[derive(Debug, Clone)]
+pub struct ClientLimit {
+    max: usize,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// A connection counted in the `ClientLimit`
+///
+/// The counter is decremented when the slot is dropped, i.e. when the
+/// connection is closed.
+#[derive(Debug)]
+pub struct ClientSlot {
+    ip: IpAddr,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ClientLimit {
+    /// Create a limit of `max` concurrent connections per client IP
+    pub fn new(max: usize) -> ClientLimit {
+        ClientLimit {
+            max: max,
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Count a new connection from `ip`
+    ///
+    /// Returns `None` if there are already `max` connections from the
+    /// address.
+    pub fn acquire(&self, ip: IpAddr) -> Option<ClientSlot> {
+        let mut active = self.active.lock().unwrap();
+        // Check before inserting, so refused addresses don't stay in the map
+        if active.get(&ip).map(|x| *x).unwrap_or(0) >= self.max {
+            return None;
+        }
+        *active.entry(ip).or_insert(0) += 1;
+        Some(ClientSlot { ip: ip, active: self.active.clone() })
+    }
+    /// Returns the number of active connections from `ip`
+    pub fn active(&self, ip: IpAddr) -> usize {
+        self.active.lock().unwrap().get(&ip).map(|x| *x).unwrap_or(0)
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        let last = match active.get_mut(&self.ip) {
+            Some(num) => {
+                *num -= 1;
+                *num == 0
+            }
+            None => false,
+        };
+        if last {
+            active.remove(&self.ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use super::ClientLimit;
+
+    #[test]
+    fn test_same_address() {
+        let limit = ClientLimit::new(2);
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let first = limit.acquire(ip).unwrap();
+        let second = limit.clone().acquire(ip).unwrap();
+        assert!(limit.acquire(ip).is_none());
+        assert_eq!(limit.active(ip), 2);
+        let third = limit.acquire(other).unwrap();
+        drop(first);
+        assert_eq!(limit.active(ip), 1);
+        let fourth = limit.acquire(ip).unwrap();
+        assert!(limit.acquire(ip).is_none());
+        drop(second);
+        drop(fourth);
+        drop(third);
+        assert_eq!(limit.active(ip), 0);
+        assert_eq!(limit.active(other), 0);
+    }
+
+    #[test]
+    fn test_zero_max() {
+        let limit = ClientLimit::new(0);
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(limit.acquire(ip).is_none());
+        assert!(limit.acquire(ip).is_none());
+        assert!(limit.active.lock().unwrap().is_empty());
+    }
+}
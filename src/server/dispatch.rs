@@ -0,0 +1,431 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor::mio::tcp::TcpStream;
+
+use recvmode::RecvMode;
+use message::AutoHeaders;
+use super::{Server, Head, Response, HttpError, RequestId, HeaderDecision};
+use super::ClientLimit;
+
+
+/// The handler chosen for the request, see `Select`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    First,
+    Second,
+}
+
+/// Chooses which of the servers composed by `Dispatch` handles a request
+pub trait Select {
+    type Context;
+    /// Called for each request when headers are received
+    fn select(head: &Head, scope: &mut Scope<Self::Context>) -> Choice;
+}
+
+enum Handler<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// A server which dispatches each request to one of two servers
+///
+/// The handler is chosen by `S` for every request, so requests of a single
+/// keep-alive connection may be served by different handlers. Nest the
+/// type (e.g. `Dispatch<S, A, Dispatch<T, B, C>>`) to compose more servers.
+///
+/// The seed is a pair of seeds of the inner servers. Settings which are
+/// needed before the request is dispatched (timeouts, limits, error pages
+/// and so on) are taken from the first server.
+pub struct Dispatch<S, A, B>(Handler<A, B>, PhantomData<S>);
+
+impl<S, A, B> Dispatch<S, A, B> {
+    fn first(a: A) -> Self {
+        Dispatch(Handler::First(a), PhantomData)
+    }
+    fn second(b: B) -> Self {
+        Dispatch(Handler::Second(b), PhantomData)
+    }
+}
+
+impl<S, A, B> Server for Dispatch<S, A, B>
+    where S: Select<Context=A::Context>,
+          A: Server,
+          B: Server<Context=A::Context>,
+{
+    type Context = A::Context;
+    type Seed = (A::Seed, B::Seed);
+    fn headers_received(seed: Self::Seed, head: Head, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        match S::select(&head, scope) {
+            Choice::First => {
+                A::headers_received(seed.0, head, response, scope)
+                .map(|(a, mode, dline)| (Dispatch::first(a), mode, dline))
+            }
+            Choice::Second => {
+                B::headers_received(seed.1, head, response, scope)
+                .map(|(b, mode, dline)| (Dispatch::second(b), mode, dline))
+            }
+        }
+    }
+    fn request_received(self, data: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.request_received(data, response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.request_received(data, response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn body_received(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.body_received(response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.body_received(response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn bad_request(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+    {
+        match self.0 {
+            Handler::First(a) => a.bad_request(response, scope),
+            Handler::Second(b) => b.bad_request(response, scope),
+        }
+    }
+    fn request_chunk(self, chunk: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.request_chunk(chunk, response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.request_chunk(chunk, response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn request_end(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.request_end(response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.request_end(response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn chunk_started(self, chunk_len: u64, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.chunk_started(chunk_len, response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.chunk_started(chunk_len, response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn request_trailers(self, trailers: &[Header],
+        response: &mut Response, scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.request_trailers(trailers, response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => {
+                b.request_trailers(trailers, response, scope)
+                .map(Dispatch::second)
+            }
+        }
+    }
+    fn timeout(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Time)>
+    {
+        match self.0 {
+            Handler::First(a) => a.timeout(response, scope)
+                .map(|(a, tm)| (Dispatch::first(a), tm)),
+            Handler::Second(b) => b.timeout(response, scope)
+                .map(|(b, tm)| (Dispatch::second(b), tm)),
+        }
+    }
+    fn wakeup(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            Handler::First(a) => a.wakeup(response, scope)
+                .map(Dispatch::first),
+            Handler::Second(b) => b.wakeup(response, scope)
+                .map(Dispatch::second),
+        }
+    }
+    fn emit_error_page(code: &HttpError, response: &mut Response,
+        seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+    {
+        A::emit_error_page(code, response, &seed.0, scope)
+    }
+    fn reason_phrase(code: u16) -> Option<&'static str> {
+        A::reason_phrase(code)
+    }
+    fn error_page_footer(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<String>
+    {
+        A::error_page_footer(&seed.0, scope)
+    }
+    fn idle_timeout(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        A::idle_timeout(&seed.0, scope)
+    }
+    fn header_byte_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        A::header_byte_timeout(&seed.0, scope)
+    }
+    fn send_response_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        A::send_response_timeout(&seed.0, scope)
+    }
+    fn max_request_duration(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Duration>
+    {
+        A::max_request_duration(&seed.0, scope)
+    }
+    fn add_date_header(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        A::add_date_header(&seed.0, scope)
+    }
+    fn auto_headers(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> AutoHeaders
+    {
+        A::auto_headers(&seed.0, scope)
+    }
+    fn catch_panics(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        A::catch_panics(&seed.0, scope)
+    }
+    fn size_hint_header(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<&'static str>
+    {
+        A::size_hint_header(&seed.0, scope)
+    }
+    fn request_id(seed: &Self::Seed, head: &Head,
+        scope: &mut Scope<Self::Context>)
+        -> Option<RequestId>
+    {
+        A::request_id(&seed.0, head, scope)
+    }
+    fn max_duplicate_headers(seed: &Self::Seed, name: &str,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        A::max_duplicate_headers(&seed.0, name, scope)
+    }
+    fn max_header_value_size(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        A::max_header_value_size(&seed.0, scope)
+    }
+    fn configure_socket(seed: &Self::Seed, sock: &TcpStream,
+        scope: &mut Scope<Self::Context>)
+    {
+        A::configure_socket(&seed.0, sock, scope)
+    }
+    fn max_chunks(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<usize>
+    {
+        A::max_chunks(&seed.0, scope)
+    }
+    fn max_pipeline_depth(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        A::max_pipeline_depth(&seed.0, scope)
+    }
+    fn client_limit(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Option<ClientLimit>
+    {
+        A::client_limit(&seed.0, scope)
+    }
+    fn strict_line_endings(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        A::strict_line_endings(&seed.0, scope)
+    }
+    fn strict_framing(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        A::strict_framing(&seed.0, scope)
+    }
+    fn reject_obs_fold(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        A::reject_obs_fold(&seed.0, scope)
+    }
+    fn inspect_headers(seed: &Self::Seed, headers: &[Header],
+        scope: &mut Scope<Self::Context>)
+        -> HeaderDecision
+    {
+        A::inspect_headers(&seed.0, headers, scope)
+    }
+    fn rewrite_path<'x>(seed: &Self::Seed, path: &'x str,
+        scope: &mut Scope<Self::Context>)
+        -> Cow<'x, str>
+    {
+        A::rewrite_path(&seed.0, path, scope)
+    }
+    fn allowed_methods(seed: &Self::Seed, path: &str,
+        scope: &mut Scope<Self::Context>)
+        -> Vec<String>
+    {
+        A::allowed_methods(&seed.0, path, scope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use rotor_test::{MemIo, MockLoop};
+    use rotor_stream::{Stream, Accepted};
+    use rotor::{Scope, Time, EventSet, Machine};
+    use super::{Dispatch, Select, Choice};
+    use super::super::{Server, Head, Response, RecvMode, Parser};
+
+    #[derive(Debug, PartialEq, Eq, Default)]
+    struct Context {
+        served: Vec<String>,
+    }
+
+    struct ByPath;
+
+    impl Select for ByPath {
+        type Context = Context;
+        fn select(head: &Head, _scope: &mut Scope<Context>) -> Choice {
+            if head.path_only().starts_with("/api/") {
+                Choice::Second
+            } else {
+                Choice::First
+            }
+        }
+    }
+
+    struct Page(String);
+
+    impl Server for Page {
+        type Seed = ();
+        type Context = Context;
+        fn headers_received((): (), head: Head, _response: &mut Response,
+            scope: &mut Scope<Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Page(head.path.to_string()), RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.served.push(format!("page {}", self.0));
+            response.send_body(200, "OK", b"text/html", b"<p>hello</p>");
+            None
+        }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<(Self, Time)>
+        { unreachable!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+    }
+
+    struct Api(Vec<u8>);
+
+    impl Server for Api {
+        type Seed = &'static str;
+        type Context = Context;
+        fn headers_received(_seed: &'static str, _head: Head,
+            _response: &mut Response, scope: &mut Scope<Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            Some((Api(Vec::new()), RecvMode::Progressive(1),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+        fn request_chunk(mut self, chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        {
+            self.0.extend(chunk);
+            Some(self)
+        }
+        fn request_end(self, response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.served.push(format!("api {}",
+                String::from_utf8(self.0).unwrap()));
+            response.send_body(200, "OK", b"application/json", b"{}");
+            None
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<(Self, Time)>
+        { unreachable!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+    }
+
+    type Both = Dispatch<ByPath, Page, Api>;
+
+    #[test]
+    fn test_keep_alive() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Context::default());
+        io.push_bytes("GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                       POST /api/items HTTP/1.1\r\nHost: example.com\r\n\
+                       Content-Length: 7\r\n\r\n{\"x\":1}\
+                       GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Both, MemIo>>::accepted(
+            io.clone(), ((), "v1"), &mut lp.scope(1)).expect_machine();
+        // the connection is kept open after all three requests
+        m.ready(EventSet::readable() | EventSet::writable(),
+            &mut lp.scope(1)).expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            served: vec![
+                String::from("page /index.html"),
+                String::from("api {\"x\":1}"),
+                String::from("page /"),
+            ],
+        });
+    }
+}
@@ -1,9 +1,13 @@
+use std::ascii::AsciiExt;
 use std::error::Error;
 use std::str::Utf8Error;
 use std::num::ParseIntError;
 
 use httparse;
 
+use headers;
+use status::reason_phrase;
+
 
 quick_error!{
     /// Error type which is passed to bad_request and emit_error_page
@@ -28,11 +32,44 @@ quick_error!{
             description("error parsing chunk size")
         }
         DuplicateContentLength {
-            description("duplicate `Content-Length` header in request")
+            description("conflicting `Content-Length` values in request")
+        }
+        AmbiguousBodyLength {
+            description("both `Content-Length` and `Transfer-Encoding` \
+                         are present in request")
+        }
+        BareLineFeed {
+            description("request line or header is terminated by bare LF")
+        }
+        ObsoleteLineFolding {
+            description("request contains obsolete line folding in headers")
+        }
+        HeaderValueTooLarge {
+            description("value of a request header is larger than \
+                         `Server::max_header_value_size`")
+        }
+        TooManyDuplicateHeaders {
+            description("too many headers with the same name in request")
+        }
+        DuplicateHost {
+            description("more than one `Host` header in request")
+        }
+        MissingHost {
+            description("HTTP/1.1 request without `Host` header")
         }
         HeadersReceived {
             description("request aborted in `headers_received()` handler")
         }
+        HeadersRejected(code: u16) {
+            description("request rejected by `inspect_headers()` hook")
+            display(me) -> ("{}: {}", me.description(), code)
+        }
+        TooManyConnections {
+            description("too many concurrent connections from the client")
+        }
+        TooManyChunks {
+            description("chunked request body has too many chunks")
+        }
         PayloadTooLarge {
             description("payload is larger than is allowed by server settings")
         }
@@ -48,6 +85,17 @@ quick_error!{
         HandlerTimeout {
             description("timeout happened waiting for handler")
         }
+        HandlerPanic {
+            description("request handler panicked")
+        }
+        UnexpectedUpgrade {
+            description("handler returned `RecvMode::Upgrade` for a request \
+                         which doesn't upgrade the connection")
+        }
+        ZeroFrameSize {
+            description("handler returned `RecvMode::Frames` with zero \
+                         frame size")
+        }
         BadUtf8(err: Utf8Error) {
             from()
             description("bad utf8 in one of the crucial headers")
@@ -57,6 +105,9 @@ quick_error!{
             description("error parsing `Content-Length` header")
             display(me) -> ("{}: {}", me.description(), err)
         }
+        InvalidContentLength {
+            description("`Content-Length` header contains non-digits")
+        }
     }
 }
 
@@ -76,15 +127,152 @@ impl HttpError for RequestError {
             BadHeaders(_) => (400, "Bad Request"),
             BadUtf8(_) => (400, "Bad Request"),
             BadContentLength(_) => (400, "Bad Request"),
+            InvalidContentLength => (400, "Bad Request"),
             InvalidChunkSize(_) => (400, "Bad Request"),
             DuplicateContentLength => (400, "Bad Request"),
+            AmbiguousBodyLength => (400, "Bad Request"),
+            BareLineFeed => (400, "Bad Request"),
+            ObsoleteLineFolding => (400, "Bad Request"),
+            HeaderValueTooLarge => (431, "Request Header Fields Too Large"),
+            TooManyDuplicateHeaders => (431, "Request Header Fields Too Large"),
+            DuplicateHost => (400, "Bad Request"),
+            MissingHost => (400, "Bad Request"),
             HeadersReceived => (400, "Bad Request"),
+            HeadersRejected(code) => (code, reason_phrase(code).unwrap_or("")),
+            TooManyConnections => (429, "Too Many Requests"),
+            TooManyChunks => (400, "Bad Request"),
             PayloadTooLarge => (413, "Payload Too Large"),
             HeadersTimeout => (408, "Request Timeout"),
             RequestTimeout => (408, "Request Timeout"),
             HandlerTimeout => (504, "Gateway Timeout"),
+            HandlerPanic => (500, "Internal Server Error"),
+            UnexpectedUpgrade => (500, "Internal Server Error"),
+            ZeroFrameSize => (500, "Internal Server Error"),
             // This one almost never reaches the destination
             PrematureEndOfStream => (400, "Bad Request"),
         }
     }
 }
+
+/// The format of the error page, see `choose_error_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// A short HTML page, suitable for browsers
+    Html,
+    /// A JSON object with `status` and `reason` fields
+    Json,
+}
+
+// Specificity of the media range matching the `kind/subtype` media type,
+// or `None` if it doesn't match
+fn media_range_matches(range: &[u8], kind: &[u8], subtype: &[u8])
+    -> Option<usize>
+{
+    let slash = match range.iter().position(|&x| x == b'/') {
+        Some(x) => x,
+        None => return None,
+    };
+    let (rkind, rsub) = (&range[..slash], &range[slash+1..]);
+    if rkind == &b"*"[..] && rsub == &b"*"[..] {
+        Some(0)
+    } else if !rkind.eq_ignore_ascii_case(kind) {
+        None
+    } else if rsub == &b"*"[..] {
+        Some(1)
+    } else if rsub.eq_ignore_ascii_case(subtype) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn update_quality(quality: &mut Option<(usize, u16)>,
+    specificity: Option<usize>, q: u16)
+{
+    if let Some(spec) = specificity {
+        match *quality {
+            Some((old, _)) if old >= spec => {}
+            _ => *quality = Some((spec, q)),
+        }
+    }
+}
+
+/// Chooses the format of an error page by the value of `Accept` header
+///
+/// JSON is chosen only if the client prefers `application/json` over
+/// `text/html`. Otherwise, including the cases where there is no `Accept`
+/// header, it's `*/*`, or neither format is acceptable, HTML is chosen.
+/// The most specific media range is used to determine the quality of
+/// each format, items with malformed quality values are ignored.
+pub fn choose_error_format(accept: Option<&[u8]>) -> ErrorFormat {
+    let accept = match accept {
+        Some(x) => x,
+        None => return ErrorFormat::Html,
+    };
+    // (specificity of the media range, quality) for html and json
+    let mut html = None::<(usize, u16)>;
+    let mut json = None::<(usize, u16)>;
+    for item in accept.split(|&x| x == b',') {
+        let (range, q) = match headers::split_quality(item) {
+            Some(pair) => pair,
+            None => continue,
+        };
+        update_quality(&mut html,
+            media_range_matches(range, b"text", b"html"), q);
+        update_quality(&mut json,
+            media_range_matches(range, b"application", b"json"), q);
+    }
+    let html = html.map(|(_, q)| q).unwrap_or(0);
+    let json = json.map(|(_, q)| q).unwrap_or(0);
+    if json > html {
+        ErrorFormat::Json
+    } else {
+        ErrorFormat::Html
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{choose_error_format, ErrorFormat};
+
+    fn format(accept: &str) -> ErrorFormat {
+        choose_error_format(Some(accept.as_bytes()))
+    }
+
+    #[test]
+    fn test_no_accept() {
+        assert_eq!(choose_error_format(None), ErrorFormat::Html);
+        assert_eq!(format(""), ErrorFormat::Html);
+    }
+
+    #[test]
+    fn test_any() {
+        assert_eq!(format("*/*"), ErrorFormat::Html);
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(format("application/json"), ErrorFormat::Json);
+        assert_eq!(format("Application/JSON"), ErrorFormat::Json);
+        assert_eq!(format("application/json, */*;q=0.1"), ErrorFormat::Json);
+    }
+
+    #[test]
+    fn test_browser() {
+        assert_eq!(format("text/html,application/xhtml+xml,\
+                           application/xml;q=0.9,*/*;q=0.8"),
+                   ErrorFormat::Html);
+    }
+
+    #[test]
+    fn test_quality() {
+        assert_eq!(format("text/html;q=0.5, application/json"),
+                   ErrorFormat::Json);
+        assert_eq!(format("text/*;q=0.5, application/*;q=0.9"),
+                   ErrorFormat::Json);
+        assert_eq!(format("application/json;q=0, */*"), ErrorFormat::Html);
+        assert_eq!(format("application/json;q=x, text/html;q=0.1"),
+                   ErrorFormat::Html);
+        assert_eq!(format("image/png"), ErrorFormat::Html);
+    }
+}
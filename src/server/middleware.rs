@@ -0,0 +1,479 @@
+//! Adapters which wrap one `Server` implementation into another
+use std::borrow::Cow;
+use std::time::Duration;
+
+use httparse::Header;
+use rotor::{Scope, Time};
+use rotor::mio::tcp::TcpStream;
+
+use recvmode::RecvMode;
+use message::AutoHeaders;
+use super::{Server, Head, Response, HttpError, RequestId, HeaderDecision};
+use super::ClientLimit;
+
+
+/// A check run over the full request body, see `BodyAuth`
+pub trait BodyCheck: Sized {
+    type Context;
+    /// Called when headers are received, before inner `headers_received`
+    ///
+    /// This is the place to extract what is needed for the check, for
+    /// example a signature header.
+    fn start(head: &Head, scope: &mut Scope<Self::Context>) -> Self;
+
+    /// Maximum size of the body buffered for the check
+    ///
+    /// It's used only if inner handler returns `Progressive` mode. For
+    /// `Buffered` mode the limit returned by the handler is used.
+    fn max_body_size(&self) -> usize {
+        1048576
+    }
+
+    /// Checks the full body of the request
+    ///
+    /// Return `false` to reject the request. In this case inner handler
+    /// never receives the body.
+    fn check(self, body: &[u8], scope: &mut Scope<Self::Context>) -> bool;
+
+    /// Writes a response for the rejected request
+    ///
+    /// Default is an empty `401 Unauthorized` response
+    fn reject(response: &mut Response, _scope: &mut Scope<Self::Context>) {
+        response.status(401, "Unauthorized");
+        response.add_length(0).unwrap();
+        response.done_headers().unwrap();
+        response.done();
+    }
+}
+
+enum AuthState<A, I> {
+    /// Checking body, the mode is one requested by the inner handler
+    Checking(A, I, RecvMode),
+    Passed(I),
+}
+
+/// A server which checks request body before passing it to the inner server
+///
+/// The body is always buffered, then checked by `A`, and when check
+/// succeeds it's passed to the inner server. If inner server requested
+/// `Progressive` mode it receives whole body as a single `request_chunk`
+/// followed by `request_end`. In `Frames` mode the body is split into
+/// frames of the requested size. Data of the upgraded connection
+/// (`RecvMode::Upgrade`) is passed to the inner server unchecked.
+///
+/// This is useful for receivers of signed webhooks, which have to verify
+/// a signature (e.g. HMAC) computed over the whole body.
+pub struct BodyAuth<A, I>(AuthState<A, I>);
+
+impl<A, I> BodyAuth<A, I> {
+    fn wrap<F>(self, fun: F) -> Option<Self>
+        where F: FnOnce(I) -> Option<I>
+    {
+        match self.0 {
+            AuthState::Checking(a, i, p) => {
+                fun(i).map(|i| BodyAuth(AuthState::Checking(a, i, p)))
+            }
+            AuthState::Passed(i) => fun(i).map(|i| BodyAuth(AuthState::Passed(i))),
+        }
+    }
+}
+
+impl<A, I> Server for BodyAuth<A, I>
+    where A: BodyCheck<Context=I::Context>, I: Server
+{
+    type Context = I::Context;
+    type Seed = I::Seed;
+    fn headers_received(seed: Self::Seed, head: Head, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, RecvMode, Time)>
+    {
+        let check = A::start(&head, scope);
+        let limit = check.max_body_size();
+        I::headers_received(seed, head, response, scope)
+        .map(|(inner, mode, deadline)| {
+            let buffered = match mode {
+                RecvMode::Buffered(x) => RecvMode::Buffered(x),
+                RecvMode::Progressive(_) => RecvMode::Buffered(limit),
+                // the parser rejects it before the body is read
+                RecvMode::Frames(0, num) => {
+                    return (BodyAuth(AuthState::Passed(inner)),
+                            RecvMode::Frames(0, num), deadline);
+                }
+                RecvMode::Frames(size, num) => {
+                    RecvMode::Buffered(size.saturating_mul(num))
+                }
+                // there is no body to check
+                RecvMode::Upgrade => {
+                    return (BodyAuth(AuthState::Passed(inner)),
+                            RecvMode::Upgrade, deadline);
+                }
+            };
+            (BodyAuth(AuthState::Checking(check, inner, mode)),
+             buffered, deadline)
+        })
+    }
+    fn request_received(self, data: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            AuthState::Checking(check, inner, mode) => {
+                if !check.check(data, scope) {
+                    A::reject(response, scope);
+                    return None;
+                }
+                let inner = match mode {
+                    RecvMode::Buffered(_) => {
+                        inner.body_received(response, scope)
+                            .and_then(|m| {
+                                m.request_received(data, response, scope)
+                            })
+                    }
+                    RecvMode::Progressive(_) => {
+                        inner.request_chunk(data, response, scope)
+                            .and_then(|m| m.request_end(response, scope))
+                    }
+                    RecvMode::Frames(size, _) => {
+                        let mut inner = Some(inner);
+                        for frame in data.chunks(size) {
+                            inner = inner.and_then(|m| {
+                                m.request_chunk(frame, response, scope)
+                            });
+                        }
+                        inner.and_then(|m| m.request_end(response, scope))
+                    }
+                    RecvMode::Upgrade => unreachable!(),
+                };
+                inner.map(|i| BodyAuth(AuthState::Passed(i)))
+            }
+            AuthState::Passed(..) => unreachable!(),
+        }
+    }
+    fn request_chunk(self, chunk: &[u8], response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            // body is always buffered
+            AuthState::Checking(..) => unreachable!(),
+            // upgraded connection
+            AuthState::Passed(i) => {
+                i.request_chunk(chunk, response, scope)
+                    .map(|i| BodyAuth(AuthState::Passed(i)))
+            }
+        }
+    }
+    fn request_end(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        match self.0 {
+            // body is always buffered
+            AuthState::Checking(..) => unreachable!(),
+            // upgraded connection
+            AuthState::Passed(i) => {
+                i.request_end(response, scope)
+                    .map(|i| BodyAuth(AuthState::Passed(i)))
+            }
+        }
+    }
+    fn request_trailers(self, trailers: &[Header],
+        response: &mut Response, scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.request_trailers(trailers, response, scope))
+    }
+    fn bad_request(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+    {
+        match self.0 {
+            AuthState::Checking(_, i, _) | AuthState::Passed(i) => {
+                i.bad_request(response, scope)
+            }
+        }
+    }
+    fn timeout(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<(Self, Time)>
+    {
+        match self.0 {
+            AuthState::Checking(a, i, p) => {
+                i.timeout(response, scope).map(|(i, tm)| {
+                    (BodyAuth(AuthState::Checking(a, i, p)), tm)
+                })
+            }
+            AuthState::Passed(i) => {
+                i.timeout(response, scope).map(|(i, tm)| {
+                    (BodyAuth(AuthState::Passed(i)), tm)
+                })
+            }
+        }
+    }
+    fn wakeup(self, response: &mut Response,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        self.wrap(|i| i.wakeup(response, scope))
+    }
+    fn emit_error_page(code: &HttpError, response: &mut Response,
+        seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+    {
+        I::emit_error_page(code, response, seed, scope)
+    }
+    fn reason_phrase(code: u16) -> Option<&'static str> {
+        I::reason_phrase(code)
+    }
+    fn error_page_footer(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<String>
+    {
+        I::error_page_footer(seed, scope)
+    }
+    fn idle_timeout(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::idle_timeout(seed, scope)
+    }
+    fn header_byte_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::header_byte_timeout(seed, scope)
+    }
+    fn send_response_timeout(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Duration
+    {
+        I::send_response_timeout(seed, scope)
+    }
+    fn max_request_duration(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<Duration>
+    {
+        I::max_request_duration(seed, scope)
+    }
+    fn add_date_header(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::add_date_header(seed, scope)
+    }
+    fn auto_headers(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> AutoHeaders
+    {
+        I::auto_headers(seed, scope)
+    }
+    fn catch_panics(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::catch_panics(seed, scope)
+    }
+    fn size_hint_header(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<&'static str>
+    {
+        I::size_hint_header(seed, scope)
+    }
+    fn request_id(seed: &Self::Seed, head: &Head,
+        scope: &mut Scope<Self::Context>)
+        -> Option<RequestId>
+    {
+        I::request_id(seed, head, scope)
+    }
+    fn max_duplicate_headers(seed: &Self::Seed, name: &str,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_duplicate_headers(seed, name, scope)
+    }
+    fn max_header_value_size(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_header_value_size(seed, scope)
+    }
+    fn configure_socket(seed: &Self::Seed, sock: &TcpStream,
+        scope: &mut Scope<Self::Context>)
+    {
+        I::configure_socket(seed, sock, scope)
+    }
+    fn max_chunks(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> Option<usize>
+    {
+        I::max_chunks(seed, scope)
+    }
+    fn max_pipeline_depth(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        I::max_pipeline_depth(seed, scope)
+    }
+    fn client_limit(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> Option<ClientLimit>
+    {
+        I::client_limit(seed, scope)
+    }
+    fn strict_line_endings(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::strict_line_endings(seed, scope)
+    }
+    fn strict_framing(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::strict_framing(seed, scope)
+    }
+    fn reject_obs_fold(seed: &Self::Seed,
+        scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        I::reject_obs_fold(seed, scope)
+    }
+    fn inspect_headers(seed: &Self::Seed, headers: &[Header],
+        scope: &mut Scope<Self::Context>)
+        -> HeaderDecision
+    {
+        I::inspect_headers(seed, headers, scope)
+    }
+    fn rewrite_path<'x>(seed: &Self::Seed, path: &'x str,
+        scope: &mut Scope<Self::Context>)
+        -> Cow<'x, str>
+    {
+        I::rewrite_path(seed, path, scope)
+    }
+    fn allowed_methods(seed: &Self::Seed, path: &str,
+        scope: &mut Scope<Self::Context>)
+        -> Vec<String>
+    {
+        I::allowed_methods(seed, path, scope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use std::str::from_utf8;
+    use rotor_test::{MemIo, MockLoop};
+    use rotor_stream::{Stream, Accepted};
+    use rotor::{Scope, Time, EventSet, Machine};
+    use super::{BodyAuth, BodyCheck};
+    use super::super::{Server, Head, Response, RecvMode, Parser};
+
+    #[derive(Debug, PartialEq, Eq, Default)]
+    struct Context {
+        progressive: bool,
+        bodies: Vec<String>,
+        rejected: usize,
+    }
+
+    struct Signature(Vec<u8>);
+
+    impl BodyCheck for Signature {
+        type Context = Context;
+        fn start(head: &Head, _scope: &mut Scope<Context>) -> Signature {
+            Signature(head.headers.iter()
+                .find(|h| h.name == "X-Signature")
+                .map(|h| h.value.to_vec())
+                .unwrap_or(Vec::new()))
+        }
+        fn check(self, body: &[u8], scope: &mut Scope<Context>) -> bool {
+            // a real check would compute an HMAC of the body here
+            let ok = self.0.len() > 0 && body.starts_with(&self.0);
+            if !ok {
+                scope.rejected += 1;
+            }
+            ok
+        }
+    }
+
+    struct Inner;
+
+    impl Server for Inner {
+        type Seed = ();
+        type Context = Context;
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            let mode = if scope.progressive {
+                RecvMode::Progressive(1)
+            } else {
+                RecvMode::Buffered(1000)
+            };
+            Some((Inner, mode, scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, data: &[u8], _response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.bodies.push(from_utf8(data).unwrap().to_string());
+            Some(Inner)
+        }
+        fn request_chunk(self, chunk: &[u8], _response: &mut Response,
+            scope: &mut Scope<Context>) -> Option<Self>
+        {
+            scope.bodies.push(from_utf8(chunk).unwrap().to_string());
+            Some(Inner)
+        }
+        fn request_end(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        {
+            Some(Inner)
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<(Self, Time)>
+        { unreachable!(); }
+        fn wakeup(self, _response: &mut Response,
+            _scope: &mut Scope<Context>) -> Option<Self>
+        { unreachable!(); }
+    }
+
+    type Auth = BodyAuth<Signature, Inner>;
+
+    fn request(ctx: Context, data: &str, expected: Context) {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ctx);
+        io.push_bytes(data.as_bytes());
+        let m = Stream::<Parser<Auth, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), expected);
+    }
+
+    #[test]
+    fn test_accepted() {
+        request(Default::default(),
+            "POST / HTTP/1.1\r\nHost: example.com\r\nX-Signature: abc\r\n\
+             Content-Length: 8\r\n\r\nabcdefgh",
+            Context {
+                progressive: false,
+                bodies: vec![String::from("abcdefgh")],
+                rejected: 0,
+            });
+    }
+
+    #[test]
+    fn test_accepted_progressive() {
+        request(Context { progressive: true, ..Default::default() },
+            "POST / HTTP/1.1\r\nHost: example.com\r\nX-Signature: abc\r\n\
+             Content-Length: 8\r\n\r\nabcdefgh",
+            Context {
+                progressive: true,
+                bodies: vec![String::from("abcdefgh")],
+                rejected: 0,
+            });
+    }
+
+    #[test]
+    fn test_rejected() {
+        request(Default::default(),
+            "POST / HTTP/1.1\r\nHost: example.com\r\nX-Signature: xyz\r\n\
+             Content-Length: 8\r\n\r\nabcdefgh",
+            Context {
+                progressive: false,
+                bodies: vec![],
+                rejected: 1,
+            });
+    }
+}
@@ -8,12 +8,23 @@ pub use rotor_stream::{Accept, Stream};
 
 pub use recvmode::RecvMode;
 pub use version::Version;
-pub use self::body::BodyKind;
+pub use message::{AutoHeaders, AUTO_FRAMING_THRESHOLD, body_etag};
+pub use date::{HttpDate, InvalidDate};
+pub use status::reason_phrase;
+pub use self::body::{BodyKind, Framing};
 pub use self::parser::Parser;
-pub use self::protocol::Server;
-pub use self::request::Head;
+pub use self::protocol::{Server, HeaderDecision};
+pub use self::request::{Head, PathSegments, Method};
 pub use self::response::Response;
 pub use self::error::{RequestError, HttpError};
+pub use self::error::{ErrorFormat, choose_error_format};
+pub use self::router::Router;
+pub use self::dispatch::{Dispatch, Select, Choice};
+pub use self::range::{RangeSpec, parse_range};
+pub use self::request_id::RequestId;
+pub use self::uri::QueryPairs;
+pub use self::client_limit::{ClientLimit, ClientSlot};
+pub use headers::HeaderValues;
 
 mod body;
 mod parser;
@@ -21,6 +32,15 @@ mod protocol;
 mod request;
 mod response;
 mod error;
+mod router;
+mod dispatch;
+mod range;
+mod request_id;
+mod client_limit;
+mod uri;
+pub mod middleware;
+pub mod channel;
+#[cfg(feature="websocket")] pub mod websocket;
 
 
 // TODO(tailhook) MAX_HEADERS_SIZE can be moved to Protocol
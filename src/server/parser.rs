@@ -1,31 +1,100 @@
 use std::any::Any;
+use std::ascii::AsciiExt;
 use std::cmp::min;
 use std::marker::PhantomData;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::str::from_utf8;
 use std::error::Error;
+use std::time::Duration;
+#[cfg(feature="reuse_headers")] use std::cell::RefCell;
+#[cfg(feature="reuse_headers")] use std::mem::{replace, transmute};
 
-use httparse::{EMPTY_HEADER, Request, parse_chunk_size};
+use httparse::{EMPTY_HEADER, Request, Header, parse_chunk_size};
+use httparse::parse_headers;
 use rotor::{Scope, Time};
 use rotor::mio::tcp::TcpStream;
 use rotor_stream::{Exception, Intent, Protocol, StreamSocket, Transport};
 
 use version::Version;
-use headers;
-use message::MessageState;
+use headers::{self, ConnectionToken};
+use message::{MessageState, TrailerPolicy};
 use recvmode::RecvMode;
 use super::{MAX_HEADERS_NUM, MAX_HEADERS_SIZE, MAX_CHUNK_HEAD};
-use super::{Head, Response, Server};
+use super::{Head, Response, Server, HeaderDecision, Method};
+use super::choose_error_format;
 use super::body::BodyKind;
-use super::response::state;
+use super::client_limit::ClientSlot;
+use super::response::{state, body_paused, flush_requested, reset_requested};
+use super::response::{set_trailer_policy, set_error_format};
+use super::response::set_reason_phrases;
 use super::error::RequestError;
+use super::uri::split_absolute;
+
+#[cfg(feature="reuse_headers")]
+thread_local!(static HEADERS: RefCell<Vec<Header<'static>>>
+    = RefCell::new(Vec::new()));
+
+/// Header array borrowed from the thread-local storage
+///
+/// `MAX_HEADERS_NUM` headers take several kilobytes, which is noticeable
+/// when the parser is called deep inside the nested state machines. So with
+/// `reuse_headers` feature the array is allocated once per thread and put
+/// back on drop (if parser is reentered, inner call allocates a new one).
+#[cfg(feature="reuse_headers")]
+struct HeaderBuf<'a>(Vec<Header<'a>>);
+
+#[cfg(feature="reuse_headers")]
+impl<'a> HeaderBuf<'a> {
+    fn new() -> HeaderBuf<'a> {
+        let vec = HEADERS.with(|x| replace(&mut *x.borrow_mut(), Vec::new()));
+        // The stored vector is always empty, so there are no references of
+        // any lifetime in it
+        debug_assert!(vec.len() == 0);
+        let mut vec: Vec<Header<'a>> = unsafe { transmute(vec) };
+        vec.resize(MAX_HEADERS_NUM, EMPTY_HEADER);
+        HeaderBuf(vec)
+    }
+}
+
+#[cfg(feature="reuse_headers")]
+impl<'a> ::std::ops::Deref for HeaderBuf<'a> {
+    type Target = [Header<'a>];
+    fn deref(&self) -> &[Header<'a>] {
+        &self.0
+    }
+}
+
+#[cfg(feature="reuse_headers")]
+impl<'a> ::std::ops::DerefMut for HeaderBuf<'a> {
+    fn deref_mut(&mut self) -> &mut [Header<'a>] {
+        &mut self.0
+    }
+}
+
+#[cfg(feature="reuse_headers")]
+impl<'a> Drop for HeaderBuf<'a> {
+    fn drop(&mut self) {
+        let mut vec = replace(&mut self.0, Vec::new());
+        vec.clear();
+        let vec: Vec<Header<'static>> = unsafe { transmute(vec) };
+        HEADERS.with(|x| *x.borrow_mut() = vec);
+    }
+}
 
 #[derive(Debug)]
 pub struct ReadBody<M: Server> {
     machine: Option<M>,
     deadline: Time,
+    /// Absolute limit set by `Server::max_request_duration`
+    max_deadline: Option<Time>,
+    /// Chunks left until `Server::max_chunks` is reached
+    chunks_left: Option<usize>,
     response: MessageState,
     progress: BodyProgress,
     connection_close: bool,
+    paused: bool,
+    /// Handler requested flush, reading is resumed when output is sent
+    flush: bool,
 }
 
 #[derive(Debug)]
@@ -40,6 +109,15 @@ pub enum BodyProgress {
     /// Progressive with chunked encoding
     /// (hint, offset, bytes left for current chunk)
     ProgressiveChunked(usize, usize, u64),
+    /// Fixed-size request read by frames (frame size, bytes left)
+    FramesFixed(usize, u64),
+    /// Chunked request read by frames
+    /// (frame size, frames left, offset, bytes left for current chunk)
+    FramesChunked(usize, usize, usize, u64),
+    /// Trailer section of buffered chunked request (bytes buffered)
+    BufferTrailers(usize),
+    /// Trailer section of progressive chunked request
+    ProgressiveTrailers,
 }
 
 fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
@@ -48,16 +126,57 @@ fn start_body(mode: RecvMode, body: BodyKind) -> BodyProgress {
     use self::BodyProgress::*;
 
     match (mode, body) {
-        // The size of Fixed(x) is checked in parse_headers
+        // The size of Fixed(x) is checked when headers are received
         (Buffered(_), Fixed(y)) => BufferFixed(y as usize),
         (Buffered(x), Chunked) => BufferChunked(x, 0, 0),
         (Progressive(x), Fixed(y)) => ProgressiveFixed(x, y),
         (Progressive(x), Chunked) => ProgressiveChunked(x, 0, 0),
-        (_, Upgrade) => unimplemented!(),
+        // The size of Fixed(x) is checked when headers are received
+        (Frames(x, _), Fixed(y)) => FramesFixed(x, y),
+        (Frames(x, n), Chunked) => FramesChunked(x, n, 0, 0),
+        // Upgrade has no body, and `RecvMode::Upgrade` is either accepted
+        // or rejected when headers are received
+        (_, Upgrade) | (Upgrade, _) => unreachable!(),
+    }
+}
+
+// Accounts a non-empty chunk, returns false if the limit is exceeded
+fn count_chunk(chunks_left: &mut Option<usize>) -> bool {
+    match *chunks_left {
+        Some(0) => false,
+        Some(ref mut n) => {
+            *n -= 1;
+            true
+        }
+        None => true,
+    }
+}
+
+// RFC 7230 allows `Content-Length: 5, 5` when all values are the same
+fn parse_content_length(value: &[u8]) -> Result<u64, RequestError> {
+    use super::RequestError::*;
+    let mut result = None;
+    for item in value.split(|&x| x == b',') {
+        let item = headers::trim_ows(item);
+        if !headers::is_digits(item) {
+            // `parse()` would accept `+5`, which other servers in the chain
+            // may read differently
+            return Err(InvalidContentLength);
+        }
+        let s = try!(from_utf8(item));
+        let len = try!(s.parse().map_err(BadContentLength));
+        if result.map(|x| x != len).unwrap_or(false) {
+            return Err(DuplicateContentLength);
+        }
+        result = Some(len);
     }
+    // split always yields at least one item
+    Ok(result.unwrap())
 }
 
-fn scan_raw_request(raw_request: &Request)
+fn scan_raw_request(raw_request: &Request,
+    max_duplicates: &mut FnMut(&str) -> usize, max_value_size: usize,
+    strict_framing: bool)
     -> Result<(BodyKind, bool, bool, bool), RequestError>
 {
     // Implements the body length algorithm for requests:
@@ -76,14 +195,49 @@ fn scan_raw_request(raw_request: &Request)
     //    present the request has an empty body
     //    (6th option in RFC).
     // 4. In all other cases the request is a bad request.
+    //
+    // The method doesn't influence request body length, so bodies of
+    // `PATCH`, `DELETE` and custom methods are read the same way as `POST`.
+    //
+    // When both `Transfer-Encoding` and `Content-Length` are present the
+    // former wins but connection is closed after the request. In strict
+    // mode such requests are rejected altogether.
+    //
+    // More than one `Host` header is always rejected, even if values are
+    // the same, as proxies and caches may pick different ones. HTTP/1.1
+    // requests without `Host` are rejected too, as required by RFC 7230.
     use super::body::BodyKind::*;
     use super::RequestError::*;
-    let is_head = raw_request.method.unwrap() == "HEAD";
+    let method = Method::parse(raw_request.method.unwrap());
+    let is_head = method == Method::Head;
     let mut has_content_length = false;
-    let mut close = raw_request.version.unwrap() == 0;
+    let mut content_length = None;
+    let mut close = false;
+    let mut keep_alive = false;
     let mut expect_continue = false;
     let mut body = Fixed(0);
+    let mut has_host = false;
+    let mut upgrade_token = false;
+    let mut has_upgrade = false;
     for header in raw_request.headers.iter() {
+        if headers::is_host(header.name) {
+            if has_host {
+                return Err(DuplicateHost);
+            }
+            has_host = true;
+        }
+        if header.value.len() > max_value_size {
+            return Err(HeaderValueTooLarge);
+        }
+        let limit = max_duplicates(header.name);
+        if limit < raw_request.headers.len() {
+            let num = raw_request.headers.iter()
+                .filter(|h| h.name.eq_ignore_ascii_case(header.name))
+                .count();
+            if num > limit {
+                return Err(TooManyDuplicateHeaders);
+            }
+        }
         if headers::is_transfer_encoding(header.name) {
             if let Some(enc) = header.value.split(|&x| x == b',').last() {
                 if headers::is_chunked(enc) {
@@ -95,32 +249,159 @@ fn scan_raw_request(raw_request: &Request)
                 }
             }
         } else if headers::is_content_length(header.name) {
-            if has_content_length {
-                // duplicate content_length
+            let len = try!(parse_content_length(header.value));
+            if content_length.map(|x| x != len).unwrap_or(false) {
+                // duplicate content_length with different value
                 return Err(DuplicateContentLength);
             }
+            content_length = Some(len);
             has_content_length = true;
             if body != Chunked {
-                let s = try!(from_utf8(header.value));
-                let len = try!(s.parse().map_err(BadContentLength));
                 body = Fixed(len);
             } else {
                 // transfer-encoding has preference and don't allow keep-alive
                 close = true;
             }
         } else if headers::is_connection(header.name) {
-            if header.value.split(|&x| x == b',').any(headers::is_close) {
-                close = true;
+            for token in headers::connection_tokens(header.value) {
+                match token {
+                    ConnectionToken::Close => close = true,
+                    // only meaningful for HTTP/1.0, for HTTP/1.1
+                    // keep-alive is the default
+                    ConnectionToken::KeepAlive => keep_alive = true,
+                    ConnectionToken::Upgrade => upgrade_token = true,
+                    ConnectionToken::Other(_) => {}
+                }
             }
+        } else if headers::is_upgrade(header.name) {
+            has_upgrade = true;
         } else if headers::is_expect(header.name) {
             if headers::is_continue(header.value) {
                 expect_continue = true;
             }
         }
     }
+    if raw_request.version.unwrap() == 1 && !has_host {
+        return Err(MissingHost);
+    }
+    // HTTP/1.0 connection is closed unless client asked otherwise
+    if raw_request.version.unwrap() == 0 && !keep_alive {
+        close = true;
+    }
+    // Request with a body can't be upgraded, as we don't know where the
+    // body ends and the new protocol starts, the upgrade is ignored then
+    // which is allowed by spec
+    if body == Fixed(0) && !has_content_length &&
+        (upgrade_token && has_upgrade ||
+         method == Method::Connect)
+    {
+        body = Upgrade;
+    }
+    if strict_framing && has_content_length && body == Chunked {
+        return Err(AmbiguousBodyLength);
+    }
     Ok((body, is_head, expect_continue, close))
 }
 
+// The `data` is the trailer section without the CRLF after the last chunk
+// size, and including the final empty line. Empty section is common, so
+// the handler is only called when there are trailers.
+fn read_trailers<M: Server>(data: &[u8], machine: Option<M>,
+    response: &mut Response, scope: &mut Scope<M::Context>)
+    -> Result<Option<M>, RequestError>
+{
+    use httparse::Status::*;
+    if data == b"\r\n" {
+        return Ok(machine);
+    }
+    let mut headers = [EMPTY_HEADER; MAX_HEADERS_NUM];
+    match parse_headers(data, &mut headers) {
+        Ok(Complete((_, trailers))) => {
+            Ok(machine.and_then(|m| {
+                m.request_trailers(trailers, response, scope)
+            }))
+        }
+        // delimiter is already found
+        Ok(Partial) => unreachable!(),
+        Err(e) => {
+            machine.map(|m| m.bad_request(response, scope));
+            Err(RequestError::from(e))
+        }
+    }
+}
+
+// Invalid or duplicate values are ignored, as it's just a hint
+fn parse_size_hint(headers: &[Header], name: &str) -> Option<u64> {
+    let mut values = headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value);
+    match (values.next(), values.next()) {
+        (Some(value), None) => {
+            from_utf8(value).ok().and_then(|x| x.trim().parse().ok())
+        }
+        _ => None,
+    }
+}
+
+// Whether the request is answered from `Server::allowed_methods` without
+// calling the handler: `OPTIONS` or a method not allowed for a known path
+fn answer_allowed(allowed: &[String], method: &str) -> bool {
+    if allowed.len() == 0 {
+        return false;
+    }
+    let known = allowed.iter().any(|m| m == method ||
+        method == "HEAD" && m == "GET");
+    return method == "OPTIONS" || !known;
+}
+
+fn has_bare_lf(data: &[u8]) -> bool {
+    data.iter().enumerate()
+        .any(|(i, &x)| x == b'\n' && (i == 0 || data[i-1] != b'\r'))
+}
+
+// Only the bytes of the current request are consumed from the input when
+// reading the body, whatever is left is parsed as the next request. When
+// we are not going to read the next request the data is dropped.
+fn drop_input<S: StreamSocket>(transport: &mut Transport<S>) {
+    let input = transport.input();
+    let len = input.len();
+    input.consume(len);
+}
+
+// With zero linger timeout the kernel sends RST on close and doesn't keep
+// the socket in TIME_WAIT
+#[cfg(unix)]
+fn set_zero_linger(sock: &TcpStream) {
+    use std::net;
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+    use net2::TcpStreamExt;
+    let stream = unsafe { net::TcpStream::from_raw_fd(sock.as_raw_fd()) };
+    if let Err(e) = stream.set_linger(Some(Duration::new(0, 0))) {
+        info!("Can't set SO_LINGER to reset connection: {}", e);
+    }
+    // The descriptor is still owned by the mio socket
+    stream.into_raw_fd();
+}
+
+#[cfg(not(unix))]
+fn set_zero_linger(_sock: &TcpStream) {
+}
+
+fn reset_connection<S: StreamSocket, P>(transport: &mut Transport<S>)
+    -> Intent<P>
+{
+    {
+        let output = transport.output();
+        let len = output.len();
+        output.consume(len);
+    }
+    drop_input(transport);
+    if let Some(tcp) = Any::downcast_ref::<TcpStream>(transport.socket()) {
+        set_zero_linger(tcp);
+    }
+    Intent::done()
+}
+
 #[inline]
 fn consumed(off: usize) -> usize {
     // If buffer is not empty it has final '\r\n' at the
@@ -132,20 +413,50 @@ fn consumed(off: usize) -> usize {
 #[derive(Debug)]
 pub enum ParserImpl<M: Server> {
     Idle,
+    /// Too many connections from the client, replying with an error
+    Rejected,
+    /// Too many pipelined responses are not sent yet, waiting for flush
+    Draining,
     ReadHeaders,
     ReadingBody(ReadBody<M>),
     Processing(M, MessageState, bool, Time),
-    DoneResponse,
+    /// The connection is switched to another protocol
+    Upgraded(M, MessageState, Time),
+    /// The last response is complete, flushing it before closing
+    DoneResponse(Time),
+    /// Handler requested connection reset, it's done on the next event
+    Reset,
+}
+
+/// The state of the connection which is kept between requests
+#[derive(Debug, Default)]
+pub struct Conn {
+    /// The entry in the `Server::client_limit` (boxed to keep parser small)
+    slot: Option<Box<ClientSlot>>,
+    /// Number of requests processed since output buffer was last empty
+    queued: usize,
 }
 
 impl <M: Server>ParserImpl<M> {
-    fn wrap<S: StreamSocket>(self, seed: M::Seed) -> Parser<M, S> {
-        Parser(self, seed, PhantomData)
+    fn wrap<S: StreamSocket>(self, seed: M::Seed, conn: Conn)
+        -> Parser<M, S>
+    {
+        Parser(self, seed, conn, PhantomData)
+    }
+    fn is_response_started(&self) -> bool {
+        use self::ParserImpl::*;
+        match *self {
+            ReadingBody(ref rb) => rb.response.is_started(),
+            Processing(_, ref response, _, _) => response.is_started(),
+            Upgraded(..) => true,
+            _ => false,
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct Parser<M, S>(ParserImpl<M>, M::Seed, PhantomData<*const S>)
+pub struct Parser<M, S>(ParserImpl<M>, M::Seed, Conn,
+                        PhantomData<*const S>)
     where M: Server, S: StreamSocket;
 
 unsafe impl<M, S> Send for Parser<M, S>
@@ -159,33 +470,75 @@ unsafe impl<M, S> Sync for Parser<M, S>
 
 impl<M: Server, S: StreamSocket> Parser<M, S> {
     #[inline]
-    fn intent_idle(seed: M::Seed, scope: &mut Scope<M::Context>)
+    fn intent_idle(seed: M::Seed, conn: Conn,
+        scope: &mut Scope<M::Context>)
+        -> Intent<Self>
+    {
+        let deadline = scope.now() + M::idle_timeout(&seed, scope);
+        Intent::of(ParserImpl::Idle.wrap(seed, conn))
+            .expect_bytes(1)
+            .deadline(deadline)
+    }
+    #[inline]
+    fn intent_rejected(seed: M::Seed, scope: &mut Scope<M::Context>)
         -> Intent<Self>
     {
         let deadline = scope.now() + M::idle_timeout(&seed, scope);
-        Intent::of(ParserImpl::Idle.wrap(seed))
+        Intent::of(ParserImpl::Rejected.wrap(seed, Conn::default()))
             .expect_bytes(1)
             .deadline(deadline)
     }
     #[inline]
-    fn intent_headers(seed: M::Seed, scope: &mut Scope<M::Context>, n: usize)
+    fn intent_drain(seed: M::Seed, conn: Conn,
+        scope: &mut Scope<M::Context>)
+        -> Intent<Self>
+    {
+        let deadline = scope.now() + M::send_response_timeout(&seed, scope);
+        Intent::of(ParserImpl::Draining.wrap(seed, conn))
+            .expect_flush()
+            .deadline(deadline)
+    }
+    #[inline]
+    fn intent_headers(seed: M::Seed, conn: Conn,
+        scope: &mut Scope<M::Context>, n: usize)
         -> Intent<Self>
     {
         let deadline = scope.now() + M::header_byte_timeout(&seed, scope);
-        Intent::of(ParserImpl::ReadHeaders.wrap(seed))
+        Intent::of(ParserImpl::ReadHeaders.wrap(seed, conn))
             .expect_bytes(n + 1)
             .deadline(deadline)
     }
     #[inline]
-    fn intent_flush(seed: M::Seed, scope: &mut Scope<M::Context>)
+    fn intent_flush(seed: M::Seed, conn: Conn,
+        scope: &mut Scope<M::Context>)
         -> Intent<Self>
     {
         let deadline = scope.now() + M::send_response_timeout(&seed, scope);
-        Intent::of(ParserImpl::DoneResponse.wrap(seed))
+        Parser::flush_until(seed, conn, deadline)
+    }
+    // The deadline is not moved when more data is received, so the client
+    // can't keep the connection open by sending garbage instead of reading
+    #[inline]
+    fn flush_until(seed: M::Seed, conn: Conn, deadline: Time)
+        -> Intent<Self>
+    {
+        Intent::of(ParserImpl::DoneResponse(deadline).wrap(seed, conn))
             .expect_flush()
             .deadline(deadline)
     }
-    fn intent_body(seed: M::Seed, body: ReadBody<M>) -> Intent<Self> {
+    // Response borrows the output buffer, so we can't reach the socket
+    // in the handler, the reset is done on the timeout immediately after
+    #[inline]
+    fn intent_reset(seed: M::Seed, conn: Conn,
+        scope: &mut Scope<M::Context>)
+        -> Intent<Self>
+    {
+        Intent::of(ParserImpl::Reset.wrap(seed, conn))
+            .sleep()
+            .deadline(scope.now())
+    }
+    fn intent_body(seed: M::Seed, conn: Conn,
+        body: ReadBody<M>) -> Intent<Self> {
         use rotor_stream::Expectation::*;
         use self::BodyProgress::*;
         let exp = match *&body.progress {
@@ -199,138 +552,447 @@ impl<M: Server, S: StreamSocket> Parser<M, S> {
             ProgressiveChunked(hint, off, left) => {
                 Bytes(min(hint as u64, off as u64 + left) as usize + 2)
             }
+            FramesFixed(size, left) => Bytes(min(size as u64, left) as usize),
+            FramesChunked(_, _, off, 0) => {
+                Delimiter(off, b"\r\n", off + MAX_CHUNK_HEAD)
+            }
+            FramesChunked(size, _, off, left) => {
+                if off as u64 + left <= size as u64 {
+                    // wait for the whole chunk including CRLF
+                    Bytes(off + left as usize + 2)
+                } else {
+                    Bytes(size)
+                }
+            }
+            // The search starts at the CRLF after the last chunk size, so
+            // empty trailer section matches too
+            BufferTrailers(off) => {
+                Delimiter(off, b"\r\n\r\n", off + MAX_HEADERS_SIZE)
+            }
+            ProgressiveTrailers => Delimiter(0, b"\r\n\r\n", MAX_HEADERS_SIZE),
+        };
+        let deadline = match body.max_deadline {
+            Some(limit) => min(limit, body.deadline),
+            None => body.deadline,
         };
-        let deadline = body.deadline;
-        Intent::of(ParserImpl::ReadingBody(body).wrap(seed))
+        if body.flush {
+            // `wakeup` is called in `bytes_flushed`, not reading the body
+            // meanwhile is a back pressure for the full duplex streaming
+            return Intent::of(ParserImpl::ReadingBody(body).wrap(seed, conn))
+                .expect_flush().deadline(deadline);
+        }
+        if body.paused {
+            // Not reading anything lets the TCP window close, so the peer
+            // stops sending until the body read is resumed on wakeup
+            return Intent::of(ParserImpl::ReadingBody(body).wrap(seed, conn))
+                .sleep().deadline(deadline);
+        }
+        Intent::of(ParserImpl::ReadingBody(body).wrap(seed, conn))
             .expect(exp).deadline(deadline)
     }
-    fn complete<'x>(seed: M::Seed, scope: &mut Scope<M::Context>,
+    fn upgraded<'x>(seed: M::Seed, conn: Conn,
+                    scope: &mut Scope<M::Context>,
+                    machine: Option<M>,
+                    response: Response<'x>,
+                    deadline: Time)
+                    -> Intent<Parser<M, S>> {
+        if reset_requested(&response) {
+            return Parser::intent_reset(seed, conn, scope);
+        }
+        match machine {
+            Some(m) => {
+                let flush = flush_requested(&response);
+                let intent = Intent::of(ParserImpl::Upgraded(m,
+                    state(response), deadline).wrap(seed, conn));
+                if flush {
+                    // `wakeup` is called in `bytes_flushed`
+                    intent.expect_flush().deadline(deadline)
+                } else {
+                    intent.expect_bytes(1).deadline(deadline)
+                }
+            }
+            None => Parser::intent_flush(seed, conn, scope),
+        }
+    }
+    fn complete<'x>(seed: M::Seed, conn: Conn,
+                    scope: &mut Scope<M::Context>,
                     machine: Option<M>,
                     response: Response<'x>,
                     connection_close: bool,
                     deadline: Time)
                     -> Intent<Parser<M, S>> {
+        if reset_requested(&response) {
+            return Parser::intent_reset(seed, conn, scope);
+        }
         match machine {
             Some(m) => {
-                Intent::of(ParserImpl::Processing(m, state(response),
-                                    connection_close, deadline).wrap(seed))
-                    .sleep()
-                    .deadline(deadline)
+                let flush = flush_requested(&response);
+                let intent = Intent::of(ParserImpl::Processing(m,
+                    state(response), connection_close, deadline)
+                        .wrap(seed, conn));
+                if flush {
+                    // `wakeup` is called in `bytes_flushed`
+                    intent.expect_flush().deadline(deadline)
+                } else {
+                    intent.sleep().deadline(deadline)
+                }
             }
             None => {
                 // TODO(tailhook) probably we should do something better than
                 // an assert?
                 assert!(response.is_complete());
                 if connection_close {
-                    Parser::intent_flush(seed, scope)
+                    Parser::intent_flush(seed, conn, scope)
                 } else {
-                    Parser::intent_idle(seed, scope)
+                    Parser::intent_idle(seed, conn, scope)
                 }
             }
         }
     }
 }
 
-impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
-    type Context = M::Context;
-    type Socket = S;
-    type Seed = M::Seed;
-    fn create(seed: Self::Seed,
-              _sock: &mut Self::Socket,
-              scope: &mut Scope<Self::Context>)
-              -> Intent<Self> {
-        Parser::intent_idle(seed, scope)
+impl<M: Server, S: StreamSocket> Parser<M, S> {
+    // All handlers are called within the `action`, so if the panic is
+    // caught here, the connection state is lost and all we can do is to
+    // send an error page (if nothing is written yet) and close connection
+    fn guard<F>(self, transport: &mut Transport<S>,
+        scope: &mut Scope<M::Context>, action: F)
+        -> Intent<Self>
+        where F: FnOnce(Self, &mut Transport<S>, &mut Scope<M::Context>)
+                 -> Intent<Self>
+    {
+        if !M::catch_panics(&self.1, scope) {
+            return action(self, transport, scope);
+        }
+        let seed = self.1.clone();
+        let started = self.0.is_response_started();
+        let buffered = transport.output().len();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            action(self, transport, scope)
+        }));
+        match result {
+            Ok(intent) => intent,
+            Err(_) => {
+                if started || transport.output().len() != buffered {
+                    return Intent::done();
+                }
+                let auto = M::auto_headers(&seed, scope);
+                let mut response = Response::new(transport.output(),
+                    Version::Http10, false, true, auto);
+                M::emit_error_page(&RequestError::HandlerPanic,
+                    &mut response, &seed, scope);
+                Parser::intent_flush(seed, Conn::default(), scope)
+            }
+        }
     }
-    fn bytes_read(self,
-                  transport: &mut Transport<Self::Socket>,
-                  end: usize,
-                  scope: &mut Scope<Self::Context>)
-                  -> Intent<Self> {
+    fn on_bytes_read(mut self,
+                     transport: &mut Transport<S>,
+                     end: usize,
+                     scope: &mut Scope<M::Context>)
+                     -> Intent<Self> {
         use self::ParserImpl::*;
         use super::RequestError::*;
         match self.0 {
+            Rejected => {
+                let auto = M::auto_headers(&self.1, scope);
+                drop_input(transport);
+                let mut response = Response::new(transport.output(),
+                    Version::Http10, false, true, auto);
+                M::emit_error_page(&TooManyConnections, &mut response,
+                    &self.1, scope);
+                Parser::intent_flush(self.1, self.2, scope)
+            }
+            Idle if transport.output().len() > 0 &&
+                self.2.queued >= M::max_pipeline_depth(&self.1, scope) =>
+            {
+                // Client doesn't read responses, so don't parse pipelined
+                // requests until the output buffer is drained
+                Parser::intent_drain(self.1, self.2, scope)
+            }
             Idle | ReadHeaders => {
                 use httparse::Status::*;
                 let n;
                 let client = Any::downcast_ref::<TcpStream>(transport.socket())
                                  .and_then(|x| x.peer_addr().ok());
+                let auto = M::auto_headers(&self.1, scope);
+                let max_deadline = M::max_request_duration(&self.1, scope)
+                    .map(|x| scope.now() + x);
+                let max_chunks = M::max_chunks(&self.1, scope);
                 let (input, output) = transport.buffers();
-                let ((machine, mode, deadline), response, body, close) = {
+                if output.len() == 0 {
+                    self.2.queued = 0;
+                }
+                let processed = {
+                    #[cfg(not(feature="reuse_headers"))]
                     let mut headers = [EMPTY_HEADER; MAX_HEADERS_NUM];
+                    #[cfg(feature="reuse_headers")]
+                    let mut headers = HeaderBuf::new();
                     let mut raw_request = Request::new(&mut headers);
                     n = match raw_request.parse(&input[..]) {
-                        Ok(Complete(n)) => n,
+                        Ok(Complete(n)) => {
+                            self.2.queued += 1;
+                            n
+                        }
                         Ok(Partial) => {
                             if input.len() > MAX_HEADERS_SIZE {
                                 let mut response = Response::new(output,
                                                                  Version::Http10,
                                                                  false,
-                                                                 true);
+                                                                 true,
+                                                                 auto);
                                 M::emit_error_page(&HeadersAreTooLarge,
                                     &mut response, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
                             }
-                            return Parser::intent_headers(self.1,
+                            return Parser::intent_headers(self.1, self.2,
                                 scope, input.len());
                         }
                         Err(e) => {
                             let mut response = Response::new(output,
-                                Version::Http10, false, true);
+                                Version::Http10, false, true, auto);
                             M::emit_error_page(&RequestError::from(e),
                                 &mut response, &self.1, scope);
-                            return Parser::intent_flush(self.1, scope);
+                            return Parser::intent_flush(self.1, self.2, scope);
                         }
                     };
-                    match scan_raw_request(&raw_request) {
+                    // The headers are parsed, so error pages can be
+                    // formatted as the client prefers
+                    let errors = choose_error_format(raw_request.headers
+                        .iter().find(|h| h.name.eq_ignore_ascii_case("Accept"))
+                        .map(|h| h.value));
+                    if M::strict_line_endings(&self.1, scope) &&
+                        has_bare_lf(&input[..n])
+                    {
+                        let mut response = Response::new(output,
+                            Version::Http10, false, true, auto);
+                        set_error_format(&mut response, errors);
+                        M::emit_error_page(&BareLineFeed,
+                            &mut response, &self.1, scope);
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                    if M::reject_obs_fold(&self.1, scope) &&
+                        headers::has_obs_fold(&input[..n])
+                    {
+                        let mut response = Response::new(output,
+                            Version::Http10, false, true, auto);
+                        set_error_format(&mut response, errors);
+                        M::emit_error_page(&ObsoleteLineFolding,
+                            &mut response, &self.1, scope);
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                    if let HeaderDecision::Reject(code) =
+                        M::inspect_headers(&self.1, raw_request.headers, scope)
+                    {
+                        let mut response = Response::new(output,
+                            Version::Http10, false, true, auto);
+                        set_error_format(&mut response, errors);
+                        M::emit_error_page(&HeadersRejected(code),
+                            &mut response, &self.1, scope);
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                    let strict_framing = M::strict_framing(&self.1, scope);
+                    let max_value_size = M::max_header_value_size(&self.1,
+                                                                  scope);
+                    let scan = scan_raw_request(&raw_request,
+                        &mut |name| M::max_duplicate_headers(&self.1,
+                                                             name, scope),
+                        max_value_size, strict_framing);
+                    match scan {
                         Ok((body, is_head, expect_continue, close)) => {
                             let version = if raw_request.version.unwrap() == 1 {
                                 Version::Http11
                             } else {
                                 Version::Http10
                             };
-                            let request = Head {
+                            let path = M::rewrite_path(&self.1,
+                                raw_request.path.unwrap(), scope);
+                            let size_hint = match body {
+                                BodyKind::Fixed(n) => Some(n),
+                                BodyKind::Chunked => {
+                                    M::size_hint_header(&self.1, scope)
+                                    .and_then(|name| parse_size_hint(
+                                        raw_request.headers, name))
+                                }
+                                BodyKind::Upgrade => None,
+                            };
+                            let mut request = Head {
                                 client: client,
                                 version: version,
                                 method: raw_request.method.unwrap(),
-                                scheme: "http",
-                                path: raw_request.path.unwrap(),
+                                scheme: split_absolute(
+                                        raw_request.path.unwrap())
+                                    .map(|(scheme, _, _)| scheme)
+                                    .unwrap_or("http"),
+                                path: &path,
                                 headers: raw_request.headers,
                                 body_kind: body,
+                                size_hint: size_hint,
+                                request_id: None,
                             };
-                            let mut response = Response::new(output,
-                                request.version, is_head, close);
-                            let triple = M::headers_received(self.1.clone(),
-                                request, &mut response, scope);
-                            if triple.is_none() && response.is_started() {
-                                if !expect_continue {
-                                    return Intent::done();
+                            request.request_id = M::request_id(&self.1,
+                                &request, scope);
+                            let allowed = M::allowed_methods(&self.1,
+                                request.path, scope);
+                            if answer_allowed(&allowed, request.method) {
+                                // The body isn't read, so it's easier to
+                                // close the connection than to skip it
+                                let close = close ||
+                                    body != BodyKind::Fixed(0);
+                                let mut response = Response::new(output,
+                                    request.version, is_head, close, auto);
+                                let allowed = allowed.iter()
+                                    .map(|x| &x[..]).collect::<Vec<_>>();
+                                if request.method == "OPTIONS" {
+                                    response.options(&allowed);
                                 } else {
-                                    return Parser::intent_flush(self.1, scope);
+                                    response.method_not_allowed(&allowed);
                                 }
-                            } else if triple.is_none() {
-                                M::emit_error_page(&HeadersReceived,
-                                    &mut response, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
-                            }
-                            if expect_continue {
-                                response.response_continue();
+                                if close {
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
+                                None
+                            } else {
+                                let mut response = Response::new(output,
+                                    request.version, is_head, close, auto);
+                                set_error_format(&mut response, errors);
+                                set_reason_phrases(&mut response,
+                                                   M::reason_phrase);
+                                let te = raw_request.headers.iter()
+                                    .any(|h| headers::is_te(h.name) &&
+                                             headers::has_trailers(h.value));
+                                set_trailer_policy(&mut response, if te {
+                                    TrailerPolicy::Allow
+                                } else if strict_framing {
+                                    TrailerPolicy::Deny
+                                } else {
+                                    TrailerPolicy::Warn
+                                });
+                                let triple = M::headers_received(
+                                    self.1.clone(), request, &mut response,
+                                    scope);
+                                if reset_requested(&response) {
+                                    return Parser::intent_reset(self.1,
+                                        self.2, scope);
+                                }
+                                if triple.is_none() && response.is_started() {
+                                    if !expect_continue {
+                                        return Intent::done();
+                                    } else {
+                                        return Parser::intent_flush(self.1,
+                                            self.2, scope);
+                                    }
+                                } else if triple.is_none() {
+                                    M::emit_error_page(&HeadersReceived,
+                                        &mut response, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
+                                if expect_continue {
+                                    response.response_continue();
+                                }
+                                Some((triple.unwrap(), response, body, close))
                             }
-                            (triple.unwrap(), response, body, close)
                         }
                         Err(e) => {
                             let mut response = Response::new(output,
-                                Version::Http10, false, true);
+                                Version::Http10, false, true, auto);
+                            set_error_format(&mut response, errors);
                             M::emit_error_page(&e, &mut response,
                                 &self.1, scope);
-                            return Parser::intent_flush(self.1, scope);
+                            return Parser::intent_flush(self.1, self.2, scope);
                         }
                     }
                 };
                 input.consume(n);
-                return Parser::intent_body(self.1, ReadBody {
+                let ((machine, mode, deadline), response, body, close) =
+                    match processed {
+                        Some(x) => x,
+                        // Answered without a handler, see `allowed_methods`
+                        None => {
+                            return Parser::intent_idle(self.1, self.2, scope);
+                        }
+                    };
+                // The rest of the input belongs to the new protocol
+                if mode == RecvMode::Upgrade {
+                    if body != BodyKind::Upgrade {
+                        // A bug in the handler, there is no new protocol
+                        // to pass the rest of the input to
+                        let mut response = response;
+                        if response.is_started() {
+                            return Intent::done();
+                        }
+                        M::emit_error_page(&UnexpectedUpgrade,
+                            &mut response, &self.1, scope);
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                    return Parser::upgraded(self.1, self.2, scope,
+                        Some(machine), response, deadline);
+                }
+                if let RecvMode::Frames(0, _) = mode {
+                    // A bug in the handler, the body can't be split
+                    let mut response = response;
+                    if response.is_started() {
+                        return Intent::done();
+                    }
+                    M::emit_error_page(&ZeroFrameSize,
+                        &mut response, &self.1, scope);
+                    return Parser::intent_flush(self.1, self.2, scope);
+                }
+                let body = if body == BodyKind::Upgrade {
+                    BodyKind::Fixed(0)
+                } else {
+                    body
+                };
+                // Reject the body which doesn't fit in the limit before
+                // reading (or allocating buffer for) it
+                let limit = match mode {
+                    RecvMode::Buffered(x) => Some(x as u64),
+                    RecvMode::Frames(size, num) => {
+                        Some((size as u64).saturating_mul(num as u64))
+                    }
+                    RecvMode::Progressive(_) | RecvMode::Upgrade => None,
+                };
+                if let (Some(limit), BodyKind::Fixed(len)) = (limit, body) {
+                    if len > limit {
+                        let mut response = response;
+                        machine.bad_request(&mut response, scope);
+                        if !response.is_started() {
+                            M::emit_error_page(&PayloadTooLarge,
+                                &mut response, &self.1, scope);
+                        }
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                }
+                if body == BodyKind::Fixed(0) {
+                    // The most common case, no need to go through
+                    // ReadingBody state for an empty body
+                    let mut response = response;
+                    let m = match mode {
+                        RecvMode::Buffered(_) => {
+                            machine.body_received(&mut response, scope)
+                            .and_then(|m| {
+                                m.request_received(&[], &mut response, scope)
+                            })
+                        }
+                        // `Upgrade` is handled above
+                        _ => machine.request_end(&mut response, scope),
+                    };
+                    return Parser::complete(self.1, self.2, scope, m, response,
+                                            close, deadline);
+                }
+                if reset_requested(&response) {
+                    return Parser::intent_reset(self.1, self.2, scope);
+                }
+                return Parser::intent_body(self.1, self.2, ReadBody {
                     machine: Some(machine),
                     deadline: deadline,
+                    max_deadline: max_deadline,
+                    chunks_left: max_chunks,
                     progress: start_body(mode, body),
+                    paused: body_paused(&response),
+                    flush: flush_requested(&response),
                     response: state(response),
                     connection_close: close,
                 });
@@ -339,10 +1001,15 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                 use self::BodyProgress::*;
                 let (inp, out) = transport.buffers();
                 let mut resp = rb.response.with(out);
+                if rb.paused {
+                    resp.pause_body_read();
+                }
+                let mut chunks_left = rb.chunks_left;
                 let (m, progress) = match rb.progress {
                     BufferFixed(x) => {
                         let m = rb.machine
-                                  .and_then(|m| m.request_received(&inp[..x], &mut resp, scope));
+                                  .and_then(|m| m.body_received(&mut resp, scope));
+                        let m = m.and_then(|m| m.request_received(&inp[..x], &mut resp, scope));
                         inp.consume(x);
                         (m, None)
                     }
@@ -351,20 +1018,26 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                         let lenstart = consumed(off);
                         match parse_chunk_size(&inp[lenstart..lenstart + end + 2]) {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..lenstart + end + 2);
-                                let m = rb.machine.and_then(|m| {
-                                    m.request_received(&inp[..off], &mut resp, scope)
-                                });
-                                inp.consume(off);
-                                (m, None)
+                                // keep CRLF for reading trailers
+                                inp.remove_range(off..lenstart + end);
+                                (rb.machine, Some(BufferTrailers(off)))
                             }
                             Ok(Complete((_, chunk_len))) => {
+                                if !count_chunk(&mut chunks_left) {
+                                    inp.consume(lenstart + end + 2);
+                                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                                    M::emit_error_page(&TooManyChunks,
+                                        &mut resp, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
                                 if off as u64 + chunk_len > limit as u64 {
                                     inp.consume(lenstart + end + 2);
                                     rb.machine.map(|m| m.bad_request(&mut resp, scope));
                                     M::emit_error_page(&PayloadTooLarge,
                                         &mut resp, &self.1, scope);
-                                    return Parser::intent_flush(self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
                                 }
                                 inp.remove_range(off..lenstart + end + 2);
                                 (rb.machine,
@@ -376,7 +1049,8 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                                 rb.machine.map(|m| m.bad_request(&mut resp, scope));
                                 M::emit_error_page(&RequestError::from(e),
                                     &mut resp, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
                             }
                         }
                     }
@@ -405,20 +1079,31 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                         use httparse::Status::*;
                         match parse_chunk_size(&inp[off..off + end + 2]) {
                             Ok(Complete((_, 0))) => {
-                                inp.remove_range(off..off + end + 2);
+                                // keep CRLF for reading trailers
+                                inp.remove_range(off..off + end);
                                 let mut m = rb.machine;
                                 if off > 0 {
                                     m = m.and_then(|m| {
                                         m.request_chunk(&inp[..off], &mut resp, scope)
                                     });
                                 }
-                                m = m.and_then(|m| m.request_end(&mut resp, scope));
                                 inp.consume(off);
-                                (m, None)
+                                (m, Some(ProgressiveTrailers))
                             }
                             Ok(Complete((_, chunk_len))) => {
+                                if !count_chunk(&mut chunks_left) {
+                                    inp.consume(off + end + 2);
+                                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                                    M::emit_error_page(&TooManyChunks,
+                                        &mut resp, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
                                 inp.remove_range(off..off + end + 2);
-                                (rb.machine, Some(ProgressiveChunked(hint, off, chunk_len)))
+                                let m = rb.machine.and_then(|m| {
+                                    m.chunk_started(chunk_len, &mut resp, scope)
+                                });
+                                (m, Some(ProgressiveChunked(hint, off, chunk_len)))
                             }
                             Ok(Partial) => unreachable!(),
                             Err(e) => {
@@ -426,7 +1111,8 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                                 rb.machine.map(|m| m.bad_request(&mut resp, scope));
                                 M::emit_error_page(&RequestError::from(e),
                                     &mut resp, &self.1, scope);
-                                return Parser::intent_flush(self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
                             }
                         }
                     }
@@ -450,63 +1136,268 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                             (m, Some(ProgressiveChunked(hint, 0, left)))
                         }
                     }
+                    FramesFixed(size, mut left) => {
+                        let avail = min(inp.len() as u64, left) as usize;
+                        // only the last frame may be incomplete
+                        let n = if avail as u64 == left {
+                            avail
+                        } else {
+                            avail - avail % size
+                        };
+                        let mut m = rb.machine;
+                        for frame in inp[..n].chunks(size) {
+                            m = m.and_then(|m| {
+                                m.request_chunk(frame, &mut resp, scope)
+                            });
+                        }
+                        inp.consume(n);
+                        left -= n as u64;
+                        if left == 0 {
+                            let m = m.and_then(|m| m.request_end(&mut resp, scope));
+                            (m, None)
+                        } else {
+                            (m, Some(FramesFixed(size, left)))
+                        }
+                    }
+                    FramesChunked(size, num, off, 0) => {
+                        use httparse::Status::*;
+                        match parse_chunk_size(&inp[off..off + end + 2]) {
+                            Ok(Complete((_, 0))) => {
+                                // keep CRLF for reading trailers
+                                inp.remove_range(off..off + end);
+                                let mut m = rb.machine;
+                                if off > 0 {
+                                    m = m.and_then(|m| {
+                                        m.request_chunk(&inp[..off], &mut resp, scope)
+                                    });
+                                }
+                                inp.consume(off);
+                                (m, Some(ProgressiveTrailers))
+                            }
+                            Ok(Complete((_, chunk_len))) => {
+                                if !count_chunk(&mut chunks_left) {
+                                    inp.consume(off + end + 2);
+                                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                                    M::emit_error_page(&TooManyChunks,
+                                        &mut resp, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
+                                let limit = (size as u64)
+                                    .saturating_mul(num as u64);
+                                if off as u64 + chunk_len > limit {
+                                    inp.consume(off + end + 2);
+                                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                                    M::emit_error_page(&PayloadTooLarge,
+                                        &mut resp, &self.1, scope);
+                                    return Parser::intent_flush(self.1,
+                                        self.2, scope);
+                                }
+                                inp.remove_range(off..off + end + 2);
+                                let m = rb.machine.and_then(|m| {
+                                    m.chunk_started(chunk_len, &mut resp, scope)
+                                });
+                                (m, Some(FramesChunked(size, num, off, chunk_len)))
+                            }
+                            Ok(Partial) => unreachable!(),
+                            Err(e) => {
+                                inp.consume(off + end + 2);
+                                rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                                M::emit_error_page(&RequestError::from(e),
+                                    &mut resp, &self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
+                            }
+                        }
+                    }
+                    FramesChunked(size, mut num, off, mut left) => {
+                        let ln;
+                        if inp.len() as u64 >= off as u64 + left + 2 {
+                            // the whole chunk is here, drop CRLF after it
+                            ln = off + left as usize;
+                            inp.remove_range(ln..ln + 2);
+                            left = 0;
+                        } else {
+                            // never take the last byte of the chunk here,
+                            // so that CRLF is always removed above
+                            ln = min(inp.len() as u64, off as u64 + left - 1)
+                                 as usize;
+                            left -= (ln - off) as u64;
+                        }
+                        let n = ln - ln % size;
+                        let mut m = rb.machine;
+                        for frame in inp[..n].chunks(size) {
+                            m = m.and_then(|m| {
+                                m.request_chunk(frame, &mut resp, scope)
+                            });
+                        }
+                        inp.consume(n);
+                        num -= n / size;
+                        (m, Some(FramesChunked(size, num, ln - n, left)))
+                    }
+                    BufferTrailers(off) => {
+                        let m = match read_trailers(&inp[off + 2..off + end + 4],
+                                                    rb.machine, &mut resp, scope)
+                        {
+                            Ok(m) => m,
+                            Err(e) => {
+                                M::emit_error_page(&e, &mut resp,
+                                    &self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
+                            }
+                        };
+                        inp.remove_range(off..off + end + 4);
+                        let m = m.and_then(|m| m.body_received(&mut resp, scope));
+                        let m = m.and_then(|m| {
+                            m.request_received(&inp[..off], &mut resp, scope)
+                        });
+                        inp.consume(off);
+                        (m, None)
+                    }
+                    ProgressiveTrailers => {
+                        let m = match read_trailers(&inp[2..end + 4],
+                                                    rb.machine, &mut resp, scope)
+                        {
+                            Ok(m) => m,
+                            Err(e) => {
+                                M::emit_error_page(&e, &mut resp,
+                                    &self.1, scope);
+                                return Parser::intent_flush(self.1,
+                                    self.2, scope);
+                            }
+                        };
+                        inp.consume(end + 4);
+                        let m = m.and_then(|m| m.request_end(&mut resp, scope));
+                        (m, None)
+                    }
                 };
+                if reset_requested(&resp) {
+                    return Parser::intent_reset(self.1, self.2, scope);
+                }
                 match progress {
                     Some(p) => {
-                        Parser::intent_body(self.1, ReadBody {
+                        Parser::intent_body(self.1, self.2, ReadBody {
                             machine: m,
                             deadline: rb.deadline,
+                            max_deadline: rb.max_deadline,
+                            chunks_left: chunks_left,
                             progress: p,
+                            paused: body_paused(&resp),
+                            flush: flush_requested(&resp),
                             response: state(resp),
                             connection_close: rb.connection_close,
                         })
                     }
-                    None => Parser::complete(self.1, scope,
+                    None => Parser::complete(self.1, self.2, scope,
                         m, resp, rb.connection_close, rb.deadline),
                 }
             }
             Processing(m, r, c, dline) => {
-                Intent::of(Processing(m, r, c, dline).wrap(self.1))
+                if c {
+                    // Connection is closed after this request, so the data
+                    // is neither a pipelined request nor a part of the body
+                    drop_input(transport);
+                }
+                Intent::of(Processing(m, r, c, dline).wrap(self.1, self.2))
                     .sleep().deadline(dline)
             },
-            /// TODO(tailhook) fix output timeout
-            DoneResponse => Parser::intent_flush(self.1, scope),
+            Upgraded(m, r, dline) => {
+                let (inp, out) = transport.buffers();
+                let mut resp = r.with(out);
+                let len = inp.len();
+                let mres = m.request_chunk(&inp[..], &mut resp, scope);
+                inp.consume(len);
+                Parser::upgraded(self.1, self.2, scope, mres, resp, dline)
+            }
+            // Keep the pipelined requests in the buffer until flush
+            Draining => Parser::intent_drain(self.1, self.2, scope),
+            Reset => reset_connection(transport),
+            DoneResponse(dline) => {
+                drop_input(transport);
+                Parser::flush_until(self.1, self.2, dline)
+            }
         }
     }
-    fn bytes_flushed(self,
-                     _transport: &mut Transport<Self::Socket>,
-                     _scope: &mut Scope<Self::Context>)
-                     -> Intent<Self> {
+    fn on_bytes_flushed(self,
+                        transport: &mut Transport<S>,
+                        scope: &mut Scope<M::Context>)
+                        -> Intent<Self> {
+        if matches!(self.0, ParserImpl::ReadingBody(..)) {
+            // Flush requested while reading the request body
+            return self.on_wakeup(transport, scope);
+        }
         match self.0 {
-            ParserImpl::DoneResponse => Intent::done(),
+            ParserImpl::DoneResponse(_) => Intent::done(),
+            ParserImpl::Draining => {
+                let mut conn = self.2;
+                conn.queued = 0;
+                Parser::intent_idle(self.1, conn, scope)
+            }
+            // Flush requested by the handler
+            ParserImpl::Processing(m, respimp, close, dline) => {
+                let mut resp = respimp.with(transport.output());
+                let mres = m.wakeup(&mut resp, scope);
+                Parser::complete(self.1, self.2, scope,
+                    mres, resp, close, dline)
+            }
+            ParserImpl::Upgraded(m, respimp, dline) => {
+                let mut resp = respimp.with(transport.output());
+                let mres = m.wakeup(&mut resp, scope);
+                Parser::upgraded(self.1, self.2, scope, mres, resp, dline)
+            }
             _ => unreachable!(),
         }
     }
-    fn timeout(self,
-               transport: &mut Transport<Self::Socket>,
-               scope: &mut Scope<Self::Context>)
-               -> Intent<Self> {
+    fn on_timeout(self,
+                  transport: &mut Transport<S>,
+                  scope: &mut Scope<M::Context>)
+                  -> Intent<Self> {
         use self::ParserImpl::*;
         use super::RequestError::*;
         match self.0 {
-            Idle | DoneResponse => Intent::done(),
+            Idle | Rejected | Draining | DoneResponse(_) => Intent::done(),
+            Reset => reset_connection(transport),
             ReadHeaders => {
+                let auto = M::auto_headers(&self.1, scope);
                 let output = transport.output();
                 let mut response = Response::new(output,
-                    Version::Http10, false, true);
+                    Version::Http10, false, true, auto);
                 M::emit_error_page(&HeadersTimeout, &mut response,
                     &self.1, scope);
-                Parser::intent_flush(self.1, scope)
+                Parser::intent_flush(self.1, self.2, scope)
             }
             ReadingBody(rb) => {
                 let mut resp = rb.response.with(transport.output());
+                if rb.paused {
+                    resp.pause_body_read();
+                }
+                let limit_reached = rb.max_deadline
+                    .map(|x| scope.now() >= x).unwrap_or(false);
+                if limit_reached {
+                    // The handler can't extend this one
+                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                    if !resp.is_started() {
+                        M::emit_error_page(&RequestTimeout, &mut resp,
+                            &self.1, scope);
+                        return Parser::intent_flush(self.1, self.2, scope);
+                    }
+                    return Intent::done();
+                }
                 let res = rb.machine.and_then(|m| m.timeout(&mut resp, scope));
+                if reset_requested(&resp) {
+                    return Parser::intent_reset(self.1, self.2, scope);
+                }
                 match res {
                     Some((m, deadline)) => {
-                        Parser::intent_body(self.1, ReadBody {
+                        Parser::intent_body(self.1, self.2, ReadBody {
                             machine: Some(m),
                             deadline: deadline,
+                            max_deadline: rb.max_deadline,
+                            chunks_left: rb.chunks_left,
                             progress: rb.progress,
+                            paused: body_paused(&resp),
+                            flush: flush_requested(&resp),
                             response: state(resp),
                             connection_close: rb.connection_close,
                         })
@@ -515,7 +1406,7 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                         if !resp.is_started() {
                             M::emit_error_page(&RequestTimeout, &mut resp,
                                 &self.1, scope);
-                            Parser::intent_flush(self.1, scope)
+                            Parser::intent_flush(self.1, self.2, scope)
                         } else {
                             Intent::done()
                         }
@@ -525,38 +1416,65 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
             Processing(m, respimp, close, _) => {
                 let mut resp = respimp.with(transport.output());
                 match m.timeout(&mut resp, scope) {
-                    Some((m, dline)) => Parser::complete(self.1,
+                    Some((m, dline)) => Parser::complete(self.1, self.2,
                                           scope, Some(m), resp, close, dline),
+                    None if reset_requested(&resp) => {
+                        Parser::intent_reset(self.1, self.2, scope)
+                    }
                     None => {
                         if !resp.is_started() {
                             M::emit_error_page(&HandlerTimeout, &mut resp,
                                 &self.1, scope);
-                            Parser::intent_flush(self.1, scope)
+                            Parser::intent_flush(self.1, self.2, scope)
                         } else {
                             Intent::done()
                         }
                     }
                 }
             }
+            Upgraded(m, respimp, _) => {
+                let mut resp = respimp.with(transport.output());
+                match m.timeout(&mut resp, scope) {
+                    Some((m, dline)) => Parser::upgraded(self.1, self.2,
+                                          scope, Some(m), resp, dline),
+                    None if reset_requested(&resp) => {
+                        Parser::intent_reset(self.1, self.2, scope)
+                    }
+                    None => Intent::done(),
+                }
+            }
         }
     }
-    fn wakeup(self,
-              transport: &mut Transport<Self::Socket>,
-              scope: &mut Scope<Self::Context>)
-              -> Intent<Self> {
+    fn on_wakeup(self,
+                 transport: &mut Transport<S>,
+                 scope: &mut Scope<M::Context>)
+                 -> Intent<Self> {
         use self::ParserImpl::*;
         match self.0 {
-            Idle => Parser::intent_idle(self.1, scope),
-            ReadHeaders => Parser::intent_headers(self.1, scope,
+            Idle => Parser::intent_idle(self.1, self.2, scope),
+            Rejected => Parser::intent_rejected(self.1, scope),
+            Draining => Parser::intent_drain(self.1, self.2, scope),
+            Reset => reset_connection(transport),
+            ReadHeaders => Parser::intent_headers(self.1, self.2, scope,
                     transport.input().len()),
-            DoneResponse => Parser::intent_flush(self.1, scope),
+            DoneResponse(dline) => Parser::flush_until(self.1, self.2, dline),
             ReadingBody(rb) => {
                 let mut resp = rb.response.with(transport.output());
+                if rb.paused {
+                    resp.pause_body_read();
+                }
                 let m = rb.machine.and_then(|m| m.wakeup(&mut resp, scope));
-                Parser::intent_body(self.1, ReadBody {
+                if reset_requested(&resp) {
+                    return Parser::intent_reset(self.1, self.2, scope);
+                }
+                Parser::intent_body(self.1, self.2, ReadBody {
                     machine: m,
                     deadline: rb.deadline,
+                    max_deadline: rb.max_deadline,
+                    chunks_left: rb.chunks_left,
                     progress: rb.progress,
+                    paused: body_paused(&resp),
+                    flush: flush_requested(&resp),
                     response: state(resp),
                     connection_close: rb.connection_close,
                 })
@@ -564,16 +1482,22 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
             Processing(m, respimp, close, dline) => {
                 let mut resp = respimp.with(transport.output());
                 let mres = m.wakeup(&mut resp, scope);
-                Parser::complete(self.1, scope, mres, resp, close, dline)
+                Parser::complete(self.1, self.2, scope,
+                    mres, resp, close, dline)
+            }
+            Upgraded(m, respimp, dline) => {
+                let mut resp = respimp.with(transport.output());
+                let mres = m.wakeup(&mut resp, scope);
+                Parser::upgraded(self.1, self.2, scope, mres, resp, dline)
             }
         }
     }
 
-    fn exception(self,
-                 transport: &mut Transport<Self::Socket>,
-                 reason: Exception,
-                 scope: &mut Scope<Self::Context>)
-                 -> Intent<Self> {
+    fn on_exception(self,
+                    transport: &mut Transport<S>,
+                    reason: Exception,
+                    scope: &mut Scope<M::Context>)
+                    -> Intent<Self> {
         use rotor_stream::Exception::*;
         use self::BodyProgress::*;
         use self::ParserImpl::*;
@@ -583,29 +1507,46 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
                 if let ReadingBody(rb) = self.0 {
                     assert!(matches!(rb.progress,
                         ProgressiveChunked(_, _, 0) |  // TODO(tailhook) why?
-                        BufferChunked(_, _, 0)));
+                        BufferChunked(_, _, 0) |
+                        FramesChunked(_, _, _, 0) |
+                        BufferTrailers(..) | ProgressiveTrailers));
+                    let error = match rb.progress {
+                        BufferTrailers(..) | ProgressiveTrailers => {
+                            HeadersAreTooLarge
+                        }
+                        _ => PayloadTooLarge,
+                    };
                     let mut resp = rb.response.with(transport.output());
                     rb.machine.map(|m| m.bad_request(&mut resp, scope));
                     if !resp.is_started() {
-                        M::emit_error_page(&PayloadTooLarge, &mut resp,
+                        M::emit_error_page(&error, &mut resp,
                             &self.1, scope);
                     }
                     if resp.is_complete() {
-                        return Parser::intent_flush(self.1, scope)
+                        return Parser::intent_flush(self.1, self.2, scope)
                     }
                 }
             }
             EndOfStream => {
-                if let ReadingBody(rb) = self.0 {
-                    let mut resp = rb.response.with(transport.output());
-                    rb.machine.map(|m| m.bad_request(&mut resp, scope));
-                    if !resp.is_started() {
-                        M::emit_error_page(&PrematureEndOfStream,
-                            &mut resp, &self.1, scope);
+                match self.0 {
+                    ReadingBody(rb) => {
+                        let mut resp = rb.response.with(transport.output());
+                        rb.machine.map(|m| m.bad_request(&mut resp, scope));
+                        if !resp.is_started() {
+                            M::emit_error_page(&PrematureEndOfStream,
+                                &mut resp, &self.1, scope);
+                        }
+                        if resp.is_complete() {
+                            return Parser::intent_flush(self.1, self.2, scope);
+                        }
                     }
-                    if resp.is_complete() {
-                        return Parser::intent_flush(self.1, scope);
+                    Upgraded(m, respimp, _) => {
+                        // Not an error for the upgraded connection
+                        let mut resp = respimp.with(transport.output());
+                        m.request_end(&mut resp, scope);
+                        return Intent::done();
                     }
+                    _ => (),
                 }
             }
             _ => (),
@@ -613,6 +1554,72 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
         info!("Error handing connection: {}", reason);
         Intent::done()
     }
+}
+
+impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
+    type Context = M::Context;
+    type Socket = S;
+    type Seed = M::Seed;
+    fn create(seed: Self::Seed,
+              sock: &mut Self::Socket,
+              scope: &mut Scope<Self::Context>)
+              -> Intent<Self> {
+        let mut slot = None;
+        if let Some(tcp) = Any::downcast_ref::<TcpStream>(&*sock) {
+            M::configure_socket(&seed, tcp, scope);
+            let limit = M::client_limit(&seed, scope);
+            if let (Some(limit), Ok(addr)) = (limit, tcp.peer_addr()) {
+                match limit.acquire(addr.ip()) {
+                    Some(s) => slot = Some(Box::new(s)),
+                    None => return Parser::intent_rejected(seed, scope),
+                }
+            }
+        }
+        let conn = Conn { slot: slot, queued: 0 };
+        Parser::intent_idle(seed, conn, scope)
+    }
+    fn bytes_read(self,
+                  transport: &mut Transport<Self::Socket>,
+                  end: usize,
+                  scope: &mut Scope<Self::Context>)
+                  -> Intent<Self> {
+        self.guard(transport, scope, |me, transport, scope| {
+            me.on_bytes_read(transport, end, scope)
+        })
+    }
+    fn bytes_flushed(self,
+                     transport: &mut Transport<Self::Socket>,
+                     scope: &mut Scope<Self::Context>)
+                     -> Intent<Self> {
+        self.guard(transport, scope, |me, transport, scope| {
+            me.on_bytes_flushed(transport, scope)
+        })
+    }
+    fn timeout(self,
+               transport: &mut Transport<Self::Socket>,
+               scope: &mut Scope<Self::Context>)
+               -> Intent<Self> {
+        self.guard(transport, scope, |me, transport, scope| {
+            me.on_timeout(transport, scope)
+        })
+    }
+    fn wakeup(self,
+              transport: &mut Transport<Self::Socket>,
+              scope: &mut Scope<Self::Context>)
+              -> Intent<Self> {
+        self.guard(transport, scope, |me, transport, scope| {
+            me.on_wakeup(transport, scope)
+        })
+    }
+    fn exception(self,
+                 transport: &mut Transport<Self::Socket>,
+                 reason: Exception,
+                 scope: &mut Scope<Self::Context>)
+                 -> Intent<Self> {
+        self.guard(transport, scope, move |me, transport, scope| {
+            me.on_exception(transport, reason, scope)
+        })
+    }
     fn fatal(self,
         reason: Exception,
         _scope: &mut Scope<Self::Context>)
@@ -627,14 +1634,65 @@ impl<M: Server, S: StreamSocket> Protocol for Parser<M, S> {
 mod test {
     #[cfg(feature="nightly")]
     use test::Bencher;
+    use std::borrow::Cow;
     use std::default::Default;
     use std::time::Duration;
     use std::str::from_utf8;
+    use std::ascii::AsciiExt;
     use rotor_test::{MemIo, MockLoop};
-    use rotor_stream::{Stream, Accepted};
+    use rotor_stream::{Stream, Accepted, Buf};
     use rotor::{Scope, Time, EventSet, Machine};
-    use super::Parser;
-    use super::super::{Server, Head, Response, RecvMode};
+    use httparse::{EMPTY_HEADER, Request, Header};
+    use super::{Parser, scan_raw_request};
+    use super::super::{Server, Head, Response, RecvMode, HttpError};
+    use super::super::{HeaderDecision, BodyKind, Router};
+    use super::super::MAX_HEADERS_SIZE;
+    use super::super::{Version, AutoHeaders, RequestError};
+    use super::super::choose_error_format;
+    use super::super::response::{set_error_format, set_reason_phrases};
+
+    // Generates handlers which must not be called by the tests which use
+    // the server, e.g. `request_chunk` for the one reading buffered bodies
+    macro_rules! unused {
+        () => {};
+        (headers_received $($rest:ident)*) => {
+            fn headers_received(_seed: Self::Seed, _head: Head,
+                _response: &mut Response, _scope: &mut Scope<Self::Context>)
+                -> Option<(Self, RecvMode, Time)>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+        (request_received $($rest:ident)*) => {
+            fn request_received(self, _data: &[u8], _response: &mut Response,
+                _scope: &mut Scope<Self::Context>) -> Option<Self>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+        (request_chunk $($rest:ident)*) => {
+            fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+                _scope: &mut Scope<Self::Context>) -> Option<Self>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+        (request_end $($rest:ident)*) => {
+            fn request_end(self, _response: &mut Response,
+                _scope: &mut Scope<Self::Context>) -> Option<Self>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+        (timeout $($rest:ident)*) => {
+            fn timeout(self, _response: &mut Response,
+                _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+        (wakeup $($rest:ident)*) => {
+            fn wakeup(self, _response: &mut Response,
+                _scope: &mut Scope<Self::Context>) -> Option<Self>
+            { unreachable!(); }
+            unused!($($rest)*);
+        };
+    }
 
     #[derive(Debug, PartialEq, Eq, Default)]
     pub struct Context {
@@ -642,6 +1700,7 @@ mod test {
         headers_received: usize,
         chunks_received: usize,
         body: String,
+        bodies_received: usize,
         requests_received: usize,
     }
 
@@ -654,6 +1713,7 @@ mod test {
     impl Server for Proto {
         type Seed = ();
         type Context = Context;
+        unused!(timeout wakeup);
         fn headers_received((): (), _head: Head, _response: &mut Response,
             scope: &mut Scope<Self::Context>)
             -> Option<(Self, RecvMode, Time)>
@@ -667,6 +1727,12 @@ mod test {
                     scope.now() + Duration::new(10, 0)))
             }
         }
+        fn body_received(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.bodies_received += 1;
+            Some(self)
+        }
         fn request_received(self, data: &[u8], _response: &mut Response,
             scope: &mut Scope<Self::Context>) -> Option<Self>
         {
@@ -687,160 +1753,1628 @@ mod test {
             scope.requests_received += 1;
             Some(Proto::Done)
         }
-        fn timeout(self, _response: &mut Response,
-            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
-        { unimplemented!(); }
-        fn wakeup(self, _response: &mut Response,
+        fn request_trailers(self, trailers: &[Header],
+            _response: &mut Response, scope: &mut Scope<Self::Context>)
+            -> Option<Self>
+        {
+            for t in trailers {
+                scope.body.push_str(&format!("[{}: {}]",
+                    t.name, from_utf8(t.value).unwrap()));
+            }
+            Some(self)
+        }
+    }
+
+    pub struct Paused;
+
+    impl Server for Paused {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Paused, RecvMode::Progressive(1),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, chunk: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.chunks_received += 1;
+            response.pause_body_read();
+            Some(Paused)
+        }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            Some(Paused)
+        }
+        fn wakeup(self, response: &mut Response,
             _scope: &mut Scope<Self::Context>) -> Option<Self>
-        { unimplemented!(); }
+        {
+            response.resume_body_read();
+            Some(Paused)
+        }
     }
 
-    #[test]
-    fn parser_size() {
-        // Just to keep track of size of structure
-        assert_eq!(::std::mem::size_of::<Parser<Proto, MemIo>>(), 80);
+    /// Echoes each chunk of the request body while reading it
+    pub struct Duplex;
+
+    impl Server for Duplex {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout);
+        fn headers_received((): (), _head: Head, response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            response.status(200, "OK");
+            response.add_chunked().unwrap();
+            response.done_headers().unwrap();
+            Some((Duplex, RecvMode::Progressive(1),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, chunk: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.chunks_received += 1;
+            response.write_body(chunk);
+            response.flush();
+            Some(Duplex)
+        }
+        fn request_end(self, response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            response.done();
+            None
+        }
+        fn wakeup(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            // marks that the echoed chunk is sent
+            scope.body.push_str("|");
+            Some(Duplex)
+        }
     }
 
+    pub struct Events(usize);
 
-    #[test]
-    fn test_zero_body() {
-        let mut io = MemIo::new();
-        let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\r\nContent-Length: 0\r\n\
-                       Connection: close\r\n\r\n".as_bytes());
-        let m = Stream::<Parser<Proto, MemIo>>::accepted(
-            io.clone(), (), &mut lp.scope(1)).expect_machine();
-        m.ready(EventSet::readable(), &mut lp.scope(1))
-            .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 1,
-            body: String::from(""),
-            chunks_received: 0,
-            requests_received: 1,
-        });
+    impl Events {
+        fn event(self, response: &mut Response, scope: &mut Scope<Context>)
+            -> Option<Self>
+        {
+            if self.0 == 3 {
+                response.done();
+                return None;
+            }
+            let data = format!("data: {}\n\n", self.0);
+            scope.body.push_str(&data);
+            response.write_body(data.as_bytes());
+            response.flush();
+            Some(Events(self.0 + 1))
+        }
     }
 
-    #[test]
-    fn test_partial_headers() {
-        let mut io = MemIo::new();
-        let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\r\nContent-".as_bytes());
-        let m = Stream::<Parser<Proto, MemIo>>::accepted(
-            io.clone(), (), &mut lp.scope(1)).expect_machine();
-        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
-            .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 0,
-            body: String::new(),
-            chunks_received: 0,
-            requests_received: 0,
-        });
-        io.push_bytes("Length: 0\r\n\r\n".as_bytes());
-        m.ready(EventSet::readable(), &mut lp.scope(1))
-            .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 1,
-            body: String::new(),
-            chunks_received: 0,
-            requests_received: 1,
-        });
+    impl Server for Events {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_chunk request_end timeout);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Events(0), RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            response.status(200, "OK");
+            response.add_header("Content-Type", b"text/event-stream")
+                .unwrap();
+            response.add_chunked().unwrap();
+            response.done_headers().unwrap();
+            self.event(response, scope)
+        }
+        fn wakeup(self, response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            self.event(response, scope)
+        }
     }
 
-    #[test]
-    fn test_empty_chunked() {
-        let mut io = MemIo::new();
-        let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
-                       Connection: close\r\n\r\n".as_bytes());
-        let m = Stream::<Parser<Proto, MemIo>>::accepted(
-            io.clone(), (), &mut lp.scope(1)).expect_machine();
-        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
-            .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 1,
-            body: String::new(),
-            chunks_received: 0,
-            requests_received: 0,
-        });
-        io.push_bytes("0\r\n\r\n".as_bytes());
+    pub struct Pipelined;
+
+    impl Server for Pipelined {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Pipelined, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            response.send_body(200, "OK", b"text/plain", b"hello");
+            None
+        }
+        fn max_pipeline_depth(_seed: &(),
+            _scope: &mut Scope<Self::Context>) -> usize
+        {
+            2
+        }
+    }
+
+    pub struct NoFooter;
+
+    impl Server for NoFooter {
+        type Seed = ();
+        type Context = Context;
+        unused!(headers_received request_received request_chunk request_end
+            timeout wakeup);
+        fn error_page_footer(_seed: &(), _scope: &mut Scope<Self::Context>)
+            -> Option<String>
+        {
+            None
+        }
+        fn reason_phrase(code: u16) -> Option<&'static str> {
+            if code == 404 { Some("Nope") } else { None }
+        }
+    }
+
+    struct NotFound;
+
+    impl HttpError for NotFound {
+        fn http_status(&self) -> (u16, &'static str) {
+            (404, "Not Found")
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Default)]
+    pub struct ErrorContext {
+        strict: bool,
+        headers_received: usize,
+        error_pages: Vec<u16>,
+    }
+
+    fn error_page(code: &HttpError, response: &mut Response,
+        scope: &mut Scope<ErrorContext>)
+    {
+        scope.error_pages.push(code.http_status().0);
+        response.status(code.http_status().0, code.http_status().1);
+        response.add_length(0).unwrap();
+        response.done_headers().unwrap();
+        response.done();
+    }
+
+    /// Panics in `headers_received` for GET, and on the body for POST
+    pub struct Panicky;
+
+    impl Server for Panicky {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_end timeout wakeup);
+        fn headers_received((): (), head: Head, response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            if head.method == "GET" {
+                panic!("handler failed");
+            }
+            scope.headers_received += 1;
+            if head.path == "/started" {
+                response.status(200, "OK");
+            }
+            Some((Panicky, RecvMode::Progressive(1),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            panic!("handler failed");
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+        fn catch_panics(_seed: &(), _scope: &mut Scope<Self::Context>)
+            -> bool
+        {
+            true
+        }
+    }
+
+    pub struct Abort;
+
+    impl Server for Abort {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            response.status(200, "OK");
+            response.reset_connection();
+            None
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+    }
+
+    /// Asks for an upgrade regardless of the request
+    pub struct Misupgrade;
+
+    impl Server for Misupgrade {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Misupgrade, RecvMode::Upgrade,
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+    }
+
+    /// Asks for frames of zero size
+    pub struct ZeroFrames;
+
+    impl Server for ZeroFrames {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((ZeroFrames, RecvMode::Frames(0, 4),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+    }
+
+    pub struct Strict;
+
+    impl Server for Strict {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Strict, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { Some(Strict) }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+        fn max_duplicate_headers(_seed: &(), name: &str,
+            _scope: &mut Scope<Self::Context>)
+            -> usize
+        {
+            if name.eq_ignore_ascii_case("Cookie") { 3 } else { 100 }
+        }
+        fn max_header_value_size(_seed: &(),
+            _scope: &mut Scope<Self::Context>)
+            -> usize
+        {
+            4096
+        }
+        fn inspect_headers(_seed: &(), headers: &[Header],
+            _scope: &mut Scope<Self::Context>)
+            -> HeaderDecision
+        {
+            // only canonical and lowercase forms are used by real clients
+            let odd_length = headers.iter().any(|h| {
+                h.name.eq_ignore_ascii_case("Content-Length") &&
+                h.name != "Content-Length" && h.name != "content-length"
+            });
+            if odd_length {
+                HeaderDecision::Reject(403)
+            } else {
+                HeaderDecision::Accept
+            }
+        }
+        fn strict_line_endings(_seed: &(), scope: &mut Scope<Self::Context>)
+            -> bool
+        {
+            scope.strict
+        }
+        fn strict_framing(_seed: &(), scope: &mut Scope<Self::Context>)
+            -> bool
+        {
+            scope.strict
+        }
+    }
+
+    /// Gets `OPTIONS` and disallowed methods on `/todo/*` answered
+    pub struct Routed;
+
+    impl Server for Routed {
+        type Seed = ();
+        type Context = ErrorContext;
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Routed, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<Self>
+        { Some(Routed) }
+        unused!(request_chunk request_end timeout wakeup);
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+        fn allowed_methods(_seed: &(), path: &str,
+            _scope: &mut Scope<Self::Context>)
+            -> Vec<String>
+        {
+            let mut router = Router::new();
+            router.add("GET", "/todo/*", ())
+                  .add("PATCH", "/todo/*", ())
+                  .add("DELETE", "/todo/*", ());
+            router.allowed_methods_for(path).iter()
+                .map(|x| x.to_string()).collect()
+        }
+    }
+
+    /// Separates received frames by `|`
+    pub struct Framed;
+
+    impl Server for Framed {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Framed, RecvMode::Frames(4, 3),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, chunk: &[u8], _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.body.push('|');
+            scope.chunks_received += 1;
+            Some(Framed)
+        }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            Some(Framed)
+        }
+    }
+
+    pub struct Trickle;
+
+    impl Server for Trickle {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_chunk request_end wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Trickle, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn timeout(self, _response: &mut Response,
+            _scope: &mut Scope<Self::Context>) -> Option<(Self, Time)>
+        {
+            // must not be asked when the limit is reached
+            unreachable!();
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+        fn max_request_duration(_seed: &(),
+            _scope: &mut Scope<Self::Context>)
+            -> Option<Duration>
+        {
+            Some(Duration::new(0, 0))
+        }
+    }
+
+    /// Accepts at most three chunks in the body
+    pub struct FewChunks;
+
+    impl Server for FewChunks {
+        type Seed = ();
+        type Context = ErrorContext;
+        unused!(request_received request_chunk request_end timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((FewChunks, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn emit_error_page(code: &HttpError, response: &mut Response,
+            _seed: &(), scope: &mut Scope<Self::Context>)
+        {
+            error_page(code, response, scope)
+        }
+        fn max_chunks(_seed: &(), _scope: &mut Scope<Self::Context>)
+            -> Option<usize>
+        {
+            Some(3)
+        }
+    }
+
+    /// Marks the start of each chunk with its size
+    pub struct Chunky;
+
+    impl Server for Chunky {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout wakeup);
+        fn headers_received((): (), _head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            Some((Chunky, RecvMode::Progressive(1),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn chunk_started(self, chunk_len: u64, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(&format!("<{}>", chunk_len));
+            Some(Chunky)
+        }
+        fn request_chunk(self, chunk: &[u8], _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.chunks_received += 1;
+            Some(Chunky)
+        }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            Some(Chunky)
+        }
+    }
+
+    /// Echoes everything back after the upgrade
+    pub struct Echo;
+
+    impl Server for Echo {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout wakeup);
+        fn headers_received((): (), head: Head, response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            assert_eq!(head.body_kind, BodyKind::Upgrade);
+            response.status(101, "Switching Protocols");
+            response.add_header("Connection", b"Upgrade").unwrap();
+            response.add_header("Upgrade", b"echo").unwrap();
+            response.done_headers().unwrap();
+            response.done();
+            Some((Echo, RecvMode::Upgrade,
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, chunk: &[u8], response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.chunks_received += 1;
+            response.write_raw(chunk);
+            Some(Echo)
+        }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            None
+        }
+    }
+
+    pub struct Hinted;
+
+    impl Server for Hinted {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_received timeout wakeup);
+        fn headers_received((): (), head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            scope.body.push_str(&format!("{:?};", head.size_hint));
+            Some((Hinted, RecvMode::Progressive(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_chunk(self, chunk: &[u8], _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.body.push_str(from_utf8(chunk).unwrap());
+            scope.chunks_received += 1;
+            Some(Hinted)
+        }
+        fn request_end(self, _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            None
+        }
+        fn size_hint_header(_seed: &(), _scope: &mut Scope<Self::Context>)
+            -> Option<&'static str>
+        {
+            Some("X-Content-Length")
+        }
+    }
+
+    pub struct Prefixed;
+
+    impl Server for Prefixed {
+        type Seed = ();
+        type Context = Context;
+        unused!(request_chunk request_end timeout wakeup);
+        fn headers_received((): (), head: Head, _response: &mut Response,
+            scope: &mut Scope<Self::Context>)
+            -> Option<(Self, RecvMode, Time)>
+        {
+            scope.headers_received += 1;
+            scope.body.push_str(head.path);
+            scope.body.push_str(";");
+            Some((Prefixed, RecvMode::Buffered(1000),
+                scope.now() + Duration::new(10, 0)))
+        }
+        fn request_received(self, _data: &[u8], _response: &mut Response,
+            scope: &mut Scope<Self::Context>) -> Option<Self>
+        {
+            scope.requests_received += 1;
+            None
+        }
+        fn rewrite_path<'x>(_seed: &(), path: &'x str,
+            _scope: &mut Scope<Self::Context>)
+            -> Cow<'x, str>
+        {
+            if path == "/api" {
+                Cow::Borrowed("/")
+            } else if path.starts_with("/api/") {
+                Cow::Borrowed(&path[4..])
+            } else {
+                Cow::Borrowed(path)
+            }
+        }
+    }
+
+    #[test]
+    fn parser_size() {
+        // Just to keep track of size of structure
+        assert_eq!(::std::mem::size_of::<Parser<Proto, MemIo>>(), 120);
+    }
+
+
+    #[test]
+    fn test_zero_body() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 0\r\n\
+                       Connection: close\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from(""),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_zero_body_progressive() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            Context { progressive: true, ..Default::default() });
+        io.push_bytes("DELETE /x HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 0\r\n\r\n\
+                       GET / HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: true,
+            headers_received: 1,
+            body: String::from(""),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_pause_body_read() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 10\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<Paused, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().body, "hello");
+        assert_eq!(lp.ctx().chunks_received, 1);
+        io.push_bytes("world".as_bytes());
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().body, "hello");
+        assert_eq!(lp.ctx().chunks_received, 1);
+        let m = m.wakeup(&mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("helloworld"),
+            chunks_received: 2,
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_flush_events() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /events HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Events, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable() | EventSet::writable(),
+                &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("data: 0\n\ndata: 1\n\ndata: 2\n\n"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_full_duplex() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST /echo HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 10\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<Duplex, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable() | EventSet::writable(),
+                        &mut lp.scope(1))
+            .expect_machine();
+        // the chunk is sent before the rest of the body is received
+        assert_eq!(lp.ctx().body, "hello|");
+        assert_eq!(lp.ctx().requests_received, 0);
+        io.push_bytes("world".as_bytes());
+        m.ready(EventSet::readable() | EventSet::writable(),
+                &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("hello|world"),
+            chunks_received: 2,
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    fn content_length_request(headers: &str, expected: Context) {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes(format!("POST / HTTP/1.1\r\n\
+                               Host: example.com\r\n{}\r\nhello", headers)
+                      .as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), expected);
+    }
+
+    fn hello() -> Context {
+        Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("hello"),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 1,
+        }
+    }
+
+    #[test]
+    fn test_identical_content_length() {
+        content_length_request("Content-Length: 5, 5\r\n", hello());
+        content_length_request("Content-Length: 5,5\r\n", hello());
+        content_length_request(
+            "Content-Length: 5\r\nContent-Length: 5\r\n", hello());
+    }
+
+    #[test]
+    fn test_content_length_whitespace() {
+        content_length_request("Content-Length: 5 \r\n", hello());
+        content_length_request("Content-Length:\t5\t\r\n", hello());
+        content_length_request("Content-Length: 5 ,\t5  \r\n", hello());
+        content_length_request("Content-Length: 5 5\r\n",
+                               Default::default());
+    }
+
+    #[test]
+    fn test_content_length_digits() {
+        content_length_request("Content-Length: 05\r\n", hello());
+        for value in &["+5", "-5", "0x5", "5a", "", "5,+5"] {
+            content_length_request(
+                &format!("Content-Length: {}\r\n", value),
+                Default::default());
+        }
+    }
+
+    #[test]
+    fn test_conflicting_content_length() {
+        content_length_request(
+            "Content-Length: 5\r\nContent-Length: 6\r\n",
+            Default::default());
+        content_length_request("Content-Length: 5, 6\r\n",
+                               Default::default());
+        content_length_request("Content-Length: 5,\r\n",
+                               Default::default());
+    }
+
+    #[test]
+    fn test_partial_headers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 0,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 0,
+            bodies_received: 0,
+        });
+        io.push_bytes("Length: 0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_empty_chunked() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\
+                       Connection: close\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 0,
+            bodies_received: 0,
+        });
+        io.push_bytes("0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_one_chunk() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\
+                       Connection: close\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::new(),
+            chunks_received: 0,
+            requests_received: 0,
+            bodies_received: 0,
+        });
+        io.push_bytes("5\r\nrotor\r\n0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            body: String::from("rotor"),
+            chunks_received: 0,
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_chunked_encoding() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\
+                       Connection: close\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::new(),
+            requests_received: 0,
+            bodies_received: 0,
+        });
+        io.push_bytes("4\r\n\
+                       Wiki\r\n\
+                       5\r\n\
+                       pedia\r\n\
+                       E\r\n in\r\n\
+                       \r\n\
+                       chunks.\r\n\
+                       0\r\n\
+                       \r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("Wikipedia in\r\n\r\nchunks."),
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    const PATCH: &'static str = "PATCH /todo/1 HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: 30\r\n\r\n\
+        {\"title\":\"x\",\"completed\":true}";
+
+    #[test]
+    fn test_patch() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes(PATCH.as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("{\"title\":\"x\",\"completed\":true}"),
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_patch_progressive() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            Context { progressive: true, ..Default::default() });
+        io.push_bytes(PATCH.as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: true,
+            headers_received: 1,
+            chunks_received: 1,
+            body: String::from("{\"title\":\"x\",\"completed\":true}"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_chunked_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\nX-Checksum: 1234\r\n\
+                       X-Other: x\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("[X-Checksum: 1234][X-Other: x]Wiki"),
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_chunk_started() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       3\r\nabc\r\n1\r\nd\r\n0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Chunky, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 2,
+            body: String::from("<3>abc<1>d"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_too_many_chunks() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ErrorContext::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       1\r\na\r\n1\r\nb\r\n1\r\nc\r\n1\r\nd\r\n\
+                       1\r\ne\r\n1\r\nf\r\n0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<FewChunks, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // the body is small, but the fourth chunk is over the limit
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_fixed_body_too_large() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ErrorContext::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 5000\r\n\r\n\
+                       hello".as_bytes());
+        let m = Stream::<Parser<FewChunks, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // larger than the buffer, so rejected before reading the body
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![413],
+        });
+    }
+
+    #[test]
+    fn test_progressive_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            Context { progressive: true, ..Default::default() });
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\nX-Checksum: 1234\r\n\r\n"
+                       .as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: true,
+            headers_received: 1,
+            chunks_received: 1,
+            body: String::from("Wiki[X-Checksum: 1234]"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_bad_trailers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\nNo colon here\r\n\r\n"
+                       .as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_trailers_too_large() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\nX-Trailer: ".as_bytes());
+        let value = (0..MAX_HEADERS_SIZE).map(|_| 'a').collect::<String>();
+        io.push_bytes(value.as_bytes());
+        io.push_bytes("\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![431],
+        });
+    }
+
+    #[test]
+    fn test_zero_length_and_chunked() {
+        // Transfer-Encoding takes precedence, if front-end proxy trusts
+        // `Content-Length: 0` instead, it can't smuggle the chunked body
+        // as the next request because the connection is closed afterwards
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 0\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from("Wiki"),
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_zero_length_and_chunked_strict() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            ErrorContext { strict: true, ..Default::default() });
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 0\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n\
+                       4\r\nWiki\r\n0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: true,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    fn scan_close(data: &str) -> bool {
+        let mut headers = [EMPTY_HEADER; 16];
+        let mut raw_request = Request::new(&mut headers);
+        raw_request.parse(data.as_bytes()).unwrap();
+        scan_raw_request(&raw_request, &mut |_: &str| 100, 1024, false)
+            .unwrap().3
+    }
+
+    #[test]
+    fn test_connection_tokens() {
+        assert!(scan_close("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                            Connection: close, upgrade\r\n\r\n"));
+        assert!(scan_close("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                            Connection: Upgrade,Close\r\n\r\n"));
+        assert!(scan_close("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                            Connection: keep-alive\r\n\
+                            Connection: TE, close\r\n\r\n"));
+        assert!(!scan_close("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                             Connection: keep-alive, Upgrade\r\n\r\n"));
+        assert!(!scan_close("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                             Connection: closed, x-close\r\n\r\n"));
+        assert!(!scan_close("GET / HTTP/1.0\r\n\
+                             Connection: keep-alive\r\n\r\n"));
+        assert!(scan_close("GET / HTTP/1.0\r\n\
+                            Connection: Keep-Alive, close\r\n\r\n"));
+        assert!(scan_close("GET / HTTP/1.0\r\n\r\n"));
+    }
+
+    fn scan_body(data: &str) -> BodyKind {
+        let mut headers = [EMPTY_HEADER; 16];
+        let mut raw_request = Request::new(&mut headers);
+        raw_request.parse(data.as_bytes()).unwrap();
+        scan_raw_request(&raw_request, &mut |_: &str| 100, 1024, false)
+            .unwrap().0
+    }
+
+    #[test]
+    fn test_upgrade_body_kind() {
+        assert_eq!(scan_body("GET / HTTP/1.1\r\n\
+                              Host: example.com\r\nConnection: Upgrade\r\n\
+                              Upgrade: websocket\r\n\r\n"),
+                   BodyKind::Upgrade);
+        assert_eq!(scan_body("CONNECT example.com:443 HTTP/1.1\r\n\
+                              Host: example.com\r\n\r\n"),
+                   BodyKind::Upgrade);
+        // no `Upgrade` header
+        assert_eq!(scan_body("GET / HTTP/1.1\r\n\
+                              Host: example.com\r\n\
+                              Connection: Upgrade\r\n\r\n"),
+                   BodyKind::Fixed(0));
+        // no `upgrade` connection token
+        assert_eq!(scan_body("GET / HTTP/1.1\r\n\
+                              Host: example.com\r\nUpgrade: websocket\r\n\r\n"),
+                   BodyKind::Fixed(0));
+        // request with body can't be upgraded
+        assert_eq!(scan_body("POST / HTTP/1.1\r\n\
+                              Host: example.com\r\nConnection: Upgrade\r\n\
+                              Upgrade: websocket\r\n\
+                              Content-Length: 5\r\n\r\n"),
+                   BodyKind::Fixed(5));
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\n\
+                       Host: example.com\r\nConnection: Upgrade\r\n\
+                       Upgrade: echo\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Echo, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // bytes received together with headers are not lost
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 1,
+            body: String::from("hello"),
+            requests_received: 0,
+            bodies_received: 0,
+        });
+        // and no HTTP framing is applied afterwards
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 2,
+            body: String::from("helloGET / HTTP/1.1\r\n\
+                                Host: example.com\r\n\r\n"),
+            requests_received: 0,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_upgrade_ignored() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /chat HTTP/1.1\r\n\
+                       Host: example.com\r\nConnection: Upgrade\r\n\
+                       Upgrade: echo\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from(""),
+            requests_received: 1,
+            bodies_received: 1,
+        });
+    }
+
+    #[test]
+    fn test_max_request_duration() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ErrorContext::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 10\r\n\r\n\
+                       h".as_bytes());
+        let m = Stream::<Parser<Trickle, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        io.push_bytes("e".as_bytes());
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // the deadline of the handler is 10 seconds away
+        m.timeout(&mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![408],
+        });
+    }
+
+    #[test]
+    fn test_reset_connection() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ErrorContext::default());
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                       GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Abort, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // the incomplete response is discarded instead of the error page
+        // and the pipelined request is never processed
+        m.timeout(&mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_unexpected_upgrade() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(ErrorContext::default());
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Misupgrade, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![500],
+        });
+    }
+
+    #[test]
+    fn test_zero_frame_size() {
+        for req in &["GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                     "POST / HTTP/1.1\r\nHost: example.com\r\n\
+                      Content-Length: 5\r\n\r\nhello"]
+        {
+            let mut io = MemIo::new();
+            let mut lp = MockLoop::new(ErrorContext::default());
+            io.push_bytes(req.as_bytes());
+            let m = Stream::<Parser<ZeroFrames, MemIo>>::accepted(
+                io.clone(), (), &mut lp.scope(1)).expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+            assert_eq!(*lp.ctx(), ErrorContext {
+                strict: false,
+                headers_received: 1,
+                error_pages: vec![500],
+            });
+        }
+    }
+
+    #[test]
+    fn test_frames_fixed() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 10\r\n\r\n\
+                       abcde".as_bytes());
+        let m = Stream::<Parser<Framed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(lp.ctx().body, "abcd|");
+        io.push_bytes("fghij".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 3,
+            body: String::from("abcd|efgh|ij|"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_frames_chunked() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       3\r\nabc\r\n4\r\ndefg\r\n2\r\nhi".as_bytes());
+        let m = Stream::<Parser<Framed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        // the last byte of the third chunk is not taken until CRLF
+        assert_eq!(lp.ctx().body, "abcd|efgh|");
+        io.push_bytes("\r\n1\r\nj\r\n0\r\n\r\n".as_bytes());
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 3,
+            body: String::from("abcd|efgh|ij|"),
+            requests_received: 1,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_frames_too_large() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 13\r\n\r\n\
+                       abcdefghijklm".as_bytes());
+        let m = Stream::<Parser<Framed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // more than 3 frames of 4 bytes, so rejected before reading
+        assert_eq!(*lp.ctx(), Context {
+            progressive: false,
+            headers_received: 1,
+            chunks_received: 0,
+            body: String::from(""),
+            requests_received: 0,
+            bodies_received: 0,
+        });
+    }
+
+    #[test]
+    fn test_pipelined_after_fixed_body() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 5\r\n\r\n\
+                       helloGET / HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
         m.ready(EventSet::readable(), &mut lp.scope(1))
             .expect_machine();
+        // next request is waiting for the response, not merged into body
         assert_eq!(*lp.ctx(), Context {
             progressive: false,
             headers_received: 1,
-            body: String::new(),
             chunks_received: 0,
+            body: String::from("hello"),
             requests_received: 1,
+            bodies_received: 1,
         });
     }
 
     #[test]
-    fn test_one_chunk() {
+    fn test_pipeline_depth() {
         let mut io = MemIo::new();
         let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
-                       Connection: close\r\n\r\n".as_bytes());
-        let m = Stream::<Parser<Proto, MemIo>>::accepted(
+        for _ in 0..5 {
+            io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                          .as_bytes());
+        }
+        let m = Stream::<Parser<Pipelined, MemIo>>::accepted(
             io.clone(), (), &mut lp.scope(1)).expect_machine();
-        let m = m.ready(EventSet::readable(), &mut lp.scope(1))
+        // at most two responses are buffered at once, the rest of the
+        // requests is parsed when the output is flushed
+        let m = m.ready(EventSet::readable() | EventSet::writable(),
+            &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::writable(), &mut lp.scope(1))
             .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 1,
-            body: String::new(),
-            chunks_received: 0,
-            requests_received: 0,
-        });
-        io.push_bytes("5\r\nrotor\r\n0\r\n\r\n".as_bytes());
-        m.ready(EventSet::readable(), &mut lp.scope(1))
+        m.ready(EventSet::writable(), &mut lp.scope(1))
             .expect_machine();
         assert_eq!(*lp.ctx(), Context {
             progressive: false,
-            headers_received: 1,
-            body: String::from("rotor"),
+            headers_received: 5,
             chunks_received: 0,
-            requests_received: 1,
+            body: String::from(""),
+            requests_received: 5,
+            bodies_received: 0,
         });
     }
 
     #[test]
-    fn test_chunked_encoding() {
+    fn test_idle_timeout() {
         let mut io = MemIo::new();
         let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
-                       Connection: close\r\n\r\n".as_bytes());
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Pipelined, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1)).expect_machine();
+        let m = m.ready(EventSet::readable() | EventSet::writable(),
+            &mut lp.scope(1)).expect_machine();
+        // the response is flushed, so the keep-alive connection is idle
+        assert!(m.timeout(&mut lp.scope(1)).is_stopped());
+        assert_eq!(lp.ctx().requests_received, 1);
+    }
+
+    #[test]
+    fn test_size_hint() {
+        for &(headers, expected) in &[
+            ("Transfer-Encoding: chunked\r\nX-Content-Length: 5\r\n",
+             "Some(5);hello"),
+            ("Transfer-Encoding: chunked\r\nx-content-length: five\r\n",
+             "None;hello"),
+            ("Transfer-Encoding: chunked\r\n", "None;hello"),
+        ] {
+            let mut io = MemIo::new();
+            let mut lp = MockLoop::new(Default::default());
+            io.push_bytes(format!("POST / HTTP/1.1\r\n\
+                                   Host: example.com\r\n{}\r\n\
+                                   5\r\nhello\r\n0\r\n\r\n", headers)
+                          .as_bytes());
+            let m = Stream::<Parser<Hinted, MemIo>>::accepted(
+                io.clone(), (), &mut lp.scope(1)).expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+            assert_eq!(*lp.ctx(), Context {
+                progressive: false,
+                headers_received: 1,
+                chunks_received: 1,
+                body: String::from(expected),
+                requests_received: 1,
+                bodies_received: 0,
+            });
+        }
+    }
+
+    #[test]
+    fn test_rewrite_path() {
+        for &(path, rewritten) in &[("/api/users?x=1", "/users?x=1;"),
+                                    ("/api", "/;"),
+                                    ("/apiary", "/apiary;")]
+        {
+            let mut io = MemIo::new();
+            let mut lp = MockLoop::new(Default::default());
+            io.push_bytes(format!("GET {} HTTP/1.1\r\n\
+                                   Host: example.com\r\n\r\n", path)
+                          .as_bytes());
+            let m = Stream::<Parser<Prefixed, MemIo>>::accepted(
+                io.clone(), (), &mut lp.scope(1)).expect_machine();
+            m.ready(EventSet::readable(), &mut lp.scope(1));
+            assert_eq!(*lp.ctx(), Context {
+                progressive: false,
+                headers_received: 1,
+                chunks_received: 0,
+                body: String::from(rewritten),
+                requests_received: 1,
+                bodies_received: 0,
+            });
+        }
+    }
+
+    #[test]
+    fn test_junk_after_close() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\nContent-Length: 5\r\n\
+                       Connection: close\r\n\r\n\
+                       hello0\r\n\r\nGET /admin HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n"
+                       .as_bytes());
         let m = Stream::<Parser<Proto, MemIo>>::accepted(
             io.clone(), (), &mut lp.scope(1)).expect_machine();
         let m = m.ready(EventSet::readable(), &mut lp.scope(1))
             .expect_machine();
-        assert_eq!(*lp.ctx(), Context {
-            progressive: false,
-            headers_received: 1,
-            chunks_received: 0,
-            body: String::new(),
-            requests_received: 0,
-        });
-        io.push_bytes("4\r\n\
-                       Wiki\r\n\
-                       5\r\n\
-                       pedia\r\n\
-                       E\r\n in\r\n\
-                       \r\n\
-                       chunks.\r\n\
-                       0\r\n\
-                       \r\n".as_bytes());
+        io.push_bytes("GET /other HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
         m.ready(EventSet::readable(), &mut lp.scope(1))
             .expect_machine();
         assert_eq!(*lp.ctx(), Context {
             progressive: false,
             headers_received: 1,
             chunks_received: 0,
-            body: String::from("Wikipedia in\r\n\r\nchunks."),
+            body: String::from("hello"),
             requests_received: 1,
+            bodies_received: 1,
         });
     }
 
@@ -849,7 +3383,8 @@ mod test {
         let mut io = MemIo::new();
         let mut lp = MockLoop::new(
             Context { progressive: true, ..Default::default() });
-        io.push_bytes("GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nTransfer-Encoding: chunked\r\n\
                        Connection: close\r\n\r\n".as_bytes());
         let m = Stream::<Parser<Proto, MemIo>>::accepted(
             io.clone(), (), &mut lp.scope(1)).expect_machine();
@@ -861,6 +3396,7 @@ mod test {
             chunks_received: 0,
             body: String::new(),
             requests_received: 0,
+            bodies_received: 0,
         });
         io.push_bytes("4\r\n\
                        Wiki\r\n\
@@ -879,6 +3415,7 @@ mod test {
             chunks_received: 1, // chunks are merged
             body: String::from("Wikipedia in\r\n\r\nchunks."),
             requests_received: 1,
+            bodies_received: 0,
         });
     }
 
@@ -886,7 +3423,7 @@ mod test {
     fn test_newline_delimited() {
         let mut io = MemIo::new();
         let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("GET / HTTP/1.1\n\
+        io.push_bytes("GET / HTTP/1.1\nHost: example.com\n\
             Content-Length: 0\n\
             Connection: close\n\n"
                           .as_bytes());
@@ -902,6 +3439,7 @@ mod test {
                        body: String::from(""),
                        chunks_received: 0,
                        requests_received: 1,
+                       bodies_received: 1,
                    });
     }
 
@@ -924,6 +3462,7 @@ mod test {
                        body: String::from(""),
                        chunks_received: 0,
                        requests_received: 1,
+                       bodies_received: 1,
                    });
     }
 
@@ -931,7 +3470,8 @@ mod test {
     fn test_crazy() {
         let mut io = MemIo::new();
         let mut lp = MockLoop::new(Default::default());
-        io.push_bytes("~36!$543&..JKLHfF+Dkjk /foo/$bar HTTP/1.1\r\n\r\n".as_bytes());
+        io.push_bytes("~36!$543&..JKLHfF+Dkjk /foo/$bar HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
         let m = Stream::<Parser<Proto, MemIo>>::accepted(
             io.clone(), (), &mut lp.scope(1))
             .expect_machine();
@@ -944,8 +3484,388 @@ mod test {
                        body: String::from(""),
                        chunks_received: 0,
                        requests_received: 1,
+                       bodies_received: 1,
                    });
     }
+    fn error_page<S: Server<Seed=(), Context=Context>>(code: &HttpError)
+        -> String
+    {
+        let mut lp = MockLoop::new(Default::default());
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            S::emit_error_page(code, &mut resp, &(), &mut lp.scope(1));
+            assert!(resp.is_complete());
+        }
+        String::from_utf8(buf[..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_error_page_footer() {
+        let code = RequestError::PayloadTooLarge;
+        assert!(error_page::<Proto>(&code).ends_with("\r\n\r\n\
+            <h1>413 Payload Too Large</h1>\n\
+            <p><small>Served for you by rotor-http</small></p>\n"));
+        assert!(error_page::<NoFooter>(&code).ends_with("\r\n\r\n\
+            <h1>413 Payload Too Large</h1>\n"));
+    }
+
+    #[test]
+    fn test_json_error_page() {
+        let mut lp = MockLoop::new(Default::default());
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            set_error_format(&mut resp,
+                choose_error_format(Some(b"application/json")));
+            Proto::emit_error_page(&RequestError::PayloadTooLarge,
+                &mut resp, &(), &mut lp.scope(1));
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!(
+            "HTTP/1.1 413 Payload Too Large\r\n",
+            "Content-Length: 47\r\n",
+            "Content-Type: application/json\r\n\r\n",
+            "{\"status\": 413, \"reason\": \"Payload Too Large\"}\n",
+            ).as_bytes());
+    }
+
+    #[test]
+    fn test_custom_reason_phrase() {
+        let page = error_page::<NoFooter>(&NotFound);
+        assert!(page.starts_with("HTTP/1.1 404 Nope\r\n"));
+        assert!(page.ends_with("\r\n\r\n<h1>404 Nope</h1>\n"));
+        assert!(error_page::<Proto>(&NotFound)
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_custom_reason_phrase_on_response() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            set_reason_phrases(&mut resp, NoFooter::reason_phrase);
+            resp.status_code(404);
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert!(buf[..].starts_with(b"HTTP/1.1 404 Nope\r\n"));
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /todo/abc HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Panicky, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![500],
+        });
+    }
+
+    #[test]
+    fn test_catch_body_panic() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\nHost: example.com\r\n\
+                       Content-Length: 5\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Panicky, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![500],
+        });
+    }
+
+    #[test]
+    fn test_catch_panic_started() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST /started HTTP/1.1\r\nHost: example.com\r\n\
+                       Content-Length: 5\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Panicky, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        // too late for the error page, so the connection is just closed
+        assert!(m.ready(EventSet::readable(), &mut lp.scope(1))
+            .is_stopped());
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_duplicate_headers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nCookie: a=1\r\ncookie: b=2\r\n\
+                       Cookie: c=3\r\nCOOKIE: d=4\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![431],
+        });
+    }
+
+    #[test]
+    fn test_header_value_too_large() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nCookie: ".as_bytes());
+        // within MAX_HEADERS_SIZE but above the limit of `Strict`
+        let value = (0..5000).map(|_| 'a').collect::<String>();
+        io.push_bytes(value.as_bytes());
+        io.push_bytes("\r\nAccept: */*\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![431],
+        });
+    }
+
+    #[test]
+    fn test_newline_delimited_strict() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            ErrorContext { strict: true, ..Default::default() });
+        io.push_bytes("GET / HTTP/1.1\nHost: example.com\n\
+            Content-Length: 0\n\
+            Connection: close\n\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: true,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_crlf_strict() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(
+            ErrorContext { strict: true, ..Default::default() });
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\
+            Content-Length: 0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: true,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_obs_fold() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nX-Folded: a\r\n b\r\n\
+                       Content-Length: 0\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_inspect_headers() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("POST / HTTP/1.1\r\n\
+                       Host: example.com\r\ncontent-length: 0\r\n\
+                       cOntent-Length: 5\r\n\r\nhello".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![403],
+        });
+    }
+
+    #[test]
+    fn test_options_answered() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("OPTIONS /todo/1 HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                       GET /todo/1 HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Routed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // OPTIONS is answered without the handler, the connection is kept
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("PUT /todo/1 HTTP/1.1\r\nHost: example.com\r\n\r\n\
+                       HEAD /todo/1 HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Routed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // HEAD is allowed as GET is
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_method_not_allowed_body() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("PUT /todo/1 HTTP/1.1\r\nHost: example.com\r\n\
+                       Content-Length: 5\r\n\r\nhello\
+                       GET /todo/1 HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Routed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        // The body isn't read, so the connection is closed
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_unknown_path_not_answered() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("OPTIONS /other HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Routed, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
+
+    #[test]
+    fn test_duplicate_host() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                       host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_control_chars_in_path() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET /a\x00\r\nb HTTP/1.1\r\n\
+                       Host: example.com\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_missing_host() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\nContent-Length: 0\r\n\r\n"
+                      .as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1));
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 0,
+            error_pages: vec![400],
+        });
+    }
+
+    #[test]
+    fn test_duplicate_headers_allowed() {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        io.push_bytes("GET / HTTP/1.1\r\n\
+                       Host: example.com\r\nCookie: a=1\r\nCookie: b=2\r\n\
+                       Cookie: c=3\r\nAccept: */*\r\n\r\n".as_bytes());
+        let m = Stream::<Parser<Strict, MemIo>>::accepted(
+            io.clone(), (), &mut lp.scope(1))
+            .expect_machine();
+        m.ready(EventSet::readable(), &mut lp.scope(1))
+            .expect_machine();
+        assert_eq!(*lp.ctx(), ErrorContext {
+            strict: false,
+            headers_received: 1,
+            error_pages: vec![],
+        });
+    }
     #[cfg(feature="nightly")]
     #[bench]
     fn bench_parse1(b: &mut Bencher) {
@@ -985,6 +3905,7 @@ mod test {
                        body: String::from(""),
                        chunks_received: 0,
                        requests_received: counter,
+                       bodies_received: counter,
                    });
     }
     #[cfg(feature="nightly")]
@@ -1026,6 +3947,32 @@ mod test {
                        body: String::from(""),
                        chunks_received: 0,
                        requests_received: counter,
+                       bodies_received: counter,
+                   });
+    }
+    #[cfg(feature="nightly")]
+    #[bench]
+    fn bench_zero_body(b: &mut Bencher) {
+        let mut io = MemIo::new();
+        let mut lp = MockLoop::new(Default::default());
+        let mut counter = 0;
+        b.iter(|| {
+            counter += 1;
+            let m = Stream::<Parser<Proto, MemIo>>::accepted(
+                io.clone(), (), &mut lp.scope(1))
+                .expect_machine();
+            io.push_bytes("GET / HTTP/1.1\r\nHost: example.com\r\n\
+                           Content-Length: 0\r\n\r\n");
+            m.ready(EventSet::readable(), &mut lp.scope(1)).expect_machine();
+        });
+        assert_eq!(*lp.ctx(),
+                   Context {
+                       progressive: false,
+                       headers_received: counter,
+                       body: String::from(""),
+                       chunks_received: 0,
+                       requests_received: counter,
+                       bodies_received: counter,
                    });
     }
 }
@@ -1,13 +1,28 @@
+use std::borrow::Cow;
 use std::time::Duration;
 
+use httparse::Header;
 use rotor::{Scope, Time};
+use rotor::mio::tcp::TcpStream;
 
 use recvmode::RecvMode;
-use super::error::HttpError;
+use message::AutoHeaders;
+use date::HttpDate;
+use super::error::{HttpError, ErrorFormat};
 use super::request::Head;
-use super::Response;
+use super::{Response, RequestId, ClientLimit};
+use super::{MAX_HEADERS_NUM, MAX_HEADERS_SIZE};
 
 
+/// The result of the `Server::inspect_headers` hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderDecision {
+    /// Process the request as usual
+    Accept,
+    /// Reply with an error page with the status code and close connection
+    Reject(u16),
+}
+
 /// A handler of server-side HTTP
 ///
 /// Used for all versions of HTTP
@@ -48,6 +63,22 @@ pub trait Server: Sized {
         scope: &mut Scope<Self::Context>)
         -> Option<Self>;
 
+    /// Called when the body is fully buffered, right before
+    /// `request_received`
+    ///
+    /// It's useful for metrics and tracing to separate the time spent on
+    /// receiving the body from the time spent on processing the request.
+    /// Only called in buffered mode.
+    ///
+    /// Same as in `request_received` if you return None, the response must
+    /// be complete.
+    fn body_received(self, _response: &mut Response,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
     /// Called when request become invalid between `request_start()`
     /// and `request_received/request_end`
     ///
@@ -80,6 +111,14 @@ pub trait Server: Sized {
     ///    determined, and is usually larger than `nbytes`
     /// 3. Currently for chunked encoding we don't merge chunks, so last
     ///    part of each chunk may be shorter as `nbytes`
+    ///
+    /// Use `Frames(..)` mode if you need chunks of exact size.
+    ///
+    /// The response may be started and written to while the request body
+    /// is still being received (full duplex). The data is sent as soon as
+    /// the socket is writable, call `response.flush()` to wait until it's
+    /// sent before the next chunk is read, the `wakeup` handler is called
+    /// then.
     fn request_chunk(self, chunk: &[u8], response: &mut Response,
         scope: &mut Scope<Self::Context>)
         -> Option<Self>;
@@ -89,6 +128,37 @@ pub trait Server: Sized {
         scope: &mut Scope<Self::Context>)
         -> Option<Self>;
 
+    /// New chunk of the chunked request body is started
+    ///
+    /// Called with the chunk size as soon as the chunk size line is
+    /// parsed, before the data of the chunk is received. Only called in
+    /// `Progressive` and `Frames` modes, and not for the last (zero-size)
+    /// chunk. This is useful to analyze how clients split the body, e.g.
+    /// to reject a stream of tiny chunks early.
+    ///
+    /// Default does nothing
+    fn chunk_started(self, _chunk_len: u64, _response: &mut Response,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
+    /// Trailer fields of the chunked request body received
+    ///
+    /// Called after the last chunk of the body, before `request_received`
+    /// (or `request_end` for progressive requests), only when the request
+    /// contains trailers. The size of the trailer section is limited by
+    /// `MAX_HEADERS_SIZE`, just like for headers.
+    ///
+    /// Default is to ignore trailers
+    fn request_trailers(self, _trailers: &[Header],
+        _response: &mut Response, _scope: &mut Scope<Self::Context>)
+        -> Option<Self>
+    {
+        Some(self)
+    }
+
     /// Request timeout occurred
     ///
     /// This is only called if headers are already received but state machine
@@ -122,25 +192,74 @@ pub trait Server: Sized {
     ///
     /// You can also fallback to a default handler for pages you don't want
     /// to render.
+    ///
+    /// The default page contains a footer returned by `error_page_footer`.
+    /// It's a JSON object instead of HTML page if the client prefers it,
+    /// see `Response::error_format`.
     fn emit_error_page(code: &HttpError, response: &mut Response,
-        _seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        seed: &Self::Seed, scope: &mut Scope<Self::Context>)
     {
 
         let (status, reason) = code.http_status();
+        let reason = Self::reason_phrase(status).unwrap_or(reason);
+        let format = response.error_format();
         response.status(status, reason);
-        let data = format!("<h1>{} {}</h1>\n\
-            <p><small>Served for you by rotor-http</small></p>\n",
-            status, reason);
+        let (data, content_type) = match format {
+            ErrorFormat::Html => {
+                let data = match Self::error_page_footer(seed, scope) {
+                    Some(footer) => format!("<h1>{} {}</h1>\n\
+                        <p><small>{}</small></p>\n",
+                        status, reason, footer),
+                    None => format!("<h1>{} {}</h1>\n", status, reason),
+                };
+                (data, &b"text/html"[..])
+            }
+            ErrorFormat::Json => {
+                let data = format!("{{\"status\": {}, \"reason\": {:?}}}\n",
+                    status, reason);
+                (data, &b"application/json"[..])
+            }
+        };
         let bytes = data.as_bytes();
         response.add_length(bytes.len() as u64).unwrap();
-        response.add_header("Content-Type", b"text/html").unwrap();
+        response.add_header("Content-Type", content_type).unwrap();
         response.done_headers().unwrap();
         response.write_body(bytes);
         response.done();
     }
 
+    /// A reason phrase to use instead of the standard one
+    ///
+    /// It's used by `Response::status_code` and by the default
+    /// `emit_error_page`. It's a plain function of the code, because it's
+    /// looked up when the status line is written, which may be long after
+    /// the seed and the scope were available.
+    ///
+    /// Default is `None`, i.e. standard phrase is used
+    fn reason_phrase(_code: u16) -> Option<&'static str> {
+        return None;
+    }
+
+    /// A footer of the default error page
+    ///
+    /// Return `None` to omit the footer, for example if you don't want to
+    /// advertise the framework. The value is inserted into HTML as is.
+    ///
+    /// Default is `Served for you by rotor-http`
+    fn error_page_footer(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<String>
+    {
+        return Some(String::from("Served for you by rotor-http"));
+    }
+
     /// A timeout for idle keep-alive connection
     ///
+    /// The timer starts when the previous response is sent (or when the
+    /// connection is accepted), and the connection is silently closed if
+    /// no byte of the next request arrives in time. After the first byte
+    /// the `header_byte_timeout` is used instead.
+    ///
     /// Default is 120 seconds
     fn idle_timeout(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
         -> Duration
@@ -165,4 +284,279 @@ pub trait Server: Sized {
     {
         return Duration::new(3600, 0);
     }
+    /// Maximum time for receiving the whole request body
+    ///
+    /// Counted from the moment headers are received. Unlike the deadline
+    /// returned from `headers_received` and `timeout`, it can't be extended
+    /// by the handler, so the client which sends a byte from time to time
+    /// can't hold the connection forever. When reached, the connection is
+    /// closed with `408 Request Timeout`.
+    ///
+    /// Default is `None`, i.e. no limit
+    fn max_request_duration(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<Duration>
+    {
+        return None;
+    }
+    /// Whether to add the `Date` header to every response
+    ///
+    /// HTTP/1.1 requires origin servers to send it. The header is written
+    /// by `done_headers()` only if the handler hasn't added its own one.
+    /// This is used by the default implementation of `auto_headers`.
+    ///
+    /// Default is `true`
+    fn add_date_header(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        return true;
+    }
+    /// Headers which are added to every response automatically
+    ///
+    /// They are added to error pages too. See `AutoHeaders` for details.
+    ///
+    /// Default is to add only the `Date` header (unless `add_date_header`
+    /// returns `false`), the time is taken from `scope.now()`. To send your
+    /// own `Date` just add it with `Response::add_header`.
+    fn auto_headers(seed: &Self::Seed, scope: &mut Scope<Self::Context>)
+        -> AutoHeaders
+    {
+        let date = if Self::add_date_header(seed, scope) {
+            Some(HttpDate::cached_now(scope.now()))
+        } else {
+            None
+        };
+        return AutoHeaders { date: date, server: false };
+    }
+    /// Whether to catch panics in the handlers
+    ///
+    /// This covers every handler of the request, from `headers_received`
+    /// to `wakeup` and `timeout`.
+    ///
+    /// When enabled, a panic in the handler doesn't tear down the whole
+    /// event loop (and all other connections with it). Instead the client
+    /// gets `500 Internal Server Error` (or connection is closed if the
+    /// response is already started) and the connection is closed.
+    ///
+    /// Note: the handler must not leave the context in inconsistent state
+    /// when panicking, since it's used for other connections afterwards.
+    ///
+    /// Default is `false`
+    fn catch_panics(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        return false;
+    }
+    /// Name of the header with the total size of the chunked body
+    ///
+    /// Some clients uploading chunked body send its total size in
+    /// a non-standard header such as `X-Content-Length`. When set, the
+    /// header is parsed and passed as `Head::size_hint`. It doesn't change
+    /// how the body is read, the chunked encoding still determines the end
+    /// of the body.
+    ///
+    /// Default is `None`
+    fn size_hint_header(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<&'static str>
+    {
+        return None;
+    }
+    /// Returns an identifier of the request for tracing
+    ///
+    /// The value is stored in `Head::request_id`, so the handler can use it
+    /// for logging and echo it with `Response::add_request_id`. To
+    /// propagate an id from the client and generate a new one otherwise,
+    /// return `RequestId::from_head(head).or_else(|| Some(RequestId::generate()))`.
+    ///
+    /// Default is `None`, i.e. no request ids are used
+    fn request_id(_seed: &Self::Seed, _head: &Head,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<RequestId>
+    {
+        return None;
+    }
+    /// Maximum number of header fields with the same name in a request
+    ///
+    /// Requests having more occurrences of the header (case-insensitive)
+    /// are rejected with `431 Request Header Fields Too Large`. This is
+    /// a mitigation for requests consisting of thousands of, say, `Cookie`
+    /// headers, which otherwise stay within `MAX_HEADERS_NUM`.
+    ///
+    /// The method is called for each header of each request, so it should
+    /// be cheap.
+    ///
+    /// Default is `MAX_HEADERS_NUM`, i.e. the check is disabled
+    fn max_duplicate_headers(_seed: &Self::Seed, _name: &str,
+        _scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        return MAX_HEADERS_NUM;
+    }
+    /// Maximum size of a single header value in a request
+    ///
+    /// The whole header section is limited by `MAX_HEADERS_SIZE`, but one
+    /// huge value (a cookie usually) may take most of it. Requests having
+    /// a longer header value are rejected with
+    /// `431 Request Header Fields Too Large`.
+    ///
+    /// Default is `MAX_HEADERS_SIZE`, i.e. only the total size is limited
+    fn max_header_value_size(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        return MAX_HEADERS_SIZE;
+    }
+    /// Called when the connection is accepted to tune socket options
+    ///
+    /// This is the place to set `TCP_NODELAY` or keepalive for low-latency
+    /// servers. The hook is only called when the socket is a plain
+    /// `TcpStream`. For other transports, including TLS streams wrapping
+    /// the TCP socket, the downcast fails and the method is not called.
+    ///
+    /// Default does nothing
+    fn configure_socket(_seed: &Self::Seed, _sock: &TcpStream,
+        _scope: &mut Scope<Self::Context>)
+    {
+    }
+    /// Maximum number of chunks in a chunked request body
+    ///
+    /// Each chunk needs some parsing work, so the body which consists of
+    /// lots of tiny chunks may consume much more CPU than its size
+    /// suggests. When the limit is exceeded, the request is rejected
+    /// with `400 Bad Request`. The final zero-length chunk is not counted.
+    ///
+    /// Default is `None`, i.e. only the size of the body is limited
+    fn max_chunks(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> Option<usize>
+    {
+        return None;
+    }
+    /// Maximum number of pipelined requests processed ahead of the client
+    ///
+    /// When the client pipelines requests but doesn't read the responses,
+    /// the responses pile up in the output buffer. When this number of
+    /// requests was processed and their responses are still not sent,
+    /// the server stops parsing further requests until the output buffer
+    /// is flushed.
+    ///
+    /// Default is 16
+    fn max_pipeline_depth(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> usize
+    {
+        return 16;
+    }
+    /// Returns the per-IP connection limit shared between connections
+    ///
+    /// Usually the `ClientLimit` is stored in the `Context` and a clone is
+    /// returned here. When a connection is accepted from the address
+    /// which already has the maximum number of connections open, the
+    /// request is answered with `429 Too Many Requests` and the connection
+    /// is closed. Like `configure_socket`, it only works for `TcpStream`.
+    ///
+    /// Default is `None`, i.e. no limit
+    fn client_limit(_seed: &Self::Seed, _scope: &mut Scope<Self::Context>)
+        -> Option<ClientLimit>
+    {
+        return None;
+    }
+    /// Whether to require CRLF line endings in request headers
+    ///
+    /// By default requests with bare LF line endings are accepted as
+    /// allowed by RFC 7230. But if there is a proxy in front of the server
+    /// which treats them differently, this discrepancy might be used for
+    /// request smuggling. When enabled, such requests are rejected with
+    /// `400 Bad Request`.
+    ///
+    /// Default is `false`
+    fn strict_line_endings(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        return false;
+    }
+    /// Whether to reject requests with ambiguous body length
+    ///
+    /// By default, when request contains both `Transfer-Encoding: chunked`
+    /// and `Content-Length`, the chunked encoding is used and the connection
+    /// is closed after the response, as RFC 7230 requires. But a proxy in
+    /// front of the server might use `Content-Length` instead, which is a
+    /// classic request smuggling setup. When enabled, such requests are
+    /// rejected with `400 Bad Request`.
+    ///
+    /// Default is `false`
+    fn strict_framing(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        return false;
+    }
+    /// Whether to reject requests with obsolete line folding in headers
+    ///
+    /// A header line which starts with a space or a tab continues the
+    /// previous header value. This form is deprecated by RFC 7230 and is
+    /// a known vector for request smuggling, as proxies may disagree on
+    /// where the header ends. When enabled (the default) the raw request
+    /// head is scanned for a line feed followed by whitespace, and such
+    /// requests are rejected with `400 Bad Request`.
+    ///
+    /// Note that when disabled, folded headers may still be rejected by
+    /// the header parser itself.
+    ///
+    /// Default is `true`
+    fn reject_obs_fold(_seed: &Self::Seed,
+        _scope: &mut Scope<Self::Context>)
+        -> bool
+    {
+        return true;
+    }
+    /// Inspects raw request headers before the request is processed
+    ///
+    /// Headers are passed as received, in the original order and casing,
+    /// so custom rules against request smuggling or other anomalies can
+    /// be implemented here. It's called before the body length is
+    /// determined, and if `Reject` is returned, the error page is emitted
+    /// via `emit_error_page` and `headers_received` is never called.
+    ///
+    /// Default is to accept every request
+    fn inspect_headers(_seed: &Self::Seed, _headers: &[Header],
+        _scope: &mut Scope<Self::Context>)
+        -> HeaderDecision
+    {
+        return HeaderDecision::Accept;
+    }
+    /// Rewrites the request path before `headers_received` is called
+    ///
+    /// The returned value is passed as `head.path` to the handler (and to
+    /// the `request_id` hook). This is useful to strip a path prefix when
+    /// the application is mounted under a subdirectory or behind a reverse
+    /// proxy. Return a sub-slice of the `path` when possible, as the owned
+    /// value means an allocation per request.
+    ///
+    /// Default is to return the path unchanged
+    fn rewrite_path<'x>(_seed: &Self::Seed, path: &'x str,
+        _scope: &mut Scope<Self::Context>)
+        -> Cow<'x, str>
+    {
+        return Cow::Borrowed(path);
+    }
+    /// Methods allowed for the request path
+    ///
+    /// Usually it's `Router::allowed_methods_for`. For a known path (when
+    /// the list is not empty) requests are answered without calling
+    /// `headers_received`: `OPTIONS` gets `200 OK` with the `Allow` header,
+    /// and a method which isn't in the list gets `405 Method Not Allowed`.
+    /// `HEAD` is allowed wherever `GET` is. The path is the one returned by
+    /// `rewrite_path`.
+    ///
+    /// Default is an empty list, i.e. every request is passed to
+    /// `headers_received`
+    fn allowed_methods(_seed: &Self::Seed, _path: &str,
+        _scope: &mut Scope<Self::Context>)
+        -> Vec<String>
+    {
+        return Vec::new();
+    }
 }
@@ -0,0 +1,140 @@
+use std::ascii::AsciiExt;
+use std::str::from_utf8;
+
+
+/// Parsed value of the `Range` header for a representation of known length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeSpec {
+    /// Satisfiable byte ranges in the order they appear in the header
+    ///
+    /// Each range is `(first, last)` where both positions are inclusive and
+    /// `last` is clamped to the length of the representation. Unsatisfiable
+    /// ranges are skipped. Overlapping ranges are not merged.
+    Ranges(Vec<(u64, u64)>),
+    /// None of the ranges is satisfiable
+    ///
+    /// The `416 Range Not Satisfiable` response should be sent.
+    Unsatisfiable,
+}
+
+// Parses a single byte-range-spec or suffix-byte-range-spec, returns
+// `Err` if it's malformed and `Ok(None)` if it's unsatisfiable
+fn parse_spec(spec: &str, total_len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let dash = try!(spec.find('-').ok_or(()));
+    let (first, last) = (&spec[..dash], &spec[dash+1..]);
+    if !first.bytes().chain(last.bytes()).all(|x| x >= b'0' && x <= b'9') {
+        return Err(());
+    }
+    if first.len() == 0 {
+        let suffix: u64 = try!(last.parse().map_err(|_| ()));
+        if suffix == 0 || total_len == 0 {
+            return Ok(None);
+        }
+        return Ok(Some((total_len.saturating_sub(suffix), total_len - 1)));
+    }
+    let first: u64 = try!(first.parse().map_err(|_| ()));
+    let last = if last.len() == 0 {
+        None
+    } else {
+        let last: u64 = try!(last.parse().map_err(|_| ()));
+        if last < first {
+            return Err(());
+        }
+        Some(last)
+    };
+    if first >= total_len {
+        return Ok(None);
+    }
+    Ok(Some((first, last.map(|x| ::std::cmp::min(x, total_len - 1))
+                        .unwrap_or(total_len - 1))))
+}
+
+/// Parses the value of the `Range` header
+///
+/// The `total_len` is the length of the representation. Returns `None` if
+/// the header is malformed or the unit is not `bytes`, in this case the
+/// header must be ignored and the full representation sent, as RFC 7233
+/// requires.
+pub fn parse_range(value: &[u8], total_len: u64) -> Option<RangeSpec> {
+    let value = match from_utf8(value) {
+        Ok(value) => value.trim(),
+        Err(_) => return None,
+    };
+    let eq = match value.find('=') {
+        Some(eq) => eq,
+        None => return None,
+    };
+    if !value[..eq].trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let mut ranges = Vec::new();
+    let mut any = false;
+    for spec in value[eq+1..].split(',').map(|x| x.trim()) {
+        if spec.len() == 0 {
+            // empty list elements are allowed
+            continue;
+        }
+        any = true;
+        match parse_spec(spec, total_len) {
+            Ok(Some(range)) => ranges.push(range),
+            Ok(None) => {}
+            Err(()) => return None,
+        }
+    }
+    if !any {
+        None
+    } else if ranges.len() == 0 {
+        Some(RangeSpec::Unsatisfiable)
+    } else {
+        Some(RangeSpec::Ranges(ranges))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_range, RangeSpec};
+    use super::RangeSpec::*;
+
+    fn range(value: &str) -> Option<RangeSpec> {
+        parse_range(value.as_bytes(), 1000)
+    }
+
+    #[test]
+    fn test_simple() {
+        assert_eq!(range("bytes=0-499"), Some(Ranges(vec![(0, 499)])));
+        assert_eq!(range("bytes=500-"), Some(Ranges(vec![(500, 999)])));
+        assert_eq!(range("bytes=500-5000"), Some(Ranges(vec![(500, 999)])));
+        assert_eq!(range("Bytes = 1-1"), Some(Ranges(vec![(1, 1)])));
+    }
+
+    #[test]
+    fn test_suffix() {
+        assert_eq!(range("bytes=-100"), Some(Ranges(vec![(900, 999)])));
+        assert_eq!(range("bytes=-5000"), Some(Ranges(vec![(0, 999)])));
+        assert_eq!(range("bytes=-0"), Some(Unsatisfiable));
+    }
+
+    #[test]
+    fn test_multiple() {
+        assert_eq!(range("bytes=0-0, -1"),
+                   Some(Ranges(vec![(0, 0), (999, 999)])));
+        assert_eq!(range("bytes=2000-, 10-19,"),
+                   Some(Ranges(vec![(10, 19)])));
+    }
+
+    #[test]
+    fn test_unsatisfiable() {
+        assert_eq!(range("bytes=1000-"), Some(Unsatisfiable));
+        assert_eq!(range("bytes=1000-2000, 5000-"), Some(Unsatisfiable));
+        assert_eq!(parse_range(b"bytes=-10", 0), Some(Unsatisfiable));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for val in &["", "bytes=", "bytes=,", "items=0-1", "bytes=1-0",
+                     "bytes=abc", "bytes=1", "bytes=0-1,x", "bytes=+1-2"]
+        {
+            assert_eq!(range(val), None);
+        }
+    }
+}
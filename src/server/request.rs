@@ -1,7 +1,15 @@
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
 use std::net::SocketAddr;
+use std::str::{Split, from_utf8};
 use httparse;
 
-use super::body::BodyKind;
+use headers::{self, HeaderValues};
+use date::HttpDate;
+use super::body::{BodyKind, Framing};
+use super::request_id::RequestId;
+use super::range::{RangeSpec, parse_range};
+use super::uri::{QueryPairs, percent_decode, split_absolute};
 use version::Version;
 
 
@@ -23,11 +31,673 @@ pub struct Head<'a> {
     /// The HTTP scheme is  a sequence of characters beginning with a
     /// letter and followed by any combination of letters, digits, plus,
     /// period or hyphen.
+    ///
+    /// It's `http` unless the request target is in absolute form (like
+    /// `GET http://example.com/ HTTP/1.1` sent to a proxy), then the scheme
+    /// of the target is used as is, i.e. it's not lowercased.
     pub scheme: &'a str,
     /// The path points to a specific resource.
+    ///
+    /// This is the request target as sent by the client. Usually it's in
+    /// origin form (`/path?query`), but it's in absolute form for requests
+    /// to proxies and in authority form for `CONNECT`. Methods like
+    /// `path_only()` and `path_segments()` handle the absolute form too.
+    ///
+    /// Raw control characters are rejected by the parser, but the path may
+    /// contain percent-encoded ones. Check `path_is_safe()` before echoing
+    /// the path back in a response (e.g. in a `Location` header).
     pub path: &'a str,
     /// A slice of HTTP headers.
     pub headers: &'a [httparse::Header<'a>],
     /// The body kind is either fixed, chunked or upgrade.
     pub body_kind: BodyKind,
+    /// The expected size of the body, if known
+    ///
+    /// It's the body length for the fixed-size body. For the chunked body
+    /// it's taken from the header set by `Server::size_hint_header`, if any,
+    /// and must not be trusted (it's fine to preallocate a buffer or show
+    /// progress, but the actual body may be larger or smaller).
+    pub size_hint: Option<u64>,
+    /// An identifier of the request for tracing, if enabled.
+    ///
+    /// See `Server::request_id`.
+    pub request_id: Option<RequestId>,
+}
+
+/// The request method
+///
+/// Standard methods are matched case-sensitively as required by RFC 7230,
+/// so `get` is `Other("get")`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method<'a> {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Connect,
+    Trace,
+    /// Any other (extension) method
+    Other(&'a str),
+}
+
+impl<'a> Method<'a> {
+    /// Parses the method name
+    pub fn parse(name: &'a str) -> Method<'a> {
+        use self::Method::*;
+        match name {
+            "GET" => Get,
+            "HEAD" => Head,
+            "POST" => Post,
+            "PUT" => Put,
+            "DELETE" => Delete,
+            "PATCH" => Patch,
+            "OPTIONS" => Options,
+            "CONNECT" => Connect,
+            "TRACE" => Trace,
+            _ => Other(name),
+        }
+    }
+    /// Returns the method name
+    pub fn as_str(&self) -> &'a str {
+        use self::Method::*;
+        match *self {
+            Get => "GET",
+            Head => "HEAD",
+            Post => "POST",
+            Put => "PUT",
+            Delete => "DELETE",
+            Patch => "PATCH",
+            Options => "OPTIONS",
+            Connect => "CONNECT",
+            Trace => "TRACE",
+            Other(name) => name,
+        }
+    }
+}
+
+// Basic filtering from RFC 4647: range `en` matches tag `en-US`
+fn language_matches(range: &[u8], tag: &str) -> bool {
+    let tag = tag.as_bytes();
+    range == &b"*"[..] || range.eq_ignore_ascii_case(tag) ||
+        tag.len() > range.len() && tag[range.len()] == b'-' &&
+        range.eq_ignore_ascii_case(&tag[..range.len()])
+}
+
+/// An iterator over non-empty segments of the request path
+///
+/// See `Head::path_segments`
+#[derive(Debug, Clone)]
+pub struct PathSegments<'a>(Split<'a, char>);
+
+impl<'a> Iterator for PathSegments<'a> {
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            match self.0.next() {
+                Some("") => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<'a> Head<'a> {
+    /// Returns the value of the first header with the name
+    ///
+    /// Name is compared case-insensitively. The value is returned exactly
+    /// as received, i.e. not trimmed and not decoded.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.get_all(name).next()
+    }
+    /// Returns an iterator over values of all headers with the name
+    ///
+    /// Name is compared case-insensitively. Values are not split by comma.
+    pub fn get_all<'n>(&self, name: &'n str) -> HeaderValues<'a, 'n> {
+        headers::header_values(self.headers, name)
+    }
+    /// Returns the method as an enum, for exhaustive matching
+    pub fn method_enum(&self) -> Method<'a> {
+        Method::parse(self.method)
+    }
+    /// Returns true if the path has no control characters, neither raw
+    /// nor percent-encoded (like `%0D%0A` or `%00`)
+    ///
+    /// Such path is safe to put into the response headers.
+    pub fn path_is_safe(&self) -> bool {
+        let path = self.path.as_bytes();
+        let mut idx = 0;
+        while idx < path.len() {
+            let ch = match path[idx] {
+                b'%' if idx + 2 < path.len() => {
+                    match from_utf8(&path[idx+1..idx+3]).ok()
+                        .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    {
+                        Some(x) => { idx += 2; x }
+                        None => b'%',
+                    }
+                }
+                x => x,
+            };
+            if ch < 0x20 || ch == 0x7f {
+                return false;
+            }
+            idx += 1;
+        }
+        return true;
+    }
+    /// Returns the path without the query string
+    ///
+    /// Use it for routing, so that the query doesn't match as a part of
+    /// the path.
+    ///
+    /// For the absolute-form target only the path component is returned,
+    /// which is `/` if the target has no path.
+    pub fn path_only(&self) -> &'a str {
+        let path = self.path.splitn(2, '?').next().unwrap();
+        match split_absolute(path) {
+            Some((_, _, "")) => "/",
+            Some((_, _, path)) => path,
+            None => path,
+        }
+    }
+    /// Returns the request target if it's in absolute form
+    ///
+    /// That's how requests are sent to forward proxies, e.g.
+    /// `http://example.com/index.html?x=1`. Returns `None` for usual
+    /// requests, whose target starts with a slash.
+    pub fn absolute_target(&self) -> Option<&'a str> {
+        split_absolute(self.path).map(|_| self.path)
+    }
+    /// Returns the authority (`host[:port]`) of the request target
+    ///
+    /// This is the authority component of the absolute-form target, or the
+    /// whole target of the `CONNECT` request. Returns `None` for requests
+    /// in origin form, look at the `Host` header for them.
+    pub fn authority(&self) -> Option<&'a str> {
+        match split_absolute(self.path) {
+            Some((_, authority, _)) => Some(authority),
+            None if self.method_enum() == Method::Connect => Some(self.path),
+            None => None,
+        }
+    }
+    /// Returns the percent-decoded path without the query string
+    ///
+    /// Malformed `%` sequences are left as is, and the bytes which don't
+    /// make a valid UTF-8 are replaced by `U+FFFD`. Note that `%2F` is
+    /// decoded to a slash, so split the path into segments before decoding
+    /// if it matters. The path is borrowed when there is nothing to decode.
+    pub fn decoded_path(&self) -> Cow<'a, str> {
+        percent_decode(self.path_only(), false)
+    }
+    /// Returns the query string, i.e. the part of the path after `?`
+    ///
+    /// The value is not decoded, use `query_pairs` for that. Returns `None`
+    /// if there is no `?` in the path.
+    pub fn query(&self) -> Option<&'a str> {
+        self.path.find('?').map(|idx| &self.path[idx+1..])
+    }
+    /// Returns an iterator over key-value pairs of the query string
+    ///
+    /// Keys and values are percent-decoded and `+` is decoded as a space.
+    /// Empty items are skipped, and an item without `=` yields an empty
+    /// value.
+    pub fn query_pairs(&self) -> QueryPairs<'a> {
+        QueryPairs::new(self.query().unwrap_or(""))
+    }
+    /// Returns an iterator over non-empty segments of the path
+    ///
+    /// Query string is not included, and empty segments (the ones produced
+    /// by leading, trailing or repeated slashes) are skipped. So both `/`
+    /// and an empty path yield nothing, and `//a/b/` yields `a` and `b`.
+    /// Segments are not percent-decoded.
+    pub fn path_segments(&self) -> PathSegments<'a> {
+        PathSegments(self.path_only().split('/'))
+    }
+    /// Returns the best of the supported languages for the request
+    ///
+    /// This parses `Accept-Language` header(s) with quality values.
+    /// The language range `en` matches `en-US` and `*` matches any
+    /// language, the most specific range is used to determine the quality
+    /// of each supported language. Tags are compared case-insensitively.
+    ///
+    /// If there are multiple languages with the same quality, the one that
+    /// goes first in `supported` is returned. If there is no
+    /// `Accept-Language` header (or it's empty) the first supported language
+    /// is returned. Items with malformed quality values are ignored.
+    ///
+    /// Returns `None` if none of the supported languages is acceptable.
+    pub fn best_language<'x>(&self, supported: &[&'x str]) -> Option<&'x str> {
+        let mut any = false;
+        // (length of the most specific range, quality)
+        let mut quality = vec![None::<(usize, u16)>; supported.len()];
+        for header in self.headers.iter() {
+            if !headers::is_accept_language(header.name) {
+                continue;
+            }
+            for item in header.value.split(|&x| x == b',') {
+                let (range, q) = match headers::split_quality(item) {
+                    Some((range, _)) if range.len() == 0 => continue,
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                any = true;
+                let specificity = if range == &b"*"[..] { 0 } else { range.len() };
+                for (idx, tag) in supported.iter().enumerate() {
+                    if !language_matches(range, tag) {
+                        continue;
+                    }
+                    match quality[idx] {
+                        Some((spec, _)) if spec >= specificity => {}
+                        _ => quality[idx] = Some((specificity, q)),
+                    }
+                }
+            }
+        }
+        if !any {
+            return supported.first().map(|x| *x);
+        }
+        let mut best: Option<(u16, &'x str)> = None;
+        for (idx, &tag) in supported.iter().enumerate() {
+            if let Some((_, q)) = quality[idx] {
+                if q > 0 && best.map(|(bq, _)| q > bq).unwrap_or(true) {
+                    best = Some((q, tag));
+                }
+            }
+        }
+        best.map(|(_, tag)| tag)
+    }
+    /// Returns true if the client accepts gzip-compressed response
+    ///
+    /// This checks `Accept-Encoding` header(s). The `gzip` coding (or its
+    /// `x-gzip` alias) is acceptable unless it has zero quality, the `*`
+    /// coding is used only when `gzip` isn't listed explicitly. Use it to
+    /// decide whether to call `Response::enable_gzip`.
+    pub fn accepts_gzip(&self) -> bool {
+        let mut gzip = None;
+        let mut any = None;
+        for header in self.headers.iter() {
+            if !headers::is_accept_encoding(header.name) {
+                continue;
+            }
+            for item in header.value.split(|&x| x == b',') {
+                let (coding, q) = match headers::split_quality(item) {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                if coding.eq_ignore_ascii_case(b"gzip") ||
+                    coding.eq_ignore_ascii_case(b"x-gzip")
+                {
+                    gzip = Some(q);
+                } else if coding == &b"*"[..] {
+                    any = Some(q);
+                }
+            }
+        }
+        gzip.or(any).map(|q| q > 0).unwrap_or(false)
+    }
+    /// Returns the framing of the request body as sent by the client
+    ///
+    /// This is useful for logging and for proxies to decide whether the
+    /// request should be re-framed when forwarding. Note when both
+    /// `Transfer-Encoding: chunked` and `Content-Length` are present the
+    /// former takes precedence.
+    pub fn framing(&self) -> Framing {
+        match self.body_kind {
+            BodyKind::Chunked => Framing::Chunked,
+            BodyKind::Fixed(n) => {
+                if self.headers.iter()
+                    .any(|h| headers::is_content_length(h.name))
+                {
+                    Framing::ContentLength(n)
+                } else {
+                    Framing::None
+                }
+            }
+            BodyKind::Upgrade => Framing::None,
+        }
+    }
+    /// Returns parsed `Range` header for the representation of `total_len`
+    ///
+    /// Returns `None` if there is no `Range` header, or it's malformed, or
+    /// there are multiple such headers. In all these cases the full
+    /// representation should be sent. See `parse_range` for details.
+    ///
+    /// Note: you should also check `if_range_satisfied`.
+    pub fn range(&self, total_len: u64) -> Option<RangeSpec> {
+        let mut iter = self.headers.iter()
+            .filter(|h| headers::is_range(h.name));
+        match (iter.next(), iter.next()) {
+            (Some(h), None) => parse_range(h.value, total_len),
+            _ => None,
+        }
+    }
+    /// Checks `If-Range` precondition of the range request
+    ///
+    /// Returns `true` if the `Range` header should be honored, i.e. either
+    /// there is no `If-Range` header, or it matches the current
+    /// representation. Otherwise full `200 OK` response must be sent.
+    ///
+    /// The `etag` is the current entity tag of the resource as sent in the
+    /// `ETag` header (including quotes, e.g. `"xyzzy"`). Entity tags are
+    /// compared using strong comparison, so weak tags (either in the
+    /// request or the `etag` itself) never match. The date in `If-Range`
+    /// matches only if it's exactly equal to `last_modified`. Malformed
+    /// header never matches.
+    pub fn if_range_satisfied(&self, etag: Option<&str>,
+        last_modified: HttpDate)
+        -> bool
+    {
+        let header = self.headers.iter()
+            .find(|h| headers::is_if_range(h.name));
+        let value = match header.map(|h| from_utf8(h.value)) {
+            None => return true,
+            Some(Ok(value)) => value.trim(),
+            Some(Err(_)) => return false,
+        };
+        if value.starts_with("W/") {
+            // weak entity tags are not allowed for ranges
+            return false;
+        }
+        if value.starts_with('"') {
+            return match etag {
+                Some(etag) => !etag.starts_with("W/") && etag == value,
+                None => false,
+            };
+        }
+        value.parse::<HttpDate>().map(|d| d == last_modified)
+            .unwrap_or(false)
+    }
+    /// Renders headers back into the `Name: value\r\n` form
+    ///
+    /// Headers are written in the original order and with the original
+    /// case of names. Values which are not valid UTF-8 are converted
+    /// lossily. Useful for debugging endpoints and for logging.
+    pub fn format_headers(&self) -> String {
+        let mut result = String::new();
+        for header in self.headers {
+            result.push_str(header.name);
+            result.push_str(": ");
+            result.push_str(&String::from_utf8_lossy(header.value));
+            result.push_str("\r\n");
+        }
+        result
+    }
+}
+
+/// A `GET /` head with the given headers, shared by the tests
+#[cfg(test)]
+pub fn test_head<'x>(headers: &'x [httparse::Header<'x>]) -> Head<'x> {
+    Head {
+        client: None,
+        version: Version::Http11,
+        method: "GET",
+        scheme: "http",
+        path: "/",
+        headers: headers,
+        body_kind: BodyKind::Fixed(0),
+        size_hint: Some(0),
+        request_id: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use httparse::Header;
+    use date::HttpDate;
+    use super::{Head, Method};
+    use super::test_head as head;
+    use super::super::{BodyKind, Framing, RangeSpec};
+
+    #[test]
+    fn test_get_header() {
+        let headers = [
+            Header { name: "Accept", value: b"text/html" },
+            Header { name: "x-tag", value: b" a" },
+            Header { name: "X-Tag", value: b"b, c" },
+        ];
+        let head = head(&headers);
+        assert_eq!(head.get("accept"), Some(&b"text/html"[..]));
+        assert_eq!(head.get("X-TAG"), Some(&b" a"[..]));
+        assert_eq!(head.get("Host"), None);
+        assert_eq!(head.get_all("x-tag").collect::<Vec<_>>(),
+                   vec![&b" a"[..], &b"b, c"[..]]);
+        assert_eq!(head.get_all("Host").count(), 0);
+    }
+
+    fn lang(value: &[u8], supported: &[&'static str]) -> Option<&'static str>
+    {
+        let headers = [Header { name: "Accept-Language", value: value }];
+        head(&headers).best_language(supported)
+    }
+
+    #[test]
+    fn test_language_quality_order() {
+        assert_eq!(lang(b"de-DE,de;q=0.8,en-US;q=0.6,en;q=0.4",
+                        &["en", "de"]), Some("de"));
+        assert_eq!(lang(b"fr;q=0.2, en;q=0.9", &["fr", "en"]), Some("en"));
+        assert_eq!(lang(b"fr, en", &["en", "fr"]), Some("en"));
+    }
+
+    #[test]
+    fn test_language_prefix() {
+        assert_eq!(lang(b"en", &["de", "en-US"]), Some("en-US"));
+        assert_eq!(lang(b"EN-us", &["de", "en-US"]), Some("en-US"));
+        assert_eq!(lang(b"en-US", &["en"]), None);
+        assert_eq!(lang(b"e", &["en"]), None);
+    }
+
+    #[test]
+    fn test_language_star() {
+        assert_eq!(lang(b"*", &["de"]), Some("de"));
+        assert_eq!(lang(b"de;q=0, *;q=0.1", &["de", "fr"]), Some("fr"));
+        assert_eq!(lang(b"de;q=0", &["de"]), None);
+    }
+
+    #[test]
+    fn test_language_default() {
+        assert_eq!(head(&[]).best_language(&["de", "en"]), Some("de"));
+        assert_eq!(lang(b"", &["de", "en"]), Some("de"));
+        assert_eq!(lang(b"en;q=abc", &["de", "en"]), Some("de"));
+        assert_eq!(lang(b"en;q=abc, fr", &["de", "en"]), None);
+    }
+
+    fn gzip(value: &[u8]) -> bool {
+        let headers = [Header { name: "Accept-Encoding", value: value }];
+        head(&headers).accepts_gzip()
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(!head(&[]).accepts_gzip());
+        assert!(gzip(b"gzip, deflate"));
+        assert!(gzip(b"deflate, GZIP;q=0.5"));
+        assert!(gzip(b"x-gzip"));
+        assert!(gzip(b"*"));
+        assert!(!gzip(b""));
+        assert!(!gzip(b"identity"));
+        assert!(!gzip(b"gzip;q=0"));
+        assert!(!gzip(b"gzip;q=0, *"));
+        assert!(!gzip(b"gzip;q=abc"));
+    }
+
+    fn if_range(value: &[u8], etag: Option<&str>) -> bool {
+        let headers = [
+            Header { name: "Range", value: b"bytes=100-" },
+            Header { name: "If-Range", value: value },
+        ];
+        let modified = "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap();
+        head(&headers).if_range_satisfied(etag, modified)
+    }
+
+    #[test]
+    fn test_if_range_absent() {
+        let headers = [Header { name: "Range", value: b"bytes=100-" }];
+        assert!(head(&headers).if_range_satisfied(None, HttpDate::now()));
+    }
+
+    #[test]
+    fn test_if_range_etag() {
+        assert!(if_range(b"\"xyzzy\"", Some("\"xyzzy\"")));
+        assert!(!if_range(b"\"xyzzy\"", Some("\"abc\"")));
+        assert!(!if_range(b"\"xyzzy\"", None));
+        // weak tags never match
+        assert!(!if_range(b"W/\"xyzzy\"", Some("W/\"xyzzy\"")));
+        assert!(!if_range(b"\"xyzzy\"", Some("W/\"xyzzy\"")));
+    }
+
+    #[test]
+    fn test_if_range_date() {
+        assert!(if_range(b"Sun, 06 Nov 1994 08:49:37 GMT", Some("\"x\"")));
+        assert!(if_range(b"Sunday, 06-Nov-94 08:49:37 GMT", None));
+        assert!(!if_range(b"Sun, 06 Nov 1994 08:49:38 GMT", None));
+        assert!(!if_range(b"Sat, 05 Nov 1994 08:49:37 GMT", None));
+        assert!(!if_range(b"yesterday", None));
+    }
+
+    #[test]
+    fn test_framing() {
+        assert_eq!(head(&[]).framing(), Framing::None);
+        let headers = [Header { name: "Content-Length", value: b"0" }];
+        assert_eq!(head(&headers).framing(), Framing::ContentLength(0));
+        let headers = [Header { name: "content-length", value: b"12" }];
+        let mut req = head(&headers);
+        req.body_kind = BodyKind::Fixed(12);
+        assert_eq!(req.framing(), Framing::ContentLength(12));
+        let headers = [
+            Header { name: "Transfer-Encoding", value: b"chunked" },
+        ];
+        let mut req = head(&headers);
+        req.body_kind = BodyKind::Chunked;
+        assert_eq!(req.framing(), Framing::Chunked);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut req = head(&[]);
+        assert_eq!(req.query(), None);
+        assert_eq!(req.query_pairs().count(), 0);
+        req.path = "/search?q=a+b&page=2";
+        assert_eq!(req.path_only(), "/search");
+        assert_eq!(req.query(), Some("q=a+b&page=2"));
+        assert_eq!(req.query_pairs().collect::<Vec<_>>(), vec![
+            (Cow::Borrowed("q"), Cow::Owned::<str>(String::from("a b"))),
+            (Cow::Borrowed("page"), Cow::Borrowed("2")),
+        ]);
+        req.path = "/?";
+        assert_eq!(req.query(), Some(""));
+    }
+
+    #[test]
+    fn test_decoded_path() {
+        let mut req = head(&[]);
+        req.path = "/todo/1";
+        assert!(matches!(req.decoded_path(), Cow::Borrowed("/todo/1")));
+        req.path = "/my%20file+1%zz?q=%20";
+        assert_eq!(req.decoded_path(), "/my file+1%zz");
+        req.path = "/%D0%B6%";
+        assert_eq!(req.decoded_path(), "/\u{436}%");
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        let mut req = head(&[]);
+        assert_eq!(req.absolute_target(), None);
+        assert_eq!(req.authority(), None);
+        req.path = "http://example.com:8080/todo/1?x=http://y/";
+        assert_eq!(req.absolute_target(), Some(req.path));
+        assert_eq!(req.authority(), Some("example.com:8080"));
+        assert_eq!(req.path_only(), "/todo/1");
+        assert_eq!(req.query(), Some("x=http://y/"));
+        assert_eq!(req.path_segments().collect::<Vec<_>>(),
+                   vec!["todo", "1"]);
+        req.path = "http://example.com?x";
+        assert_eq!(req.authority(), Some("example.com"));
+        assert_eq!(req.path_only(), "/");
+        req.method = "CONNECT";
+        req.path = "example.com:443";
+        assert_eq!(req.absolute_target(), None);
+        assert_eq!(req.authority(), Some("example.com:443"));
+    }
+
+    fn segments(path: &str) -> Vec<&str> {
+        let mut req = head(&[]);
+        req.path = path;
+        req.path_segments().collect()
+    }
+
+    #[test]
+    fn test_path_segments() {
+        assert_eq!(segments("/"), Vec::<&str>::new());
+        assert_eq!(segments(""), Vec::<&str>::new());
+        assert_eq!(segments("/?a=b/c"), Vec::<&str>::new());
+        assert_eq!(segments("//a"), vec!["a"]);
+        assert_eq!(segments("/todo/12/"), vec!["todo", "12"]);
+        assert_eq!(segments("/todo//12?x=/y"), vec!["todo", "12"]);
+    }
+
+    #[test]
+    fn test_path_is_safe() {
+        fn safe(path: &str) -> bool {
+            Head { path: path, ..head(&[]) }.path_is_safe()
+        }
+        assert!(safe("/"));
+        assert!(safe("/a%20b?x=%2F%"));
+        assert!(safe("/100%"));
+        assert!(!safe("/a%00"));
+        assert!(!safe("/a%0d%0aSet-Cookie:%20x=1"));
+        assert!(!safe("/a%7F"));
+        assert!(!safe("/a\r\nb"));
+        assert!(!safe("/a\x00"));
+    }
+
+    fn range(value: &[u8]) -> Option<RangeSpec> {
+        let headers = [Header { name: "range", value: value }];
+        head(&headers).range(1000)
+    }
+
+    #[test]
+    fn test_range() {
+        use super::super::RangeSpec::*;
+        assert_eq!(head(&[]).range(1000), None);
+        assert_eq!(range(b"bytes=0-499"), Some(Ranges(vec![(0, 499)])));
+        assert_eq!(range(b"bytes=-100"), Some(Ranges(vec![(900, 999)])));
+        assert_eq!(range(b"bytes=0-0, 990-"),
+                   Some(Ranges(vec![(0, 0), (990, 999)])));
+        assert_eq!(range(b"bytes=1000-"), Some(Unsatisfiable));
+        assert_eq!(range(b"bytes=5-1"), None);
+        let headers = [
+            Header { name: "Range", value: b"bytes=0-1" },
+            Header { name: "Range", value: b"bytes=2-3" },
+        ];
+        assert_eq!(head(&headers).range(1000), None);
+    }
+
+    #[test]
+    fn test_format_headers() {
+        assert_eq!(head(&[]).format_headers(), "");
+        let headers = [
+            Header { name: "Host", value: b"example.com" },
+            Header { name: "x-binary", value: b"a\xffb" },
+            Header { name: "Accept", value: b"*/*" },
+        ];
+        assert_eq!(head(&headers).format_headers(),
+            "Host: example.com\r\nx-binary: a\u{fffd}b\r\nAccept: */*\r\n");
+    }
+
+    #[test]
+    fn test_method() {
+        assert_eq!(head(&[]).method_enum(), Method::Get);
+        assert_eq!(Method::parse("OPTIONS"), Method::Options);
+        assert_eq!(Method::parse("get"), Method::Other("get"));
+        assert_eq!(Method::parse("PROPFIND"), Method::Other("PROPFIND"));
+        for name in &["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH",
+                      "OPTIONS", "CONNECT", "TRACE", "PROPFIND"]
+        {
+            assert_eq!(Method::parse(name).as_str(), *name);
+        }
+    }
 }
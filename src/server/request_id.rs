@@ -0,0 +1,118 @@
+use std::ascii::AsciiExt;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use date::HttpDate;
+use super::Head;
+
+
+/// Maximum length of the incoming request id we accept
+pub const MAX_REQUEST_ID: usize = 128;
+
+static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// An identifier of the request used for tracing
+///
+/// It's either propagated from the `X-Request-Id` (or `traceparent`)
+/// header of the request or generated. See `Server::request_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+fn is_hex(ch: u8) -> bool {
+    (ch >= b'0' && ch <= b'9') || (ch >= b'a' && ch <= b'f') ||
+        (ch >= b'A' && ch <= b'F')
+}
+
+fn valid_id(value: &[u8]) -> bool {
+    value.len() > 0 && value.len() <= MAX_REQUEST_ID &&
+        value.iter().all(|&x| x > b' ' && x < 0x7f)
+}
+
+impl RequestId {
+    /// Extract request id from the request headers
+    ///
+    /// The `X-Request-Id` header is used if present, otherwise the trace-id
+    /// part of the W3C `traceparent` header. Values containing whitespace,
+    /// control or non-ascii characters, and values longer than
+    /// `MAX_REQUEST_ID` are ignored, so it's safe to echo the id back.
+    pub fn from_head(head: &Head) -> Option<RequestId> {
+        let mut traceparent = None;
+        for header in head.headers {
+            if header.name.eq_ignore_ascii_case("X-Request-Id") {
+                if valid_id(header.value) {
+                    return Some(RequestId(String::from_utf8(
+                        header.value.to_vec()).unwrap()));
+                }
+            } else if header.name.eq_ignore_ascii_case("traceparent") {
+                // version "-" trace-id "-" parent-id "-" trace-flags
+                traceparent = header.value.split(|&x| x == b'-').nth(1)
+                    .and_then(|x| if x.len() == 32 &&
+                                     x.iter().all(|&c| is_hex(c))
+                                  { Some(x) } else { None });
+            }
+        }
+        traceparent.map(|x| RequestId(String::from_utf8(x.to_vec()).unwrap()))
+    }
+    /// Generate a new unique request id
+    ///
+    /// The id consists of the current timestamp and a process-wide counter,
+    /// so it's unique in the process and very likely unique among several
+    /// processes started at different times.
+    pub fn generate() -> RequestId {
+        let num = COUNTER.fetch_add(1, Ordering::Relaxed);
+        RequestId(format!("{:08x}-{:08x}",
+            HttpDate::now().timestamp() as u32, num as u32))
+    }
+    /// The id as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+    use super::RequestId;
+    use super::super::request::test_head as head;
+
+    #[test]
+    fn test_request_id_header() {
+        let headers = [
+            Header { name: "Host", value: b"example.com" },
+            Header { name: "x-request-id", value: b"abc-123" },
+        ];
+        assert_eq!(RequestId::from_head(&head(&headers)).unwrap().as_str(),
+                   "abc-123");
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let headers = [
+            Header { name: "traceparent", value:
+                b"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01" },
+        ];
+        assert_eq!(RequestId::from_head(&head(&headers)).unwrap().as_str(),
+                   "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let headers = [
+            Header { name: "X-Request-Id", value: b"a b" },
+            Header { name: "traceparent", value: b"00-xyz-01" },
+        ];
+        assert_eq!(RequestId::from_head(&head(&headers)), None);
+        assert_eq!(RequestId::from_head(&head(&[])), None);
+    }
+
+    #[test]
+    fn test_generate() {
+        assert!(RequestId::generate() != RequestId::generate());
+    }
+}
@@ -1,7 +1,15 @@
+use std::cmp::{min, max};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+use std::u64;
+
 use rotor_stream::Buf;
 
-use message::{MessageState, Message, HeaderError};
+use message::{MessageState, Message, HeaderError, AutoHeaders};
+use message::{TrailerPolicy, ReasonPhrases};
 use version::Version;
+use status::reason_phrase;
+use super::{RequestId, RangeSpec, ErrorFormat};
 
 
 /// This response is returned when Response is dropping without writing
@@ -22,27 +30,51 @@ pub const NOT_IMPLEMENTED_HEAD: &'static str = concat!(
     "\r\n",
     );
 
-pub struct Response<'a>(Message<'a>);
+pub struct Response<'a> {
+    message: Message<'a>,
+    /// Reading of the request body is paused by the handler
+    paused: bool,
+    /// Handler requested a flush before it's called again
+    flush: bool,
+    /// A copy of the response body is written here
+    tee: Option<Box<Write + 'a>>,
+    /// Handler requested connection reset
+    reset: bool,
+}
 
 impl<'a> From<Message<'a>> for Response<'a> {
     fn from(msg: Message) -> Response {
-        Response(msg)
+        Response {
+            message: msg,
+            paused: false,
+            flush: false,
+            tee: None,
+            reset: false,
+        }
     }
 }
 
 // TODO: Support responses to CONNECT requests.
 impl<'a> Response<'a> {
     /// Creates new response message by extracting needed fields from Head.
+    ///
+    /// The `auto` headers are written right after the status line.
+    ///
+    /// For HTTP/1.0 the `do_close` should be `true` unless the request
+    /// contains `Connection: keep-alive`, in the latter case the same header
+    /// is sent in the response.
     pub fn new(out_buf: &mut Buf, version: Version,
-        is_head: bool, do_close: bool) -> Response
+        is_head: bool, do_close: bool, auto: AutoHeaders) -> Response
     {
         use message::Body::*;
-        // TODO(tailhook) implement Connection: Close,
-        // (including explicit one in HTTP/1.0) and maybe others
         MessageState::ResponseStart {
             body: if is_head { Head } else { Normal },
             version: version,
-            close: do_close || version == Version::Http10,
+            close: do_close,
+            auto: auto,
+            te: TrailerPolicy::Allow,
+            errors: ErrorFormat::Html,
+            reasons: reason_phrase,
         }.with(out_buf)
     }
     /// Returns true if it's okay to proceed with keep-alive connection
@@ -52,7 +84,7 @@ impl<'a> Response<'a> {
         if self.is_complete() {
             return true;
         }
-        let (buf, me) = self.0.decompose();
+        let (buf, me) = self.message.decompose();
         match me {
             // If response is not even started yet, send something to make
             // debugging easier
@@ -69,6 +101,24 @@ impl<'a> Response<'a> {
         return false;
     }
 
+    /// Overrides the HTTP version of the response
+    ///
+    /// By default the response has the same version as the request. The
+    /// version may only be downgraded, e.g. a proxy may respond with
+    /// HTTP/1.0 to mirror the upstream, otherwise
+    /// `HeaderError::IncompatibleVersion` is returned. Note that HTTP/1.0
+    /// response is sent with `Connection: keep-alive` unless the connection
+    /// is going to be closed.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn set_version(&mut self, version: Version)
+        -> Result<(), HeaderError>
+    {
+        self.message.set_version(version)
+    }
+
     /// Write a 100 (Continue) response.
     ///
     /// A server should respond with the 100 status code if it receives a
@@ -79,7 +129,7 @@ impl<'a> Response<'a> {
     /// When the response is already started. It's expected that your response
     /// handler state machine will never call the method twice.
     pub fn response_continue(&mut self) {
-        self.0.response_continue()
+        self.message.response_continue()
     }
 
     /// Write status line.
@@ -95,7 +145,19 @@ impl<'a> Response<'a> {
     /// When the status code is 100 (Continue). 100 is not allowed
     /// as a final status code.
     pub fn status(&mut self, code: u16, reason: &str) {
-        self.0.response_status(code, reason)
+        self.message.response_status(code, reason)
+    }
+    /// Write status line with the reason phrase for the code
+    ///
+    /// The phrase returned by `Server::reason_phrase` is used, if there is
+    /// none the standard one is taken from `reason_phrase()`, for unknown
+    /// codes it's empty.
+    ///
+    /// # Panics
+    ///
+    /// Same as `status()`
+    pub fn status_code(&mut self, code: u16) {
+        self.message.response_status_code(code)
     }
     /// Add a header to the message.
     ///
@@ -119,7 +181,203 @@ impl<'a> Response<'a> {
     pub fn add_header(&mut self, name: &str, value: &[u8])
         -> Result<(), HeaderError>
     {
-        self.0.add_header(name, value)
+        self.message.add_header(name, value)
+    }
+    /// Writes a complete `304 Not Modified` response
+    ///
+    /// The `etag` (if any) is sent in the `ETag` header and must include
+    /// quotes, e.g. `"xyzzy"`. The response has neither body nor
+    /// `Content-Length`, the latter is enforced by the response state
+    /// machine (`add_length` returns `RequireBodyless` for 304). If you need
+    /// other headers such as `Cache-Control` or `Last-Modified`, call
+    /// `status(304, ...)` and add headers yourself.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn not_modified(&mut self, etag: Option<&str>) {
+        self.message.response_status(304, "Not Modified");
+        if let Some(etag) = etag {
+            self.message.add_header("ETag", etag.as_bytes()).unwrap();
+        }
+        self.message.done_headers().unwrap();
+        self.message.done();
+    }
+    /// Writes a complete `405 Method Not Allowed` response
+    ///
+    /// The `Allow` header lists `allowed` methods separated by comma as
+    /// required by RFC 7231, the body is a short plain-text message. See
+    /// `Router::allowed_methods_for` to get the list of methods.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn method_not_allowed(&mut self, allowed: &[&str]) {
+        let reason = "Method Not Allowed";
+        self.message.response_status(405, reason);
+        self.message.add_header("Allow", allowed.join(", ").as_bytes())
+            .unwrap();
+        self.message.add_header("Content-Type", b"text/plain").unwrap();
+        self.message.add_length(reason.len() as u64).unwrap();
+        self.message.done_headers().unwrap();
+        self.write_body(reason.as_bytes());
+        self.message.done();
+    }
+    /// Writes a complete `200 OK` response to the `OPTIONS` request
+    ///
+    /// The `Allow` header lists `allowed` methods, `OPTIONS` itself is added
+    /// if it's not in the list.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn options(&mut self, allowed: &[&str]) {
+        let mut methods = allowed.to_vec();
+        if !methods.contains(&"OPTIONS") {
+            methods.push("OPTIONS");
+        }
+        self.message.response_status(200, "OK");
+        self.message.add_header("Allow", methods.join(", ").as_bytes())
+            .unwrap();
+        self.message.add_length(0).unwrap();
+        self.message.done_headers().unwrap();
+        self.message.done();
+    }
+    /// Writes a complete response with the fixed-size `body`
+    ///
+    /// This is a shortcut for `status()`, `Content-Type` header,
+    /// `add_length()`, `done_headers()`, `write_body()` and `done()`.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started or when `code` doesn't allow
+    /// a body (1xx, 204, 304), use `send_status` for these.
+    pub fn send_body(&mut self, code: u16, reason: &str,
+        content_type: &[u8], body: &[u8])
+    {
+        self.message.response_status(code, reason);
+        self.message.add_header("Content-Type", content_type).unwrap();
+        self.message.add_length(body.len() as u64).unwrap();
+        self.message.done_headers().unwrap();
+        self.write_body(body);
+        self.message.done();
+    }
+    /// Writes a complete response with the requested range of a file
+    ///
+    /// The `range` is usually obtained by `Head::range(total_len)`, where
+    /// `total_len` is the size of the file. For satisfiable ranges the
+    /// `206 Partial Content` response is sent with `Content-Range` header
+    /// and that part of the `file`. Multiple ranges are coalesced into one
+    /// covering all of them, as multipart responses aren't supported.
+    /// For `RangeSpec::Unsatisfiable` the response is
+    /// `416 Range Not Satisfiable` with `Content-Range: bytes */total_len`.
+    ///
+    /// The data is read into the output buffer before the response is
+    /// started, so on error the response is left untouched and you can
+    /// send an error page. It's also an error if the file is shorter than
+    /// expected.
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn send_file_range<F: Read + Seek>(&mut self, content_type: &[u8],
+        file: &mut F, total_len: u64, range: &RangeSpec)
+        -> io::Result<()>
+    {
+        let (first, last) = match *range {
+            RangeSpec::Ranges(ref ranges) => {
+                ranges.iter().fold((u64::MAX, 0),
+                    |(first, last), &(x, y)| (min(first, x), max(last, y)))
+            }
+            RangeSpec::Unsatisfiable => {
+                self.message.response_status(416, "Range Not Satisfiable");
+                self.message.add_header("Content-Range",
+                    format!("bytes */{}", total_len).as_bytes()).unwrap();
+                self.message.add_length(0).unwrap();
+                self.message.done_headers().unwrap();
+                self.message.done();
+                return Ok(());
+            }
+        };
+        let len = last - first + 1;
+        let mut data = Vec::with_capacity(len as usize);
+        try!(file.seek(SeekFrom::Start(first)));
+        try!(file.by_ref().take(len).read_to_end(&mut data));
+        if (data.len() as u64) < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                "file is shorter than the range"));
+        }
+        self.message.response_status(206, "Partial Content");
+        self.message.add_header("Content-Type", content_type).unwrap();
+        self.message.add_header("Content-Range",
+            format!("bytes {}-{}/{}", first, last, total_len).as_bytes())
+            .unwrap();
+        self.message.add_length(len).unwrap();
+        self.message.done_headers().unwrap();
+        self.write_body(&data);
+        self.message.done();
+        Ok(())
+    }
+    /// Writes a complete response without a body
+    ///
+    /// The `Content-Length: 0` is sent unless the status code forbids it
+    /// (1xx, 204, 304).
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn send_status(&mut self, code: u16, reason: &str) {
+        self.message.response_status(code, reason);
+        match self.message.add_length(0) {
+            Ok(()) | Err(HeaderError::RequireBodyless) => {}
+            Err(e) => panic!("Can't add Content-Length: {}", e),
+        }
+        self.message.done_headers().unwrap();
+        self.message.done();
+    }
+    /// Add `Content-Type` header
+    ///
+    /// See the `mime` module for common values.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called in the wrong state (same as `add_header`).
+    pub fn content_type(&mut self, mime: &str) -> Result<(), HeaderError> {
+        self.message.add_header("Content-Type", mime.as_bytes())
+    }
+    /// Add `X-Request-Id` header with the identifier of the request
+    ///
+    /// # Panics
+    ///
+    /// Panics when called in the wrong state (same as `add_header`).
+    pub fn add_request_id(&mut self, id: &RequestId) {
+        self.message.add_header("X-Request-Id", id.as_str().as_bytes()).unwrap()
+    }
+    /// Add `Keep-Alive` header with connection reuse hints
+    ///
+    /// Writes `Keep-Alive: timeout=5, max=100`, where `timeout` is the idle
+    /// timeout in seconds (usually `Server::idle_timeout`), and `max` is the
+    /// number of further requests the server is going to accept on this
+    /// connection (omitted if `None`). Some clients use it to avoid sending
+    /// a request to a connection which is about to be closed.
+    ///
+    /// Nothing is written if the connection is going to be closed after
+    /// this response.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called in the wrong state (same as `add_header`).
+    pub fn add_keep_alive_hints(&mut self, timeout: Duration,
+        max: Option<usize>)
+    {
+        if self.message.is_closing() {
+            return;
+        }
+        let value = match max {
+            Some(max) => format!("timeout={}, max={}", timeout.as_secs(), max),
+            None => format!("timeout={}", timeout.as_secs()),
+        };
+        self.message.add_header("Keep-Alive", value.as_bytes()).unwrap()
     }
     /// Add a content length to the message.
     ///
@@ -127,13 +385,17 @@ impl<'a> Response<'a> {
     /// It is checked that there are no other body length headers present in the
     /// message. When the body is send the length is validated.
     ///
+    /// In the response to a `HEAD` request pass the length the body would
+    /// have for `GET`. The body is not sent in this case, so you may call
+    /// `done()` right after `done_headers()` (which returns `false`).
+    ///
     /// # Panics
     ///
     /// Panics when `add_length` is called in the wrong state.
     pub fn add_length(&mut self, n: u64)
         -> Result<(), HeaderError>
     {
-        self.0.add_length(n)
+        self.message.add_length(n)
     }
     /// Sets the transfer encoding to chunked.
     ///
@@ -147,14 +409,108 @@ impl<'a> Response<'a> {
     pub fn add_chunked(&mut self)
         -> Result<(), HeaderError>
     {
-        self.0.add_chunked()
+        self.message.add_chunked()
+    }
+    /// Enables streaming gzip compression of the response body
+    ///
+    /// Writes `Content-Encoding: gzip` and `Transfer-Encoding: chunked`
+    /// headers to the output buffer immediately. The data passed to
+    /// `write_body` is compressed incrementally, and the compressed stream
+    /// is finalized in `done()`. Neither `add_length` nor `add_chunked`
+    /// may be used with it.
+    ///
+    /// Call it only if `Head::accepts_gzip` returns true, otherwise send
+    /// the body as usual. In both cases it's a good idea to add the
+    /// `Vary: Accept-Encoding` header, so caches don't mix up responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `enable_gzip` is called in the wrong state.
+    #[cfg(feature="gzip")]
+    pub fn enable_gzip(&mut self)
+        -> Result<(), HeaderError>
+    {
+        self.message.enable_gzip()
+    }
+    /// Writes the `Trailer` header and only allows these trailer fields
+    ///
+    /// Must be called after `add_chunked()`. It's optional, without it
+    /// `add_trailer()` accepts any field name.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `declare_trailers` is called in the wrong state.
+    pub fn declare_trailers(&mut self, names: &[&str])
+        -> Result<(), HeaderError>
+    {
+        self.message.declare_trailers(names)
+    }
+    /// Adds a trailer field after the chunked body
+    ///
+    /// This is how gRPC-web sends `grpc-status` and `grpc-message`. The
+    /// first trailer ends the body, so write all the data before it and
+    /// call `done()` after the last trailer.
+    ///
+    /// If the request has no `TE: trailers` header, a warning is logged, or
+    /// `HeaderError::TrailersNotAccepted` is returned when
+    /// `Server::strict_framing` is enabled.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `add_trailer` is called in the wrong state.
+    pub fn add_trailer(&mut self, name: &str, value: &[u8])
+        -> Result<(), HeaderError>
+    {
+        self.message.add_trailer(name, value)
+    }
+    /// Lets the body length be determined from the body itself
+    ///
+    /// The body written with `write_body` is buffered. If the response is
+    /// finished with `done()` before the body exceeds
+    /// `AUTO_FRAMING_THRESHOLD` bytes, it's sent with `Content-Length`.
+    /// Otherwise the response switches to chunked encoding. This is a good
+    /// default for dynamic responses, which are usually small.
+    ///
+    /// Note that neither the body nor the end of the headers is sent until
+    /// the framing is decided.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `auto_framing` is called in the wrong state.
+    pub fn auto_framing(&mut self)
+        -> Result<(), HeaderError>
+    {
+        self.message.auto_framing()
+    }
+    /// Adds a strong `ETag` header computed from the body
+    ///
+    /// The body is buffered until `done()`, then the `ETag` (see
+    /// `body_etag` for the algorithm) and `Content-Length` headers are
+    /// written followed by the body. This implies `auto_framing()`, except
+    /// the body never switches to chunked encoding, so use it only for
+    /// reasonably small responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `auto_etag` is called in the wrong state.
+    pub fn auto_etag(&mut self)
+        -> Result<(), HeaderError>
+    {
+        self.message.auto_etag()
     }
     /// Returns true if at least `status()` method has been called
     ///
     /// This is mostly useful to find out whether we can build an error page
     /// or it's already too late.
     pub fn is_started(&self) -> bool {
-        self.0.is_started()
+        self.message.is_started()
+    }
+    /// The format of the error page negotiated from the `Accept` header
+    ///
+    /// It's `Html` when the request headers are not parsed yet or the
+    /// response is already started. Used by the default `emit_error_page`.
+    pub fn error_format(&self) -> ErrorFormat {
+        self.message.error_format()
     }
     /// Closes the HTTP header and returns `true` if entity body is expected.
     ///
@@ -168,7 +524,7 @@ impl<'a> Response<'a> {
     ///
     /// Panics when the response is in a wrong state.
     pub fn done_headers(&mut self) -> Result<bool, HeaderError> {
-        self.0.done_headers()
+        self.message.done_headers()
     }
     /// Write a chunk of the message body.
     ///
@@ -191,12 +547,97 @@ impl<'a> Response<'a> {
     /// determine response body length (either Content-Length or
     /// Transfer-Encoding).
     pub fn write_body(&mut self, data: &[u8]) {
-        self.0.write_body(data)
+        self.message.write_body(data);
+        let error = match self.tee {
+            Some(ref mut sink) => sink.write_all(data).err(),
+            None => None,
+        };
+        if let Some(e) = error {
+            warn!("Error writing response body to the tee: {}", e);
+            self.tee = None;
+        }
+    }
+    /// Writes a body consisting of several parts
+    ///
+    /// This is useful for templates, where the static text is interspersed
+    /// with dynamic values. If called before `done_headers()` the headers
+    /// are finished here, and `Content-Length` is added with the total
+    /// length of the parts if there is no framing header yet. Otherwise
+    /// each part is written with `write_body()`, i.e. as a separate chunk
+    /// for the chunked body.
+    ///
+    /// # Panics
+    ///
+    /// Same as `write_body()`, and also when the response must not contain
+    /// a body (e.g. `204 No Content`).
+    pub fn write_body_parts<'x, I>(&mut self, parts: I)
+        where I: IntoIterator<Item=&'x [u8]>
+    {
+        let parts = parts.into_iter().collect::<Vec<_>>();
+        self.message.write_body_parts(parts.iter().cloned());
+        let error = match self.tee {
+            Some(ref mut sink) => {
+                parts.iter().map(|part| sink.write_all(part))
+                    .find(|res| res.is_err()).and_then(|res| res.err())
+            }
+            None => None,
+        };
+        if let Some(e) = error {
+            warn!("Error writing response body to the tee: {}", e);
+            self.tee = None;
+        }
+    }
+    /// Copies everything passed to `write_body` into the `sink` as well
+    ///
+    /// The sink receives the body as written by the handler, i.e. without
+    /// chunk headers, which is useful to cache or log the rendered
+    /// response. If writing to the sink fails, the error is logged and the
+    /// tee is disabled, the response itself is not affected.
+    ///
+    /// The tee is attached to this `Response` value, so it only works
+    /// until the current handler method returns. Call it again in the next
+    /// callback if the body is written in several steps.
+    pub fn tee<W: Write + 'a>(&mut self, sink: W) {
+        self.tee = Some(Box::new(sink));
+    }
+    /// Writes a complete pre-rendered response to the output buffer
+    ///
+    /// The `data` must contain status line, headers and the body. It's
+    /// written as is, bypassing all the checks, and the response is marked
+    /// as complete. This is useful for sending cached static responses
+    /// (e.g. health checks) as fast as possible.
+    ///
+    /// The caller is responsible for the correctness of the response:
+    ///
+    /// * The body must be framed with `Content-Length` or chunked encoding,
+    ///   otherwise the connection can't be kept alive
+    /// * In the response to a `HEAD` request the body must be omitted
+    /// * The `auto` headers (`Date`, `Server`) are not added
+    /// * If the connection is going to be closed (e.g. for HTTP/1.0 request
+    ///   or `Connection: close`) the response should contain
+    ///   `Connection: close`, the connection is closed anyway
+    ///
+    /// # Panics
+    ///
+    /// When the response is already started.
+    pub fn send_raw(&mut self, data: &[u8]) {
+        self.message.send_raw(data)
+    }
+    /// Writes data to the connection switched to another protocol
+    ///
+    /// See `RecvMode::Upgrade`. The data is written as is, without any
+    /// framing. Use `flush()` to get `wakeup` when it's sent.
+    ///
+    /// # Panics
+    ///
+    /// When the response (e.g. `101 Switching Protocols`) is not complete.
+    pub fn write_raw(&mut self, data: &[u8]) {
+        self.message.write_raw(data)
     }
     /// Returns true if `done()` method is already called and everything
     /// was okay.
     pub fn is_complete(&self) -> bool {
-        self.0.is_complete()
+        self.message.is_complete()
     }
     /// Writes needed finalization data into the buffer and asserts
     /// that response is in the appropriate state for that.
@@ -207,10 +648,495 @@ impl<'a> Response<'a> {
     ///
     /// When the response is in the wrong state.
     pub fn done(&mut self) {
-        self.0.done()
+        self.message.done()
+    }
+    /// Stops reading the request body
+    ///
+    /// This is used for flow control, for example in a proxy when the
+    /// downstream connection can't keep up with the incoming body. While
+    /// paused, no `request_chunk` (or `request_received`) is called and no
+    /// more bytes are read from the socket, so the TCP window eventually
+    /// closes and the client stops sending. Deadline of the request still
+    /// applies.
+    ///
+    /// Reading is resumed by calling `resume_body_read()` in the `wakeup`
+    /// handler (or in `timeout`). Has no effect when the request body is
+    /// already received.
+    pub fn pause_body_read(&mut self) {
+        self.paused = true;
+    }
+    /// Resumes reading the request body paused by `pause_body_read()`
+    pub fn resume_body_read(&mut self) {
+        self.paused = false;
+    }
+    /// Sends the data written so far before calling the handler again
+    ///
+    /// Normally the output buffer is flushed whenever the socket is
+    /// writable, while the handler may write more data at any event. When
+    /// flush is requested, the connection waits until the whole buffer is
+    /// written to the socket and then calls the `wakeup` handler, so the
+    /// handler can write the next portion of the response. This is useful
+    /// for streaming responses such as server-sent events, and also works
+    /// as a flow control for large responses.
+    ///
+    /// When called while the request body is still being read (e.g. in
+    /// `request_chunk` in progressive mode), reading of the body is
+    /// suspended until the buffer is written, so a full duplex handler
+    /// which echoes the data back can't be overrun by a client which
+    /// doesn't read the response.
+    pub fn flush(&mut self) {
+        self.flush = true;
+    }
+    /// Aborts the connection with TCP reset instead of a graceful close
+    ///
+    /// This is useful to get rid of an abusive client (e.g. when an attack
+    /// is detected) without keeping the socket in `TIME_WAIT` state. When
+    /// the handler returns, the `SO_LINGER` option is set to zero and the
+    /// socket is closed, so the peer receives `RST` instead of `FIN`. No
+    /// other handler is called after that.
+    ///
+    /// Everything which is not sent yet, including the response written
+    /// in this handler, is discarded, so the client never receives a
+    /// complete response. For transports other than plain `TcpStream`
+    /// (e.g. TLS) the connection is closed as usual.
+    pub fn reset_connection(&mut self) {
+        self.reset = true;
     }
 }
 
 pub fn state(resp: Response) -> MessageState {
-    resp.0.state()
+    resp.message.state()
+}
+
+pub fn set_trailer_policy(resp: &mut Response, policy: TrailerPolicy) {
+    resp.message.set_trailer_policy(policy)
+}
+
+pub fn set_error_format(resp: &mut Response, format: ErrorFormat) {
+    resp.message.set_error_format(format)
+}
+
+pub fn set_reason_phrases(resp: &mut Response, fun: ReasonPhrases) {
+    resp.message.set_reason_phrases(fun)
+}
+
+pub fn body_paused(resp: &Response) -> bool {
+    resp.paused
+}
+
+pub fn flush_requested(resp: &Response) -> bool {
+    resp.flush
+}
+
+pub fn reset_requested(resp: &Response) -> bool {
+    resp.reset
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use rotor_stream::Buf;
+    use message::{AutoHeaders, HeaderError, TrailerPolicy};
+    use version::Version;
+    use mime;
+    use super::{Response, set_trailer_policy};
+    use super::super::RangeSpec;
+
+    #[test]
+    fn test_not_modified() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.not_modified(Some("\"xyzzy\""));
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 304 Not Modified\r\n",
+            "ETag: \"xyzzy\"\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_not_modified_no_length() {
+        let mut buf = Buf::new();
+        let mut resp = Response::new(&mut buf, Version::Http11,
+            false, false, AutoHeaders::default());
+        resp.status(304, "Not Modified");
+        assert!(matches!(resp.add_length(10),
+                         Err(HeaderError::RequireBodyless)));
+    }
+
+    #[test]
+    fn test_head_length() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                true, false, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.add_length(12).unwrap();
+            assert_eq!(resp.done_headers().unwrap(), false);
+            resp.done();
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Length: 12\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_head_body_ignored() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                true, false, AutoHeaders::default());
+            resp.send_body(200, "OK", b"text/plain", b"Hello world!");
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Length: 12\r\n\r\n").as_bytes());
+    }
+
+    fn file_range(range: RangeSpec) -> Buf {
+        let mut file = Cursor::new(&b"Hello world!"[..]);
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.send_file_range(b"text/plain", &mut file, 12, &range)
+                .unwrap();
+            assert!(resp.is_complete());
+        }
+        buf
+    }
+
+    #[test]
+    fn test_file_range() {
+        assert_eq!(&file_range(RangeSpec::Ranges(vec![(6, 10)]))[..],
+            concat!("HTTP/1.1 206 Partial Content\r\n",
+                    "Content-Type: text/plain\r\n",
+                    "Content-Range: bytes 6-10/12\r\n",
+                    "Content-Length: 5\r\n\r\n",
+                    "world").as_bytes());
+        // coalesced into a single range
+        let buf = file_range(RangeSpec::Ranges(vec![(0, 0), (11, 11)]));
+        assert!(buf[..].ends_with(
+            b"Content-Range: bytes 0-11/12\r\nContent-Length: 12\r\n\r\n\
+              Hello world!"));
+        assert_eq!(&file_range(RangeSpec::Unsatisfiable)[..],
+            concat!("HTTP/1.1 416 Range Not Satisfiable\r\n",
+                    "Content-Range: bytes */12\r\n",
+                    "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_file_range_short() {
+        let mut file = Cursor::new(&b"Hello"[..]);
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            let range = RangeSpec::Ranges(vec![(0, 11)]);
+            assert!(resp.send_file_range(b"text/plain", &mut file, 12,
+                                         &range).is_err());
+            assert!(!resp.is_started());
+        }
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[cfg(feature="gzip")]
+    #[test]
+    fn test_gzip_head() {
+        let mut buf = Buf::new();
+        let mut resp = Response::new(&mut buf, Version::Http11,
+            true, false, AutoHeaders::default());
+        resp.status(200, "OK");
+        assert!(matches!(resp.enable_gzip(),
+                         Err(HeaderError::RequireBodyless)));
+    }
+
+    #[test]
+    fn test_set_version() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, true, AutoHeaders::default());
+            resp.set_version(Version::Http10).unwrap();
+            resp.status(200, "OK");
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.0 200 OK\r\n",
+            "Content-Length: 0\r\n",
+            "Connection: close\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_set_version_upgrade() {
+        let mut buf = Buf::new();
+        let mut resp = Response::new(&mut buf, Version::Http10,
+            false, true, AutoHeaders::default());
+        assert!(matches!(resp.set_version(Version::Http11),
+                         Err(HeaderError::IncompatibleVersion)));
+        resp.status(200, "OK");
+    }
+
+    #[test]
+    fn test_status_code() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.status_code(404);
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 404 Not Found\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.method_not_allowed(&["GET", "POST"]);
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 405 Method Not Allowed\r\n",
+            "Allow: GET, POST\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Length: 18\r\n\r\n",
+            "Method Not Allowed").as_bytes());
+    }
+
+    #[test]
+    fn test_send_body() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.send_body(404, "Not Found", b"text/plain", b"Not Found");
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 404 Not Found\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Length: 9\r\n\r\n",
+            "Not Found").as_bytes());
+    }
+
+    #[test]
+    fn test_send_status() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.send_status(202, "Accepted");
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 202 Accepted\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.send_status(204, "No Content");
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], b"HTTP/1.1 204 No Content\r\n\r\n");
+    }
+
+    #[test]
+    fn test_trailers() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.add_header("Content-Type", b"application/grpc-web")
+                .unwrap();
+            resp.add_chunked().unwrap();
+            resp.declare_trailers(&["grpc-status", "grpc-message"]).unwrap();
+            resp.done_headers().unwrap();
+            resp.write_body(b"hello");
+            assert!(matches!(resp.add_trailer("x-other", b"1"),
+                             Err(HeaderError::UndeclaredTrailer)));
+            resp.add_trailer("grpc-status", b"0").unwrap();
+            resp.add_trailer("Grpc-Message", b"OK").unwrap();
+            resp.done();
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Type: application/grpc-web\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "Trailer: grpc-status, grpc-message\r\n\r\n",
+            "5\r\nhello\r\n",
+            "0\r\n",
+            "grpc-status: 0\r\n",
+            "Grpc-Message: OK\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_trailers_not_accepted() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            set_trailer_policy(&mut resp, TrailerPolicy::Deny);
+            resp.status(200, "OK");
+            resp.add_chunked().unwrap();
+            resp.done_headers().unwrap();
+            resp.write_body(b"hello");
+            assert!(matches!(resp.add_trailer("grpc-status", b"0"),
+                             Err(HeaderError::TrailersNotAccepted)));
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: chunked\r\n\r\n",
+            "5\r\nhello\r\n",
+            "0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_trailers_warning() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            set_trailer_policy(&mut resp, TrailerPolicy::Warn);
+            resp.status(200, "OK");
+            resp.add_chunked().unwrap();
+            resp.done_headers().unwrap();
+            resp.add_trailer("grpc-status", b"0").unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: chunked\r\n\r\n",
+            "0\r\n",
+            "grpc-status: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_trailers_not_chunked() {
+        let mut buf = Buf::new();
+        let mut resp = Response::new(&mut buf, Version::Http11,
+            false, false, AutoHeaders::default());
+        resp.status(200, "OK");
+        resp.add_length(5).unwrap();
+        assert!(matches!(resp.declare_trailers(&["grpc-status"]),
+                         Err(HeaderError::TrailersRequireChunked)));
+    }
+
+    #[test]
+    fn test_body_parts_tee() {
+        let mut sink = Vec::new();
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.tee(&mut sink);
+            resp.status(200, "OK");
+            let name = String::from("world");
+            resp.write_body_parts(vec![&b"hello, "[..], name.as_bytes()]);
+            resp.done();
+        }
+        assert_eq!(&sink[..], b"hello, world");
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Length: 12\r\n\r\n",
+            "hello, world").as_bytes());
+    }
+
+    #[test]
+    fn test_tee() {
+        let mut sink = Vec::new();
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.tee(&mut sink);
+            resp.status(200, "OK");
+            resp.add_chunked().unwrap();
+            resp.done_headers().unwrap();
+            resp.write_body(b"hello");
+            resp.write_body(b", world");
+            resp.done();
+        }
+        assert_eq!(&sink[..], b"hello, world");
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: chunked\r\n\r\n",
+            "5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_keep_alive_hints() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.add_keep_alive_hints(Duration::new(5, 0), Some(100));
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Keep-Alive: timeout=5, max=100\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_http10_keep_alive() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http10,
+                false, false, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.0 200 OK\r\n",
+            "Connection: keep-alive\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_keep_alive_hints_close() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, true, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.add_keep_alive_hints(Duration::new(120, 0), None);
+            resp.add_length(0).unwrap();
+            resp.done_headers().unwrap();
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Length: 0\r\n",
+            "Connection: close\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_content_type() {
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            resp.status(200, "OK");
+            resp.content_type(mime::APPLICATION_JSON).unwrap();
+            resp.add_length(2).unwrap();
+            resp.done_headers().unwrap();
+            resp.write_body(b"{}");
+            resp.done();
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Content-Type: application/json\r\n",
+            "Content-Length: 2\r\n\r\n{}").as_bytes());
+        assert_eq!(mime::TEXT_HTML, "text/html; charset=utf-8");
+        assert_eq!(mime::TEXT_PLAIN, "text/plain; charset=utf-8");
+    }
 }
@@ -0,0 +1,161 @@
+use super::Response;
+
+
+/// A table of routes, which maps method and path to an arbitrary value
+///
+/// Usually the value is a variant of the enum which implements `Server`
+/// and is returned from `headers_received`.
+///
+/// The path pattern is either matched exactly, or if it ends with `*` it
+/// is matched as a prefix. Query string is not taken into account when
+/// matching. Routes are checked in the order they were added.
+#[derive(Debug)]
+pub struct Router<T> {
+    routes: Vec<Route<T>>,
+}
+
+#[derive(Debug)]
+struct Route<T> {
+    method: String,
+    path: String,
+    value: T,
+}
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let path = path.splitn(2, '?').next().unwrap();
+    if pattern.ends_with('*') {
+        path.starts_with(&pattern[..pattern.len()-1])
+    } else {
+        path == pattern
+    }
+}
+
+impl<T> Router<T> {
+    /// Create an empty router
+    pub fn new() -> Router<T> {
+        Router { routes: Vec::new() }
+    }
+    /// Add a route
+    ///
+    /// Method is matched case-sensitively as required by the spec.
+    pub fn add(&mut self, method: &str, path: &str, value: T)
+        -> &mut Router<T>
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            value: value,
+        });
+        self
+    }
+    /// Find a value for the request
+    ///
+    /// Returns `None` both when path is unknown and when the method is not
+    /// allowed for the path. Use `allowed_methods_for` to distinguish
+    /// between `404 Not Found` and `405 Method Not Allowed`.
+    pub fn find(&self, method: &str, path: &str) -> Option<&T> {
+        self.routes.iter()
+            .find(|r| r.method == method && path_matches(&r.path, path))
+            .map(|r| &r.value)
+    }
+    /// Returns methods registered for the path in the order of addition
+    ///
+    /// Returns an empty vector if the path is unknown. Return it (converted
+    /// to strings) from `Server::allowed_methods` to get `OPTIONS` and
+    /// `405 Method Not Allowed` answered automatically.
+    pub fn allowed_methods_for(&self, path: &str) -> Vec<&str> {
+        let mut result = Vec::new();
+        for route in &self.routes {
+            if path_matches(&route.path, path)
+                && !result.contains(&&route.method[..])
+            {
+                result.push(&route.method[..]);
+            }
+        }
+        result
+    }
+    /// Responds to the `OPTIONS` request for the path
+    ///
+    /// Writes a complete `200 OK` response with `Allow` header listing the
+    /// methods registered for the path (`OPTIONS` is always included).
+    /// Returns `false` and leaves the response untouched if the path is
+    /// unknown, so you can send `404 Not Found` instead.
+    ///
+    /// # Panics
+    ///
+    /// When response is already started.
+    pub fn respond_options(&self, path: &str, response: &mut Response)
+        -> bool
+    {
+        let methods = self.allowed_methods_for(path);
+        if methods.len() == 0 {
+            return false;
+        }
+        response.options(&methods);
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rotor_stream::Buf;
+    use super::Router;
+    use super::super::{Response, Version, AutoHeaders};
+
+    fn router() -> Router<u32> {
+        let mut router = Router::new();
+        router.add("GET", "/", 1)
+              .add("POST", "/", 2)
+              .add("GET", "/todo/*", 3)
+              .add("PATCH", "/todo/*", 4)
+              .add("DELETE", "/todo/*", 5);
+        router
+    }
+
+    #[test]
+    fn test_find() {
+        let router = router();
+        assert_eq!(router.find("GET", "/"), Some(&1));
+        assert_eq!(router.find("POST", "/?x=y"), Some(&2));
+        assert_eq!(router.find("PATCH", "/todo/12"), Some(&4));
+        assert_eq!(router.find("PUT", "/todo/12"), None);
+        assert_eq!(router.find("GET", "/other"), None);
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let router = router();
+        assert_eq!(router.allowed_methods_for("/"), vec!["GET", "POST"]);
+        assert_eq!(router.allowed_methods_for("/todo/1"),
+                   vec!["GET", "PATCH", "DELETE"]);
+        assert_eq!(router.allowed_methods_for("/other"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_options() {
+        let router = router();
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            assert!(router.respond_options("/todo/1", &mut resp));
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!("HTTP/1.1 200 OK\r\n",
+            "Allow: GET, PATCH, DELETE, OPTIONS\r\n",
+            "Content-Length: 0\r\n\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_options_not_found() {
+        let router = router();
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            assert!(!router.respond_options("/other", &mut resp));
+            assert!(!resp.is_started());
+        }
+        assert_eq!(buf.len(), 0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::borrow::Cow;
+use std::str::Split;
+
+
+fn hex_digit(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'...b'9' => Some(ch - b'0'),
+        b'a'...b'f' => Some(ch - b'a' + 10),
+        b'A'...b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decodes `%XX` sequences, and `+` as a space if `plus` is true
+///
+/// Malformed sequences are kept as is, and the bytes which don't make a
+/// valid UTF-8 are replaced by `U+FFFD`. The value is borrowed when there
+/// is nothing to decode.
+pub fn percent_decode(value: &str, plus: bool) -> Cow<str> {
+    let src = value.as_bytes();
+    if !src.iter().any(|&x| x == b'%' || plus && x == b'+') {
+        return Cow::Borrowed(value);
+    }
+    let mut buf = Vec::with_capacity(src.len());
+    let mut idx = 0;
+    while idx < src.len() {
+        match src[idx] {
+            b'%' => {
+                match (src.get(idx+1).and_then(|&x| hex_digit(x)),
+                       src.get(idx+2).and_then(|&x| hex_digit(x)))
+                {
+                    (Some(hi), Some(lo)) => {
+                        buf.push(hi << 4 | lo);
+                        idx += 3;
+                        continue;
+                    }
+                    _ => buf.push(b'%'),
+                }
+            }
+            b'+' if plus => buf.push(b' '),
+            x => buf.push(x),
+        }
+        idx += 1;
+    }
+    match String::from_utf8(buf) {
+        Ok(s) => Cow::Owned(s),
+        Err(e) => {
+            Cow::Owned(String::from_utf8_lossy(&e.into_bytes()).into_owned())
+        }
+    }
+}
+
+/// Splits absolute-form request target into scheme, authority and the rest
+///
+/// The rest is the path with the query string, it's empty for the target
+/// like `http://example.com`. Returns `None` for other forms of target.
+pub fn split_absolute(target: &str) -> Option<(&str, &str, &str)> {
+    let colon = match target.find("://") {
+        Some(x) => x,
+        None => return None,
+    };
+    let scheme = &target[..colon];
+    let mut chars = scheme.bytes();
+    match chars.next() {
+        Some(b'a'...b'z') | Some(b'A'...b'Z') => {}
+        _ => return None,
+    }
+    if !chars.all(|x| matches!(x, b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' |
+                                  b'+' | b'-' | b'.'))
+    {
+        return None;
+    }
+    let rest = &target[colon+3..];
+    let end = rest.find(|x: char| x == '/' || x == '?').unwrap_or(rest.len());
+    Some((scheme, &rest[..end], &rest[end..]))
+}
+
+/// An iterator over decoded key-value pairs of the query string
+///
+/// See `Head::query_pairs`
+#[derive(Debug, Clone)]
+pub struct QueryPairs<'a>(Split<'a, char>);
+
+impl<'a> QueryPairs<'a> {
+    pub fn new(query: &'a str) -> QueryPairs<'a> {
+        QueryPairs(query.split('&'))
+    }
+}
+
+impl<'a> Iterator for QueryPairs<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next() {
+                Some("") => continue,
+                Some(item) => {
+                    let mut pair = item.splitn(2, '=');
+                    let key = pair.next().unwrap();
+                    let value = pair.next().unwrap_or("");
+                    return Some((percent_decode(key, true),
+                                 percent_decode(value, true)));
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{percent_decode, split_absolute, QueryPairs};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(percent_decode("/a%20b", false), "/a b");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+        assert_eq!(percent_decode("a+b", true), "a b");
+        assert_eq!(percent_decode("%D0%B6", false), "\u{436}");
+        assert_eq!(percent_decode("100%", false), "100%");
+        assert_eq!(percent_decode("%zz%4", false), "%zz%4");
+        assert_eq!(percent_decode("%FF", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_split_absolute() {
+        assert_eq!(split_absolute("http://example.com/a?b"),
+                   Some(("http", "example.com", "/a?b")));
+        assert_eq!(split_absolute("HTTPS://user@[::1]:8080"),
+                   Some(("HTTPS", "user@[::1]:8080", "")));
+        assert_eq!(split_absolute("coap+tcp://h?x=/y"),
+                   Some(("coap+tcp", "h", "?x=/y")));
+        assert_eq!(split_absolute("/redirect?to=http://example.com"), None);
+        assert_eq!(split_absolute("example.com:443"), None);
+        assert_eq!(split_absolute("*"), None);
+    }
+
+    fn pairs(query: &str) -> Vec<(String, String)> {
+        QueryPairs::new(query)
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pairs() {
+        assert_eq!(pairs(""), Vec::<(String, String)>::new());
+        assert_eq!(pairs("a=1&&b"), vec![
+            (String::from("a"), String::from("1")),
+            (String::from("b"), String::from("")),
+        ]);
+        assert_eq!(pairs("q=rust+http&x%3Dy=a%26b=c"), vec![
+            (String::from("q"), String::from("rust http")),
+            (String::from("x=y"), String::from("a&b=c")),
+        ]);
+    }
+}
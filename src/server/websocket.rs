@@ -0,0 +1,203 @@
+//! WebSocket opening handshake (RFC 6455)
+//!
+//! Only the handshake is implemented here. Call `accept()` in
+//! `headers_received`, and return `RecvMode::Upgrade` when it succeeds.
+//! After that WebSocket frames are passed to `request_chunk` as is, and
+//! should be written back with `Response::write_raw`.
+//!
+//! Requires the `websocket` feature.
+use std::ascii::AsciiExt;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use sha1::Sha1;
+
+use headers;
+use super::{Head, Response, HttpError, BodyKind, Method};
+
+/// The only version of the protocol supported
+pub const VERSION: &'static str = "13";
+
+const GUID: &'static [u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+
+quick_error!{
+    /// Error validating WebSocket handshake
+    ///
+    /// Use it as an argument to `Server::emit_error_page` if you wish
+    #[derive(Debug)]
+    pub enum HandshakeError {
+        NotUpgrade {
+            description("request is not a WebSocket upgrade")
+        }
+        BadMethod {
+            description("WebSocket handshake must use GET method")
+        }
+        MissingKey {
+            description("no `Sec-WebSocket-Key` header in request")
+        }
+        BadKey {
+            description("`Sec-WebSocket-Key` is not a 16-byte base64 value")
+        }
+        UnsupportedVersion {
+            description("unsupported `Sec-WebSocket-Version`")
+        }
+    }
+}
+
+impl HttpError for HandshakeError {
+    fn http_status(&self) -> (u16, &'static str) {
+        use self::HandshakeError::*;
+        match *self {
+            // The response should contain `Sec-WebSocket-Version` header
+            UnsupportedVersion => (426, "Upgrade Required"),
+            NotUpgrade | BadMethod | MissingKey | BadKey => {
+                (400, "Bad Request")
+            }
+        }
+    }
+}
+
+/// Computes the value of `Sec-WebSocket-Accept` for the key of request
+pub fn accept_key(key: &[u8]) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key);
+    sha.update(GUID);
+    sha.digest().to_base64(STANDARD)
+}
+
+fn has_token(value: &[u8], token: &[u8]) -> bool {
+    value.split(|&x| x == b',')
+        .any(|item| headers::trim(item).eq_ignore_ascii_case(token))
+}
+
+/// Validates the WebSocket handshake and returns `Sec-WebSocket-Key`
+///
+/// The request must be a `GET` request with `Connection: Upgrade` and
+/// `Upgrade: websocket` headers (the former is checked by the parser, see
+/// `BodyKind::Upgrade`), and it must use protocol version 13.
+pub fn check_handshake<'x>(head: &Head<'x>)
+    -> Result<&'x [u8], HandshakeError>
+{
+    use self::HandshakeError::*;
+    if head.body_kind != BodyKind::Upgrade ||
+        !head.get_all("Upgrade").any(|x| has_token(x, b"websocket"))
+    {
+        return Err(NotUpgrade);
+    }
+    if head.method_enum() != Method::Get {
+        return Err(BadMethod);
+    }
+    match head.get("Sec-WebSocket-Version") {
+        Some(ver) if headers::trim(ver) == VERSION.as_bytes() => {}
+        _ => return Err(UnsupportedVersion),
+    }
+    let key = match head.get("Sec-WebSocket-Key") {
+        Some(key) => headers::trim(key),
+        None => return Err(MissingKey),
+    };
+    match key.from_base64() {
+        Ok(ref raw) if raw.len() == 16 => Ok(key),
+        _ => Err(BadKey),
+    }
+}
+
+/// Validates the handshake and writes `101 Switching Protocols` response
+///
+/// The response is complete after this call, so the only thing left is
+/// to return `RecvMode::Upgrade` from `headers_received`. The `protocol`
+/// is sent in `Sec-WebSocket-Protocol` header, it should be one of those
+/// requested by client.
+///
+/// On error nothing is written, so you can send an error page. Note that
+/// for `UnsupportedVersion` the response should contain
+/// `Sec-WebSocket-Version: 13` header.
+///
+/// # Panics
+///
+/// When the response is already started.
+pub fn accept(head: &Head, protocol: Option<&str>, response: &mut Response)
+    -> Result<(), HandshakeError>
+{
+    let key = try!(check_handshake(head));
+    response.status(101, "Switching Protocols");
+    response.add_header("Upgrade", b"websocket").unwrap();
+    response.add_header("Connection", b"Upgrade").unwrap();
+    response.add_header("Sec-WebSocket-Accept", accept_key(key).as_bytes())
+        .unwrap();
+    if let Some(proto) = protocol {
+        response.add_header("Sec-WebSocket-Protocol", proto.as_bytes())
+            .unwrap();
+    }
+    response.done_headers().unwrap();
+    response.done();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use httparse::Header;
+    use rotor_stream::Buf;
+    use message::AutoHeaders;
+    use super::super::{Head, Response, Version, BodyKind};
+    use super::super::request::test_head;
+    use super::{accept_key, check_handshake, accept, HandshakeError};
+
+    fn head<'x>(method: &'x str, headers: &'x [Header<'x>]) -> Head<'x> {
+        Head {
+            method: method,
+            path: "/chat",
+            body_kind: BodyKind::Upgrade,
+            ..test_head(headers)
+        }
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // example from RFC 6455
+        assert_eq!(accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+                   "s3pPLMBiTxaQ9kYGJzo5p0LxOlQ=");
+    }
+
+    #[test]
+    fn test_handshake() {
+        let headers = [
+            Header { name: "Upgrade", value: b"WebSocket" },
+            Header { name: "Connection", value: b"Upgrade" },
+            Header { name: "Sec-WebSocket-Key",
+                     value: b"dGhlIHNhbXBsZSBub25jZQ==" },
+            Header { name: "Sec-WebSocket-Version", value: b"13" },
+        ];
+        let mut buf = Buf::new();
+        {
+            let mut resp = Response::new(&mut buf, Version::Http11,
+                false, false, AutoHeaders::default());
+            accept(&head("GET", &headers), None, &mut resp).unwrap();
+            assert!(resp.is_complete());
+        }
+        assert_eq!(&buf[..], concat!(
+            "HTTP/1.1 101 Switching Protocols\r\n",
+            "Upgrade: websocket\r\n",
+            "Connection: Upgrade\r\n",
+            "Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGJzo5p0LxOlQ=\r\n",
+            "\r\n").as_bytes());
+    }
+
+    #[test]
+    fn test_bad_handshake() {
+        let mut headers = vec![
+            Header { name: "Upgrade", value: b"h2c, websocket" },
+            Header { name: "Sec-WebSocket-Key", value: b"c2hvcnQ=" },
+            Header { name: "Sec-WebSocket-Version", value: b"13" },
+        ];
+        assert!(matches!(check_handshake(&head("GET", &headers)),
+                         Err(HandshakeError::BadKey)));
+        assert!(matches!(check_handshake(&head("POST", &headers)),
+                         Err(HandshakeError::BadMethod)));
+        headers[2].value = &b"8"[..];
+        assert!(matches!(check_handshake(&head("GET", &headers)),
+                         Err(HandshakeError::UnsupportedVersion)));
+        headers[0].value = &b"h2c"[..];
+        assert!(matches!(check_handshake(&head("GET", &headers)),
+                         Err(HandshakeError::NotUpgrade)));
+    }
+}